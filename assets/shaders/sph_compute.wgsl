@@ -0,0 +1,192 @@
+// Density and neighbor-force passes of `Backend::Gpu`, mirroring `step_simulation` and the
+// kernels in `src/kernels.rs`. Candidates are visited in the CPU grid's order, so sums agree
+// with the CPU path to float rounding.
+
+const PI: f32 = 3.14159265358979323846;
+const EOS_TAIT: u32 = 1u;
+
+struct Params {
+    n: u32,
+    width: u32,
+    height: u32,
+    scan_radius: u32,
+    cell_size: f32,
+    offset_x: f32,
+    offset_y: f32,
+    h: f32,
+    min_dist: f32,
+    particle_mass: f32,
+    target_density: f32,
+    pressure_k: f32,
+    eos: u32,
+    tait_gamma: f32,
+    tait_b: f32,
+    clamp_pressure: u32,
+    viscosity_mu: f32,
+    background_pressure: f32,
+    min_density: f32,
+    // Leading particles that get the force pass; the CPU sums the rest.
+    force_count: u32,
+}
+
+// Neighbor sums of one particle; the CPU adds gravity, interaction and cohesion.
+struct Sums {
+    pressure: vec2<f32>,
+    viscosity: vec2<f32>,
+    to_neighbors: vec2<f32>,
+    _pad: vec2<f32>,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+// Position in xy, velocity in zw.
+@group(0) @binding(1) var<storage, read> particles: array<vec4<f32>>;
+@group(0) @binding(2) var<storage, read> mass_factors: array<f32>;
+// Cell `c` holds `cell_indices[cell_start[c]..cell_start[c + 1]]`.
+@group(0) @binding(3) var<storage, read> cell_start: array<u32>;
+@group(0) @binding(4) var<storage, read> cell_indices: array<u32>;
+// Density in x, pressure in y.
+@group(0) @binding(5) var<storage, read_write> dens_press: array<vec2<f32>>;
+@group(0) @binding(6) var<storage, read_write> sums: array<Sums>;
+
+fn poly6_kernel(dist_sq: f32, h: f32) -> f32 {
+    let h2 = h * h;
+    if dist_sq < h2 {
+        let h4 = h2 * h2;
+        let coeff = 4.0 / (PI * h4 * h4);
+        let diff = h2 - dist_sq;
+        return coeff * diff * diff * diff;
+    }
+    return 0.0;
+}
+
+fn spiky_kernel_gradient(dist: f32, h: f32) -> f32 {
+    if dist < h {
+        let h2 = h * h;
+        let coeff = 30.0 / (PI * h2 * h2 * h);
+        let diff = h - dist;
+        return coeff * diff * diff;
+    }
+    return 0.0;
+}
+
+fn viscosity_laplacian(dist: f32, h: f32) -> f32 {
+    if dist < h {
+        let h2 = h * h;
+        let coeff = 40.0 / (PI * h2 * h2 * h2);
+        return coeff * (h - dist);
+    }
+    return 0.0;
+}
+
+fn pressure_at(density: f32) -> f32 {
+    var pressure = params.pressure_k * (density - params.target_density);
+    if params.eos == EOS_TAIT {
+        pressure = 0.0;
+        if params.target_density > 0.0 {
+            pressure = params.tait_b * (pow(density / params.target_density, params.tait_gamma) - 1.0);
+        }
+    }
+    if params.clamp_pressure != 0u {
+        pressure = max(pressure, 0.0);
+    }
+    return pressure + params.background_pressure;
+}
+
+fn cell_coords(pos: vec2<f32>) -> vec2<i32> {
+    // Truncating like the CPU's `as usize`, which saturates negative coordinates to 0.
+    let x = max((pos.x + params.offset_x) / params.cell_size, 0.0);
+    let y = max((pos.y + params.offset_y) / params.cell_size, 0.0);
+    return vec2<i32>(i32(x), i32(y));
+}
+
+@compute @workgroup_size(64)
+fn density(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= params.n {
+        return;
+    }
+    let pos = particles[i].xy;
+    let h = params.h;
+    let h_sq = h * h;
+    let min_dist_sq = params.min_dist * params.min_dist;
+    let cell = cell_coords(pos);
+    let r = i32(params.scan_radius);
+    var d = 0.0;
+    for (var dy = -r; dy <= r; dy++) {
+        for (var dx = -r; dx <= r; dx++) {
+            let cx = cell.x + dx;
+            let cy = cell.y + dy;
+            if cx < 0 || cy < 0 || cx >= i32(params.width) || cy >= i32(params.height) {
+                continue;
+            }
+            let c = u32(cy) * params.width + u32(cx);
+            for (var k = cell_start[c]; k < cell_start[c + 1u]; k++) {
+                let j = cell_indices[k];
+                let offset = particles[j].xy - pos;
+                let dist_sq = dot(offset, offset);
+                if dist_sq < h_sq {
+                    var kernel_dist_sq = dist_sq;
+                    if i != j {
+                        kernel_dist_sq = max(dist_sq, min_dist_sq);
+                    }
+                    d += params.particle_mass * mass_factors[j] * poly6_kernel(kernel_dist_sq, h);
+                }
+            }
+        }
+    }
+    dens_press[i] = vec2<f32>(d, pressure_at(d));
+}
+
+@compute @workgroup_size(64)
+fn forces(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= params.force_count {
+        return;
+    }
+    let pos = particles[i].xy;
+    let vel = particles[i].zw;
+    let dens = max(dens_press[i].x, params.min_density);
+    let press = dens_press[i].y;
+    let h = params.h;
+    let m = params.particle_mass;
+    let cell = cell_coords(pos);
+    let r = i32(params.scan_radius);
+    var f_pressure = vec2<f32>(0.0);
+    var f_viscosity = vec2<f32>(0.0);
+    var to_neighbors = vec2<f32>(0.0);
+    for (var dy = -r; dy <= r; dy++) {
+        for (var dx = -r; dx <= r; dx++) {
+            let cx = cell.x + dx;
+            let cy = cell.y + dy;
+            if cx < 0 || cy < 0 || cx >= i32(params.width) || cy >= i32(params.height) {
+                continue;
+            }
+            let c = u32(cy) * params.width + u32(cx);
+            for (var k = cell_start[c]; k < cell_start[c + 1u]; k++) {
+                let j = cell_indices[k];
+                if i == j {
+                    continue;
+                }
+                let other_pos = particles[j].xy;
+                let dist = distance(pos, other_pos);
+                if dist < h && dist > 0.0001 {
+                    let dir = (other_pos - pos) / dist;
+                    let safe_dens = max(dens_press[j].x, params.min_density);
+                    let kernel_dist = max(dist, params.min_dist);
+                    let slope = spiky_kernel_gradient(kernel_dist, h);
+                    let pressure_term = (press / dens / dens)
+                        + (dens_press[j].y / safe_dens / safe_dens);
+                    f_pressure += -dens * m * mass_factors[j] * pressure_term * slope * dir;
+
+                    let vel_diff = particles[j].zw - vel;
+                    f_viscosity += vel_diff * params.viscosity_mu
+                        * viscosity_laplacian(kernel_dist, h) * (1.0 / safe_dens) * m
+                        * mass_factors[j];
+
+                    to_neighbors += dir * poly6_kernel(dist * dist, h);
+                }
+            }
+        }
+    }
+    sums[i] = Sums(f_pressure, f_viscosity, to_neighbors, vec2<f32>(0.0));
+}
@@ -0,0 +1,152 @@
+//! Compares the current `Vec<Vec<usize>>` spatial grid with the flat CSR grid:
+//! rebuild time and a full neighbor traversal, at several particle counts.
+//! Both grids must produce identical neighbor sets for the timings to be comparable.
+//!
+//! Run with `cargo bench --features flat-grid --bench grid`.
+
+#[path = "../src/grid.rs"]
+#[allow(dead_code)] // The app's wider queries are not benchmarked.
+mod grid;
+
+use std::{hint::black_box, time::Instant};
+
+use bevy::prelude::*;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use grid::{FlatGrid, GridView};
+
+const DOMAIN: Vec2 = Vec2::new(1280.0, 720.0);
+const CELL_SIZE: f32 = 25.0;
+const SMOOTHING_RADIUS: f32 = 20.0;
+const PARTICLE_COUNTS: [usize; 4] = [1_000, 4_000, 16_000, 64_000];
+const ITERATIONS: u32 = 50;
+
+struct NestedGrid {
+    cells: Vec<Vec<usize>>,
+    width: usize,
+    height: usize,
+    offset: Vec2,
+}
+
+impl NestedGrid {
+    fn new(width: usize, height: usize, offset: Vec2) -> Self {
+        Self {
+            cells: vec![Vec::with_capacity(20); width * height],
+            width,
+            height,
+            offset,
+        }
+    }
+
+    /// Mirrors the rebuild in `step_simulation`.
+    fn rebuild(&mut self, positions: &[Vec2]) {
+        self.cells.iter_mut().for_each(Vec::clear);
+        for (i, pos) in positions.iter().enumerate() {
+            let gx = ((pos.x + self.offset.x) / CELL_SIZE) as usize;
+            let gy = ((pos.y + self.offset.y) / CELL_SIZE) as usize;
+            let idx = (gy * self.width + gx).clamp(0, self.cells.len() - 1);
+            self.cells[idx].push(i);
+        }
+    }
+
+    fn view(&self) -> GridView<'_> {
+        GridView {
+            cells: &self.cells,
+            width: self.width,
+            height: self.height,
+            cell_size: CELL_SIZE,
+            offset_x: self.offset.x,
+            offset_y: self.offset.y,
+            scan_radius: 1,
+        }
+    }
+}
+
+/// Sums neighbor counts within the smoothing radius, the access pattern of the density pass.
+fn traverse(positions: &[Vec2], for_each: impl Fn(Vec2, &mut dyn FnMut(usize))) -> usize {
+    let h_sq = SMOOTHING_RADIUS * SMOOTHING_RADIUS;
+    let mut total = 0;
+    for &pos in positions {
+        for_each(pos, &mut |j| {
+            if pos.distance_squared(positions[j]) < h_sq {
+                total += 1;
+            }
+        });
+    }
+    total
+}
+
+fn neighbor_sets(
+    positions: &[Vec2],
+    for_each: impl Fn(Vec2, &mut dyn FnMut(usize)),
+) -> Vec<Vec<usize>> {
+    let h_sq = SMOOTHING_RADIUS * SMOOTHING_RADIUS;
+    positions
+        .iter()
+        .map(|&pos| {
+            let mut neighbors = Vec::new();
+            for_each(pos, &mut |j| {
+                if pos.distance_squared(positions[j]) < h_sq {
+                    neighbors.push(j);
+                }
+            });
+            neighbors.sort_unstable();
+            neighbors
+        })
+        .collect()
+}
+
+fn time_ms(mut f: impl FnMut()) -> f64 {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    start.elapsed().as_secs_f64() * 1000.0 / f64::from(ITERATIONS)
+}
+
+fn main() {
+    let width = (DOMAIN.x / CELL_SIZE).ceil() as usize + 4;
+    let height = (DOMAIN.y / CELL_SIZE).ceil() as usize + 4;
+    let offset = DOMAIN / 2.0 + Vec2::splat(CELL_SIZE * 2.0);
+
+    println!(
+        "{:>8} {:>14} {:>14} {:>16} {:>16}",
+        "count", "nested build", "flat build", "nested traverse", "flat traverse"
+    );
+    for count in PARTICLE_COUNTS {
+        let mut rng = StdRng::seed_from_u64(count as u64);
+        let half = DOMAIN / 2.0;
+        let positions: Vec<Vec2> = (0..count)
+            .map(|_| {
+                Vec2::new(
+                    rng.random_range(-half.x..half.x),
+                    rng.random_range(-half.y..half.y),
+                )
+            })
+            .collect();
+
+        let mut nested = NestedGrid::new(width, height, offset);
+        let mut flat = FlatGrid::new(width, height, CELL_SIZE, offset.x, offset.y, 1);
+        nested.rebuild(&positions);
+        flat.rebuild(&positions);
+        assert_eq!(
+            neighbor_sets(&positions, |p, f| nested.view().for_each_candidate(p, f)),
+            neighbor_sets(&positions, |p, f| flat.for_each_candidate(p, f)),
+            "grids disagree on neighbor sets for {count} particles"
+        );
+
+        let nested_build = time_ms(|| nested.rebuild(black_box(&positions)));
+        let flat_build = time_ms(|| flat.rebuild(black_box(&positions)));
+        let nested_traverse = time_ms(|| {
+            black_box(traverse(&positions, |p, f| {
+                nested.view().for_each_candidate(p, f)
+            }));
+        });
+        let flat_traverse = time_ms(|| {
+            black_box(traverse(&positions, |p, f| flat.for_each_candidate(p, f)));
+        });
+        println!(
+            "{count:>8} {nested_build:>11.3} ms {flat_build:>11.3} ms {nested_traverse:>13.3} ms {flat_traverse:>13.3} ms"
+        );
+    }
+}
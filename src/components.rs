@@ -3,3 +3,39 @@ use bevy::prelude::*;
 /// Component that links visual entities to simulation particle indices.
 #[derive(Component)]
 pub struct ParticleId(pub usize);
+
+/// Marker for particle visuals of the `ComparisonSim` instance.
+#[derive(Component, Clone, Copy)]
+pub struct ComparisonParticle;
+
+/// Marker for the HUD text showing the selected initial condition.
+#[derive(Component)]
+pub struct InitialConditionLabel;
+
+/// Marker for the HUD text showing live tuning diagnostics.
+#[derive(Component)]
+pub struct DiagnosticsLabel;
+
+/// Marker for the sprite showing grid cell occupancy behind the particles.
+#[derive(Component)]
+pub struct OccupancyHeatmap;
+
+/// Sprite showing particle trails, with the exposure accumulated in each texel, row-major
+/// from the top-left like the image.
+#[derive(Component, Default)]
+pub struct TrailCanvas {
+    pub exposure: Vec<f32>,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// World-space text around the color legend bar.
+#[derive(Component)]
+pub enum ColorLegendLabel {
+    /// Value at the left end of the bar.
+    Min,
+    /// Value at the right end of the bar.
+    Max,
+    /// Name of the active color field, above the bar.
+    Title,
+}
@@ -0,0 +1,97 @@
+use bevy::prelude::*;
+
+/// Scalar field sampled on a regular grid of `cols x rows` points, row-major from `origin`.
+pub struct ScalarGrid {
+    pub values: Vec<f32>,
+    pub cols: usize,
+    pub rows: usize,
+    pub origin: Vec2,
+    pub spacing: f32,
+}
+
+impl ScalarGrid {
+    #[inline(always)]
+    fn value(&self, col: usize, row: usize) -> f32 {
+        self.values[row * self.cols + col]
+    }
+
+    #[inline(always)]
+    fn point(&self, col: usize, row: usize) -> Vec2 {
+        self.origin + Vec2::new(col as f32, row as f32) * self.spacing
+    }
+}
+
+/// Extracts the `iso` contour of a sampled field with marching squares.
+/// Returns line segments with endpoints linearly interpolated along cell edges.
+/// Ambiguous saddle cells are resolved using the cell-center average.
+pub fn marching_squares(grid: &ScalarGrid, iso: f32) -> Vec<(Vec2, Vec2)> {
+    let mut segments = Vec::new();
+    if grid.cols < 2 || grid.rows < 2 {
+        return segments;
+    }
+
+    let lerp_edge = |pa: Vec2, va: f32, pb: Vec2, vb: f32| {
+        let denom = vb - va;
+        let t = if denom.abs() > f32::EPSILON {
+            ((iso - va) / denom).clamp(0.0, 1.0)
+        } else {
+            0.5
+        };
+        pa.lerp(pb, t)
+    };
+
+    for row in 0..grid.rows - 1 {
+        for col in 0..grid.cols - 1 {
+            // Corners counter-clockwise from bottom-left.
+            let v = [
+                grid.value(col, row),
+                grid.value(col + 1, row),
+                grid.value(col + 1, row + 1),
+                grid.value(col, row + 1),
+            ];
+            let p = [
+                grid.point(col, row),
+                grid.point(col + 1, row),
+                grid.point(col + 1, row + 1),
+                grid.point(col, row + 1),
+            ];
+            let case = v
+                .iter()
+                .enumerate()
+                .fold(0u8, |acc, (k, &value)| acc | (((value >= iso) as u8) << k));
+            if case == 0 || case == 15 {
+                continue;
+            }
+
+            // Edge k joins corner k and corner k + 1.
+            let edge = |k: usize| {
+                let a = k;
+                let b = (k + 1) % 4;
+                lerp_edge(p[a], v[a], p[b], v[b])
+            };
+
+            let mut push = |ea: usize, eb: usize| segments.push((edge(ea), edge(eb)));
+            match case {
+                1 | 14 => push(3, 0),
+                2 | 13 => push(0, 1),
+                3 | 12 => push(3, 1),
+                4 | 11 => push(1, 2),
+                6 | 9 => push(0, 2),
+                7 | 8 => push(2, 3),
+                5 | 10 => {
+                    let center_inside = v.iter().sum::<f32>() * 0.25 >= iso;
+                    // Corners 0 and 2 inside (case 5) connect through the center when it is inside.
+                    if (case == 5) == center_inside {
+                        push(0, 1);
+                        push(2, 3);
+                    } else {
+                        push(3, 0);
+                        push(1, 2);
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+    segments
+}
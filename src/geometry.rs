@@ -0,0 +1,358 @@
+use bevy::prelude::*;
+
+/// A static line-segment wall that particles collide with.
+#[derive(Reflect, Clone, Copy, Debug)]
+#[reflect(Default)]
+pub struct Segment {
+    pub a: Vec2,
+    pub b: Vec2,
+}
+
+/// A short horizontal wall at the origin, the starting point for walls added in the
+/// inspector.
+impl Default for Segment {
+    fn default() -> Self {
+        Self::new(Vec2::new(-100.0, 0.0), Vec2::new(100.0, 0.0))
+    }
+}
+
+impl Segment {
+    pub const fn new(a: Vec2, b: Vec2) -> Self {
+        Self { a, b }
+    }
+
+    /// Returns the point on the segment closest to `p`, clamped to the endpoints.
+    #[inline(always)]
+    pub fn closest_point(&self, p: Vec2) -> Vec2 {
+        let ab = self.b - self.a;
+        let len_sq = ab.length_squared();
+        if len_sq <= f32::EPSILON {
+            return self.a;
+        }
+        let t = ((p - self.a).dot(ab) / len_sq).clamp(0.0, 1.0);
+        self.a + ab * t
+    }
+
+    /// Returns the fraction `t` in `[0, 1]` along the path `p0 -> p1` where it crosses
+    /// this segment, or `None` if it does not (parallel paths never count as crossing).
+    #[inline(always)]
+    pub fn sweep_intersection(&self, p0: Vec2, p1: Vec2) -> Option<f32> {
+        let path = p1 - p0;
+        let edge = self.b - self.a;
+        let denom = path.perp_dot(edge);
+        if denom.abs() <= f32::EPSILON {
+            return None;
+        }
+        let to_a = self.a - p0;
+        let t = to_a.perp_dot(edge) / denom;
+        let u = to_a.perp_dot(path) / denom;
+        ((0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u)).then_some(t)
+    }
+
+    /// Unit normal of the segment (left of `a -> b`), used when a particle sits exactly on it.
+    #[inline(always)]
+    pub fn normal(&self) -> Vec2 {
+        (self.b - self.a).perp().normalize_or(Vec2::Y)
+    }
+}
+
+/// A V-shaped funnel with a gap in the middle, used as the built-in wall demo.
+pub fn funnel_segments() -> Vec<Segment> {
+    vec![
+        Segment::new(Vec2::new(-400.0, 200.0), Vec2::new(-40.0, -60.0)),
+        Segment::new(Vec2::new(400.0, 200.0), Vec2::new(40.0, -60.0)),
+    ]
+}
+
+/// A paddle wheel: a hub of `hub_radius` with `arms` evenly spaced radial paddles reaching
+/// `arm_length` past it, spinning about `center` at `angular_velocity` (radians per unit
+/// time, counter-clockwise). Its motion is scripted: particles it hits pick up the local
+/// surface velocity `ω × r`, but they never slow it down.
+#[derive(Reflect, Clone, Copy, Debug)]
+#[reflect(Default)]
+pub struct RotatingObstacle {
+    pub center: Vec2,
+    pub hub_radius: f32,
+    pub arm_length: f32,
+    pub arms: u32,
+    pub angular_velocity: f32,
+    /// Rotation in radians at simulation time zero.
+    pub phase: f32,
+}
+
+/// A six-armed wheel at the origin turning once every four time units, the starting point
+/// for wheels added in the inspector.
+impl Default for RotatingObstacle {
+    fn default() -> Self {
+        Self {
+            center: Vec2::ZERO,
+            hub_radius: 20.0,
+            arm_length: 80.0,
+            arms: 6,
+            angular_velocity: std::f32::consts::TAU / 4.0,
+            phase: 0.0,
+        }
+    }
+}
+
+impl RotatingObstacle {
+    /// The paddle arms at simulation time `time`, from the hub rim outwards.
+    pub fn arms_at(&self, time: f32) -> impl Iterator<Item = Segment> {
+        let Self {
+            center,
+            hub_radius,
+            arm_length,
+            arms,
+            angular_velocity,
+            phase,
+        } = *self;
+        let spacing = std::f32::consts::TAU / arms.max(1) as f32;
+        (0..arms).map(move |k| {
+            let direction = Vec2::from_angle(phase + angular_velocity * time + k as f32 * spacing);
+            Segment::new(
+                center + direction * hub_radius,
+                center + direction * (hub_radius + arm_length),
+            )
+        })
+    }
+
+    /// Velocity of the wheel's surface at `point`: `ω × r` about the center.
+    #[inline(always)]
+    pub fn surface_velocity(&self, point: Vec2) -> Vec2 {
+        self.angular_velocity * (point - self.center).perp()
+    }
+
+    /// If a particle of radius `radius` at `pos` overlaps the hub or an arm at time `time`,
+    /// returns the nearest non-overlapping position and the outward surface normal there.
+    #[inline(always)]
+    pub fn resolve(&self, pos: Vec2, radius: f32, time: f32) -> Option<(Vec2, Vec2)> {
+        let hub = Obstacle::Circle {
+            center: self.center,
+            radius: self.hub_radius,
+        };
+        if let Some(contact) = hub.resolve(pos, radius) {
+            return Some(contact);
+        }
+        self.arms_at(time).find_map(|arm| {
+            let closest = arm.closest_point(pos);
+            let offset = pos - closest;
+            if offset.length_squared() >= radius * radius {
+                return None;
+            }
+            let normal = offset.try_normalize().unwrap_or_else(|| arm.normal());
+            Some((closest + normal * radius, normal))
+        })
+    }
+}
+
+/// A wheel in the lower middle of `domain` with paddles reaching into the fluid, the
+/// built-in water-wheel demo.
+pub fn paddle_wheel(domain: Rect) -> Vec<RotatingObstacle> {
+    let reach = domain.height() * 0.25;
+    vec![RotatingObstacle {
+        center: Vec2::new(domain.center().x, domain.min.y + reach * 1.2),
+        hub_radius: reach * 0.2,
+        arm_length: reach * 0.8,
+        ..default()
+    }]
+}
+
+/// An axis-aligned region whose particles feel `gravity` instead of the global gravity.
+#[derive(Reflect, Clone, Copy, Debug)]
+#[reflect(Default)]
+pub struct GravityRegion {
+    pub rect: Rect,
+    pub gravity: Vec2,
+}
+
+/// A square around the origin with the default downward gravity, the starting point for
+/// regions added in the inspector.
+impl Default for GravityRegion {
+    fn default() -> Self {
+        Self {
+            rect: Rect::from_center_size(Vec2::ZERO, Vec2::splat(200.0)),
+            gravity: Vec2::new(0.0, -100.0),
+        }
+    }
+}
+
+/// Returns the gravity of the first region containing `pos`, or `default` outside all regions.
+/// A linear scan; scenes are expected to use a handful of regions.
+#[inline(always)]
+pub fn gravity_at(regions: &[GravityRegion], pos: Vec2, default: Vec2) -> Vec2 {
+    regions
+        .iter()
+        .find(|region| region.rect.contains(pos))
+        .map_or(default, |region| region.gravity)
+}
+
+/// An axis-aligned region whose particles are held in place: while inside it they are not
+/// integrated and stay at rest, acting as a dam their neighbors still push against.
+#[derive(Reflect, Clone, Copy, Debug)]
+#[reflect(Default)]
+pub struct FreezeRegion {
+    pub rect: Rect,
+}
+
+/// A square around the origin, the starting point for regions added in the inspector.
+impl Default for FreezeRegion {
+    fn default() -> Self {
+        Self {
+            rect: Rect::from_center_size(Vec2::ZERO, Vec2::splat(200.0)),
+        }
+    }
+}
+
+/// Whether `pos` lies in any of the freeze `regions`.
+#[inline(always)]
+pub fn frozen_at(regions: &[FreezeRegion], pos: Vec2) -> bool {
+    regions.iter().any(|region| region.rect.contains(pos))
+}
+
+/// The upper half of `domain`, the built-in freeze-region demo: hold a reservoir there
+/// while the rest settles, then release it for a controlled pour.
+pub fn upper_half_freeze(domain: Rect) -> Vec<FreezeRegion> {
+    vec![FreezeRegion {
+        rect: Rect::from_corners(Vec2::new(domain.min.x, domain.center().y), domain.max),
+    }]
+}
+
+/// A strong, slanted-gravity chute on the upper left feeding a weak-gravity basin along
+/// the bottom, used as the built-in gravity-region demo.
+pub fn chute_basin_regions() -> Vec<GravityRegion> {
+    vec![
+        GravityRegion {
+            rect: Rect::new(-640.0, 0.0, -240.0, 360.0),
+            gravity: Vec2::new(150.0, -400.0),
+        },
+        GravityRegion {
+            rect: Rect::new(-640.0, -360.0, 640.0, -120.0),
+            gravity: Vec2::new(0.0, -25.0),
+        },
+    ]
+}
+
+/// A solid obstacle particles are pushed out of. Reflected so it can be edited live.
+#[derive(Reflect, Clone, Copy, Debug)]
+#[reflect(Default)]
+pub enum Obstacle {
+    Circle { center: Vec2, radius: f32 },
+    Box { center: Vec2, half_size: Vec2 },
+}
+
+/// A circle at the origin, the starting point for obstacles added in the inspector.
+impl Default for Obstacle {
+    fn default() -> Self {
+        Obstacle::Circle {
+            center: Vec2::ZERO,
+            radius: 50.0,
+        }
+    }
+}
+
+impl Obstacle {
+    /// If a particle of radius `radius` at `pos` overlaps the obstacle, returns the nearest
+    /// non-overlapping position and the outward surface normal there.
+    #[inline(always)]
+    pub fn resolve(&self, pos: Vec2, radius: f32) -> Option<(Vec2, Vec2)> {
+        match *self {
+            Obstacle::Circle {
+                center,
+                radius: obstacle_radius,
+            } => {
+                let offset = pos - center;
+                let reach = obstacle_radius + radius;
+                if offset.length_squared() >= reach * reach {
+                    return None;
+                }
+                let normal = offset.normalize_or(Vec2::Y);
+                Some((center + normal * reach, normal))
+            }
+            Obstacle::Box { center, half_size } => {
+                let local = pos - center;
+                let reach = half_size + Vec2::splat(radius);
+                let depth = reach - local.abs();
+                if depth.x <= 0.0 || depth.y <= 0.0 {
+                    return None;
+                }
+                // Push out along the axis of least penetration.
+                let sign = Vec2::new(
+                    if local.x >= 0.0 { 1.0 } else { -1.0 },
+                    if local.y >= 0.0 { 1.0 } else { -1.0 },
+                );
+                if depth.x < depth.y {
+                    let normal = Vec2::new(sign.x, 0.0);
+                    Some((Vec2::new(center.x + sign.x * reach.x, pos.y), normal))
+                } else {
+                    let normal = Vec2::new(0.0, sign.y);
+                    Some((Vec2::new(pos.x, center.y + sign.y * reach.y), normal))
+                }
+            }
+        }
+    }
+
+    /// Returns the fraction `t` in `[0, 1]` along the path `p0 -> p1` where a particle of
+    /// radius `radius` first touches the obstacle, with the outward normal there, or `None`
+    /// if the path misses it or starts overlapping it (left to `resolve`).
+    #[inline(always)]
+    pub fn sweep_intersection(&self, p0: Vec2, p1: Vec2, radius: f32) -> Option<(f32, Vec2)> {
+        let path = p1 - p0;
+        match *self {
+            Obstacle::Circle {
+                center,
+                radius: obstacle_radius,
+            } => {
+                // Smallest root of |p0 + t path - center|² = reach².
+                let reach = obstacle_radius + radius;
+                let offset = p0 - center;
+                let c = offset.length_squared() - reach * reach;
+                let a = path.length_squared();
+                let b = offset.dot(path);
+                if c <= 0.0 || a <= f32::EPSILON || b >= 0.0 {
+                    return None;
+                }
+                let discriminant = b * b - a * c;
+                if discriminant < 0.0 {
+                    return None;
+                }
+                let t = (-b - discriminant.sqrt()) / a;
+                (t <= 1.0).then(|| (t, (offset + path * t).normalize_or(Vec2::Y)))
+            }
+            Obstacle::Box { center, half_size } => {
+                // Slab test against the box grown by the particle radius.
+                let reach = half_size + Vec2::splat(radius);
+                let (lo, hi) = (center - reach, center + reach);
+                let mut entry = 0.0f32;
+                let mut exit = 1.0f32;
+                let mut normal = None;
+                for axis in 0..2 {
+                    let (start, step) = (p0[axis], path[axis]);
+                    if step.abs() <= f32::EPSILON {
+                        if start <= lo[axis] || start >= hi[axis] {
+                            return None;
+                        }
+                        continue;
+                    }
+                    let (near, far, side) = if step > 0.0 {
+                        (lo[axis], hi[axis], -1.0)
+                    } else {
+                        (hi[axis], lo[axis], 1.0)
+                    };
+                    let (t_near, t_far) = ((near - start) / step, (far - start) / step);
+                    if t_near > entry {
+                        entry = t_near;
+                        let mut axis_normal = Vec2::ZERO;
+                        axis_normal[axis] = side;
+                        normal = Some(axis_normal);
+                    }
+                    exit = exit.min(t_far);
+                }
+                if entry > exit {
+                    return None;
+                }
+                // No entry normal means the path starts inside the grown box.
+                normal.map(|normal| (entry, normal))
+            }
+        }
+    }
+}
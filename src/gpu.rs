@@ -0,0 +1,468 @@
+use std::{
+    borrow::Cow,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{
+            BindGroup, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry, BindingType, Buffer,
+            BufferBindingType, BufferDescriptor, BufferUsages, CommandEncoderDescriptor,
+            ComputePassDescriptor, ComputePipeline, DownlevelFlags, MapMode,
+            PipelineCompilationOptions, PipelineLayoutDescriptor, PollType,
+            RawComputePipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages,
+        },
+        renderer::{RenderAdapter, RenderDevice, RenderQueue},
+    },
+};
+
+use crate::{
+    grid::GridView,
+    resources::{Backend, FluidConfig, PressureEos, ViscosityKernel, ViscosityModel},
+};
+
+/// WGSL source of the density and force passes; compiled directly rather than through the
+/// asset server since the solver is used outside the render graph.
+const SHADER: &str = include_str!("../assets/shaders/sph_compute.wgsl");
+/// Must match `@workgroup_size` in the shader.
+const WORKGROUP_SIZE: u32 = 64;
+/// Bytes of the shader's `Params` uniform: 20 four-byte fields.
+const PARAMS_SIZE: u64 = 80;
+/// Bytes per particle of each storage buffer, matching the shader's array element types.
+const PARTICLE_BYTES: u64 = 16;
+const MASS_BYTES: u64 = 4;
+const DENS_PRESS_BYTES: u64 = 8;
+const SUMS_BYTES: u64 = 32;
+/// Waits on the GPU results longer than this mean it got too large a share of the forces.
+const BALANCED_WAIT: Duration = Duration::from_micros(200);
+/// Factor the GPU's share of the forces shrinks by, or grows by the inverse of, each step.
+const SHARE_ADJUST: f32 = 0.95;
+
+/// Neighbor sums of one particle computed by the GPU force pass.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NeighborSums {
+    pub pressure: Vec2,
+    pub viscosity: Vec2,
+    /// Kernel-weighted direction to the neighbors, for surface cohesion.
+    pub to_neighbors: Vec2,
+}
+
+/// Returns what `config` uses that `Backend::Gpu` does not implement, if anything.
+pub fn unsupported(config: &FluidConfig) -> Option<&'static str> {
+    if matches!(config.viscosity_model, ViscosityModel::PowerLaw { .. }) {
+        Some("power-law viscosity")
+    } else if matches!(config.viscosity_kernel, ViscosityKernel::Artificial { .. }) {
+        Some("artificial viscosity")
+    } else if config.kernel_table_size > 0 {
+        Some("kernel tables")
+    } else if config.shepard_correction {
+        Some("the Shepard correction")
+    } else {
+        None
+    }
+}
+
+/// Storage and staging buffers, rebuilt when the particles outgrow `capacity` or the grid
+/// outgrows `cells`.
+struct GpuBuffers {
+    params: Buffer,
+    particles: Buffer,
+    mass_factors: Buffer,
+    cell_start: Buffer,
+    cell_indices: Buffer,
+    sums: Buffer,
+    /// Mapped for reading back `sums`.
+    staging: Buffer,
+    bind_group: BindGroup,
+    capacity: usize,
+    cells: usize,
+}
+
+/// Compute pipelines of `Backend::Gpu`: `submit` uploads the particles and the CSR form of
+/// the neighbor grid and starts the density and force passes for a leading share of the
+/// particles, and `finish` reads their neighbor sums back. In between the CPU computes every
+/// density and the other particles' forces, and the share adapts until the GPU is done by
+/// then. The CPU still builds the grid, integrates and collides. One solver serves every
+/// simulation instance in turn.
+#[derive(Resource)]
+pub struct GpuSolver {
+    device: RenderDevice,
+    queue: RenderQueue,
+    layout: BindGroupLayout,
+    density_pipeline: ComputePipeline,
+    forces_pipeline: ComputePipeline,
+    buffers: Option<GpuBuffers>,
+    cell_start: Vec<u32>,
+    cell_indices: Vec<u32>,
+    /// Results of the latest `finish`, one entry per particle of the GPU's share.
+    pub sums: Vec<NeighborSums>,
+    /// Fraction of the particles whose neighbor sums the GPU computes.
+    share: f32,
+    /// Particles whose sums the latest `submit` started, read back by `finish`.
+    in_flight: usize,
+    /// Reason of the latest CPU fallback, so it is logged once.
+    fallback: Option<&'static str>,
+}
+
+impl GpuSolver {
+    /// Compiles the shader and pipelines on `device`. Buffers are allocated on first use.
+    pub fn new(device: &RenderDevice, queue: &RenderQueue) -> Self {
+        let module = device.create_and_validate_shader_module(ShaderModuleDescriptor {
+            label: Some("sph_compute"),
+            source: ShaderSource::Wgsl(Cow::Borrowed(SHADER)),
+        });
+        let entry = |binding, ty| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let read = BufferBindingType::Storage { read_only: true };
+        let write = BufferBindingType::Storage { read_only: false };
+        let layout = device.create_bind_group_layout(
+            "sph_compute_layout",
+            &[
+                entry(0, BufferBindingType::Uniform),
+                entry(1, read),
+                entry(2, read),
+                entry(3, read),
+                entry(4, read),
+                entry(5, write),
+                entry(6, write),
+            ],
+        );
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("sph_compute_pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&RawComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+        Self {
+            density_pipeline: pipeline("density"),
+            forces_pipeline: pipeline("forces"),
+            device: device.clone(),
+            queue: queue.clone(),
+            layout,
+            buffers: None,
+            cell_start: Vec::new(),
+            cell_indices: Vec::new(),
+            sums: Vec::new(),
+            share: 0.5,
+            in_flight: 0,
+            fallback: None,
+        }
+    }
+
+    /// Returns the solver if `config` selects `Backend::Gpu` and uses only what it
+    /// implements; otherwise the step runs on the CPU, with a warning once per reason.
+    pub fn accepts(&mut self, config: &FluidConfig) -> Option<&mut Self> {
+        if config.backend != Backend::Gpu {
+            return None;
+        }
+        match unsupported(config) {
+            Some(reason) => {
+                if self.fallback != Some(reason) {
+                    warn!("The GPU backend does not support {reason}; stepping on the CPU");
+                    self.fallback = Some(reason);
+                }
+                None
+            }
+            None => {
+                self.fallback = None;
+                Some(self)
+            }
+        }
+    }
+
+    /// Starts the density pass for the first `n` particles at `positions` and the force pass
+    /// for a leading share of them, and returns how many particles that share holds. Collect
+    /// their sums with `finish`.
+    pub fn submit(
+        &mut self,
+        config: &FluidConfig,
+        grid: &GridView,
+        positions: &[Vec2],
+        velocities: &[Vec2],
+        mass_factors: &[f32],
+        n: usize,
+    ) -> usize {
+        self.in_flight = 0;
+        if n == 0 {
+            return 0;
+        }
+        let count = ((n as f32 * self.share).round() as usize).clamp(1, n);
+
+        // Flatten the grid in cell order, so candidates are visited as on the CPU.
+        self.cell_start.clear();
+        self.cell_indices.clear();
+        for cell in grid.cells {
+            self.cell_start.push(self.cell_indices.len() as u32);
+            self.cell_indices.extend(cell.iter().map(|&j| j as u32));
+        }
+        self.cell_start.push(self.cell_indices.len() as u32);
+
+        let cells = grid.cells.len();
+        if self
+            .buffers
+            .as_ref()
+            .is_none_or(|b| b.capacity < n || b.cells < cells)
+        {
+            let capacity = self.buffers.as_ref().map_or(0, |b| b.capacity);
+            self.buffers = Some(self.create_buffers(n.max(capacity).next_power_of_two(), cells));
+        }
+        let buffers = self.buffers.as_ref().expect("buffers were just created");
+
+        let queue = &self.queue;
+        queue.write_buffer(&buffers.params, 0, &params_bytes(config, grid, n, count));
+        let mut particles = Vec::with_capacity(n * PARTICLE_BYTES as usize);
+        for (pos, vel) in positions[..n].iter().zip(&velocities[..n]) {
+            for value in [pos.x, pos.y, vel.x, vel.y] {
+                particles.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        queue.write_buffer(&buffers.particles, 0, &particles);
+        queue.write_buffer(&buffers.mass_factors, 0, &f32_bytes(&mass_factors[..n]));
+        queue.write_buffer(&buffers.cell_start, 0, &u32_bytes(&self.cell_start));
+        queue.write_buffer(&buffers.cell_indices, 0, &u32_bytes(&self.cell_indices));
+
+        let sums_size = count as u64 * SUMS_BYTES;
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("sph_compute"),
+            });
+        {
+            let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("sph_compute"),
+                timestamp_writes: None,
+            });
+            pass.set_bind_group(0, &*buffers.bind_group, &[]);
+            pass.set_pipeline(&self.density_pipeline);
+            pass.dispatch_workgroups((n as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+            pass.set_pipeline(&self.forces_pipeline);
+            pass.dispatch_workgroups((count as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&buffers.sums, 0, &buffers.staging, 0, sums_size);
+        queue.submit([encoder.finish()]);
+        self.in_flight = count;
+        count
+    }
+
+    /// Waits for the passes started by `submit` and stores the neighbor sums of its share in
+    /// `sums`. Returns false, logging why, if the device failed and those particles have to
+    /// use the CPU. Shrinks the share for the next step if the GPU was still busy, and grows
+    /// it otherwise.
+    pub fn finish(&mut self) -> bool {
+        self.sums.clear();
+        let count = std::mem::take(&mut self.in_flight);
+        let Some(buffers) = self.buffers.as_ref().filter(|_| count > 0) else {
+            return false;
+        };
+        let slice = buffers.staging.slice(..count as u64 * SUMS_BYTES);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        let wait = Instant::now();
+        if let Err(e) = self.device.poll(PollType::Wait) {
+            error!("GPU step did not complete: {e}");
+            return false;
+        }
+        self.share = if wait.elapsed() > BALANCED_WAIT {
+            self.share * SHARE_ADJUST
+        } else {
+            (self.share / SHARE_ADJUST).min(1.0)
+        };
+        match receiver.try_recv() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                error!("Cannot read back the GPU results: {e}");
+                return false;
+            }
+            Err(_) => {
+                error!("GPU results were not mapped after the step completed");
+                return false;
+            }
+        }
+        {
+            let data = slice.get_mapped_range();
+            let values: Vec<f32> = data
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect();
+            self.sums
+                .extend(values.chunks_exact(8).map(|s| NeighborSums {
+                    pressure: Vec2::new(s[0], s[1]),
+                    viscosity: Vec2::new(s[2], s[3]),
+                    to_neighbors: Vec2::new(s[4], s[5]),
+                }));
+        }
+        buffers.staging.unmap();
+        true
+    }
+
+    /// Allocates buffers for `capacity` particles and a grid of `cells` cells.
+    fn create_buffers(&self, capacity: usize, cells: usize) -> GpuBuffers {
+        let size = capacity as u64;
+        let buffer = |label, size: u64, usage| {
+            self.device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size: size.max(16),
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let input = BufferUsages::STORAGE | BufferUsages::COPY_DST;
+        let output = BufferUsages::STORAGE | BufferUsages::COPY_SRC;
+        let params = buffer(
+            "sph_params",
+            PARAMS_SIZE,
+            BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        );
+        let particles = buffer("sph_particles", size * PARTICLE_BYTES, input);
+        let mass_factors = buffer("sph_mass_factors", size * MASS_BYTES, input);
+        let cell_start = buffer("sph_cell_start", (cells as u64 + 1) * 4, input);
+        let cell_indices = buffer("sph_cell_indices", size * 4, input);
+        // Only the force pass reads the densities back; the bind group keeps it alive.
+        let dens_press = buffer("sph_dens_press", size * DENS_PRESS_BYTES, output);
+        let sums = buffer("sph_sums", size * SUMS_BYTES, output);
+        let staging = buffer(
+            "sph_staging",
+            size * SUMS_BYTES,
+            BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        );
+        let bind_group = self.device.create_bind_group(
+            "sph_compute_bind_group",
+            &self.layout,
+            &[
+                &params,
+                &particles,
+                &mass_factors,
+                &cell_start,
+                &cell_indices,
+                &dens_press,
+                &sums,
+            ]
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect::<Vec<_>>(),
+        );
+        GpuBuffers {
+            params,
+            particles,
+            mass_factors,
+            cell_start,
+            cell_indices,
+            sums,
+            staging,
+            bind_group,
+            capacity,
+            cells,
+        }
+    }
+}
+
+/// Encodes the shader's `Params` uniform for `n` particles, the first `force_count` of
+/// which get the force pass.
+fn params_bytes(config: &FluidConfig, grid: &GridView, n: usize, force_count: usize) -> Vec<u8> {
+    let h = config.smoothing_radius;
+    let (eos, gamma, b) = match config.pressure_eos {
+        PressureEos::Linear => (0u32, 0.0, 0.0),
+        PressureEos::Tait { gamma, b } => (1, gamma, b),
+    };
+    let words: [u32; 20] = [
+        n as u32,
+        grid.width as u32,
+        grid.height as u32,
+        grid.scan_radius as u32,
+        grid.cell_size.to_bits(),
+        grid.offset_x.to_bits(),
+        grid.offset_y.to_bits(),
+        h.to_bits(),
+        (h * config.min_dist_fraction.clamp(0.0, 1.0)).to_bits(),
+        config.particle_mass.to_bits(),
+        config.target_density.to_bits(),
+        config.pressure_multiplier.to_bits(),
+        eos,
+        f32::to_bits(gamma),
+        f32::to_bits(b),
+        config.clamp_negative_pressure as u32,
+        config.viscosity_strength.to_bits(),
+        config.background_pressure.to_bits(),
+        config.min_density().to_bits(),
+        force_count as u32,
+    ];
+    u32_bytes(&words)
+}
+
+fn f32_bytes(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn u32_bytes(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// Whether `adapter` can run the compute passes.
+fn supports_compute(adapter: &RenderAdapter) -> bool {
+    adapter
+        .get_downlevel_capabilities()
+        .flags
+        .contains(DownlevelFlags::COMPUTE_SHADERS)
+}
+
+/// Creates the `GpuSolver` when the render device supports compute shaders, so
+/// `Backend::Gpu` can be selected at any time; without it every step runs on the CPU.
+pub fn init_gpu_solver(
+    mut commands: Commands,
+    device: Option<Res<RenderDevice>>,
+    queue: Option<Res<RenderQueue>>,
+    adapter: Option<Res<RenderAdapter>>,
+) {
+    let (Some(device), Some(queue), Some(adapter)) = (device, queue, adapter) else {
+        return;
+    };
+    if !supports_compute(&adapter) {
+        warn!("The render device has no compute shaders; the GPU backend will use the CPU");
+        return;
+    }
+    commands.insert_resource(GpuSolver::new(&device, &queue));
+}
+
+/// Creates a solver on a device of its own, without a window, for the tests.
+/// Returns `None` if no adapter supports compute shaders.
+#[cfg(test)]
+pub fn headless_solver() -> Option<GpuSolver> {
+    use bevy::render::{
+        renderer::initialize_renderer,
+        settings::{Backends, RenderResources, WgpuSettings},
+    };
+
+    let settings = WgpuSettings::default();
+    let backends = settings.backends.unwrap_or(Backends::all());
+    // `initialize_renderer` panics when there is no adapter at all.
+    let RenderResources(device, queue, _, adapter, _) =
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            bevy::tasks::block_on(initialize_renderer(backends, None, &settings))
+        }))
+        .ok()?;
+    supports_compute(&adapter).then(|| GpuSolver::new(&device, &queue))
+}
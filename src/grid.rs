@@ -0,0 +1,167 @@
+use bevy::prelude::*;
+
+/// Borrowed view of the uniform spatial grid used for neighbor searches.
+/// Kept separate from `FluidSimulation` so passes can read the grid while
+/// mutably borrowing other particle buffers.
+#[derive(Clone, Copy)]
+pub struct GridView<'a> {
+    pub cells: &'a [Vec<usize>],
+    pub width: usize,
+    pub height: usize,
+    pub cell_size: f32,
+    pub offset_x: f32,
+    pub offset_y: f32,
+    /// Number of cell rings scanned around the query cell (1 = the 3x3 block).
+    pub scan_radius: usize,
+}
+
+impl GridView<'_> {
+    /// Returns the (column, row) of the cell containing `pos`.
+    #[inline(always)]
+    pub fn cell_coords(&self, pos: Vec2) -> (usize, usize) {
+        let gx = ((pos.x + self.offset_x) / self.cell_size) as usize;
+        let gy = ((pos.y + self.offset_y) / self.cell_size) as usize;
+        (gx, gy)
+    }
+
+    /// Calls `f` with every particle index stored within `scan_radius` cell rings of `pos`
+    /// (a `(2r + 1)^2` block). Candidates still need a distance check against the support radius.
+    #[inline(always)]
+    pub fn for_each_candidate(&self, pos: Vec2, mut f: impl FnMut(usize)) {
+        let (gx, gy) = self.cell_coords(pos);
+        let r = self.scan_radius as isize;
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let cx = (gx as isize + dx) as usize;
+                let cy = (gy as isize + dy) as usize;
+                if cx >= self.width || cy >= self.height {
+                    continue;
+                }
+                if let Some(cell) = self.cells.get(cy * self.width + cx) {
+                    for &j in cell {
+                        f(j);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Calls `f` with every particle index stored in the cells overlapping the square of
+    /// half-size `radius` around `pos`, for queries wider than the neighbor scan.
+    /// Candidates still need a distance check.
+    pub fn for_each_within(&self, pos: Vec2, radius: f32, mut f: impl FnMut(usize)) {
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+        let cells = |p: f32, offset: f32, len: usize| {
+            let cell = |p: f32| ((p + offset) / self.cell_size).floor().max(0.0) as usize;
+            cell(p - radius)..=cell(p + radius).min(len - 1)
+        };
+        for cy in cells(pos.y, self.offset_y, self.height) {
+            for cx in cells(pos.x, self.offset_x, self.width) {
+                if let Some(cell) = self.cells.get(cy * self.width + cx) {
+                    for &j in cell {
+                        f(j);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Compressed (CSR) alternative to the `Vec<Vec<usize>>` grid: particle indices sorted by
+/// cell in one buffer, with `cell_start[c]..cell_start[c + 1]` delimiting cell `c`.
+/// Rebuilding is a counting sort that reuses both allocations. Compiled with the `flat-grid`
+/// feature for `benches/grid.rs`, which compares it against the current grid.
+#[cfg(feature = "flat-grid")]
+#[allow(dead_code)] // Only the benchmark uses it so far.
+pub struct FlatGrid {
+    pub cell_start: Vec<usize>,
+    pub indices: Vec<usize>,
+    pub width: usize,
+    pub height: usize,
+    pub cell_size: f32,
+    pub offset_x: f32,
+    pub offset_y: f32,
+    pub scan_radius: usize,
+}
+
+#[cfg(feature = "flat-grid")]
+#[allow(dead_code)]
+impl FlatGrid {
+    /// Creates an empty grid with the same layout parameters as `GridView`.
+    pub fn new(
+        width: usize,
+        height: usize,
+        cell_size: f32,
+        offset_x: f32,
+        offset_y: f32,
+        scan_radius: usize,
+    ) -> Self {
+        Self {
+            cell_start: vec![0; width * height + 1],
+            indices: Vec::new(),
+            width,
+            height,
+            cell_size,
+            offset_x,
+            offset_y,
+            scan_radius,
+        }
+    }
+
+    /// Cell index of `pos`, clamped into the grid like the `Vec<Vec<usize>>` rebuild.
+    #[inline(always)]
+    fn cell_index(&self, pos: Vec2) -> usize {
+        let gx = ((pos.x + self.offset_x) / self.cell_size) as usize;
+        let gy = ((pos.y + self.offset_y) / self.cell_size) as usize;
+        (gy * self.width + gx).min(self.width * self.height - 1)
+    }
+
+    /// Re-sorts `positions` into cells with a counting sort.
+    pub fn rebuild(&mut self, positions: &[Vec2]) {
+        self.cell_start.fill(0);
+        for &pos in positions {
+            let cell = self.cell_index(pos);
+            self.cell_start[cell + 1] += 1;
+        }
+        for c in 1..self.cell_start.len() {
+            self.cell_start[c] += self.cell_start[c - 1];
+        }
+        self.indices.resize(positions.len(), 0);
+        // Scatter using a running cursor per cell; cell_start is restored afterwards.
+        for (i, &pos) in positions.iter().enumerate() {
+            let cell = self.cell_index(pos);
+            self.indices[self.cell_start[cell]] = i;
+            self.cell_start[cell] += 1;
+        }
+        for c in (1..self.cell_start.len()).rev() {
+            self.cell_start[c] = self.cell_start[c - 1];
+        }
+        self.cell_start[0] = 0;
+    }
+
+    /// Same traversal as `GridView::for_each_candidate`, over the flat buffers.
+    #[inline(always)]
+    pub fn for_each_candidate(&self, pos: Vec2, mut f: impl FnMut(usize)) {
+        let gx = ((pos.x + self.offset_x) / self.cell_size) as usize;
+        let gy = ((pos.y + self.offset_y) / self.cell_size) as usize;
+        let r = self.scan_radius as isize;
+        for dy in -r..=r {
+            let cy = (gy as isize + dy) as usize;
+            if cy >= self.height {
+                continue;
+            }
+            for dx in -r..=r {
+                let cx = (gx as isize + dx) as usize;
+                if cx >= self.width {
+                    continue;
+                }
+                let cell = cy * self.width + cx;
+                for &j in &self.indices[self.cell_start[cell]..self.cell_start[cell + 1]] {
+                    f(j);
+                }
+            }
+        }
+    }
+}
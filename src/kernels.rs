@@ -1,5 +1,7 @@
 use std::f32::consts::PI;
 
+use crate::resources::MIN_DENSITY;
+
 /// Poly6 kernel for density calculation (2D version).
 /// Formula: W(r,h) = (4/(π*h^8)) * (h^2 - r^2)^3
 #[inline(always)]
@@ -16,14 +18,15 @@ pub fn poly6_kernel(dist_sq: f32, h: f32) -> f32 {
 }
 
 /// Spiky kernel gradient magnitude for pressure force calculation (2D version).
-/// Returns |∇W| = (10/(π h^5)) * (h - r)^3 (positive magnitude)
+/// Kernel: W(r,h) = (10/(π h^5)) * (h - r)^3
+/// Returns |∇W| = (30/(π h^5)) * (h - r)^2 (positive magnitude)
 #[inline(always)]
 pub fn spiky_kernel_gradient(dist: f32, h: f32) -> f32 {
     if dist < h {
         let h5 = h.powi(5);
-        let coeff = 10.0 / (PI * h5);
+        let coeff = 30.0 / (PI * h5);
         let diff = h - dist;
-        coeff * diff * diff * diff
+        coeff * diff * diff
     } else {
         0.0
     }
@@ -31,6 +34,7 @@ pub fn spiky_kernel_gradient(dist: f32, h: f32) -> f32 {
 
 /// Viscosity kernel laplacian for viscosity force calculation (2D version).
 /// Formula: ∇²W(r,h) = (40/(π h^6)) * (h - r)
+#[cfg(test)] // Reference for `KernelCache::viscosity_laplacian`, which the step uses.
 #[inline(always)]
 pub fn viscosity_laplacian(dist: f32, h: f32) -> f32 {
     if dist < h {
@@ -42,3 +46,171 @@ pub fn viscosity_laplacian(dist: f32, h: f32) -> f32 {
         0.0
     }
 }
+
+/// Monaghan artificial viscosity term Π_ij for a pair approaching with `v_ij · x_ij < 0`
+/// (relative velocity and offset of i from j), zero for separating pairs.
+/// Formula: Π_ij = -α c μ_ij / ρ̄_ij with μ_ij = h (v_ij · x_ij) / (|x_ij|² + 0.01 h²)
+#[inline(always)]
+pub fn artificial_viscosity(
+    v_dot_x: f32,
+    dist_sq: f32,
+    h: f32,
+    alpha: f32,
+    sound_speed: f32,
+    mean_density: f32,
+) -> f32 {
+    if v_dot_x >= 0.0 {
+        return 0.0;
+    }
+    let mu = h * v_dot_x / (dist_sq + 0.01 * h * h);
+    -alpha * sound_speed * mu / mean_density.max(MIN_DENSITY)
+}
+
+/// The poly6, spiky gradient and viscosity Laplacian kernels for one smoothing radius, with
+/// their `h`-dependent coefficients computed once per step rather than once per pair.
+/// With a table size, poly6 (over `r²`) and the spiky gradient (over `r`) are read from
+/// lookup tables with linear interpolation instead of being evaluated.
+#[derive(Clone, Debug)]
+pub struct KernelCache {
+    pub h: f32,
+    /// Samples per table, 0 for exact evaluation.
+    pub table_size: usize,
+    h_sq: f32,
+    poly6_coeff: f32,
+    spiky_coeff: f32,
+    viscosity_coeff: f32,
+    /// Poly6 at evenly spaced `r²` in `[0, h²]`; empty for exact evaluation.
+    poly6_table: Vec<f32>,
+    /// Spiky gradient at evenly spaced `r` in `[0, h]`; empty for exact evaluation.
+    spiky_table: Vec<f32>,
+}
+
+impl KernelCache {
+    /// Kernels for smoothing radius `h`, tabulated at `table_size` samples (at least 2)
+    /// each, or evaluated exactly for 0. Exact values match the free kernel functions.
+    pub fn new(h: f32, table_size: usize) -> Self {
+        let h_sq = h * h;
+        let mut cache = Self {
+            h,
+            table_size,
+            h_sq,
+            poly6_coeff: 4.0 / (PI * h.powi(8)),
+            spiky_coeff: 30.0 / (PI * h.powi(5)),
+            viscosity_coeff: 40.0 / (PI * h.powi(6)),
+            poly6_table: Vec::new(),
+            spiky_table: Vec::new(),
+        };
+        if table_size > 0 {
+            let last = table_size.max(2) - 1;
+            let at = |k: usize| k as f32 / last as f32;
+            cache.poly6_table = (0..=last).map(|k| poly6_kernel(h_sq * at(k), h)).collect();
+            cache.spiky_table = (0..=last)
+                .map(|k| spiky_kernel_gradient(h * at(k), h))
+                .collect();
+        }
+        cache
+    }
+
+    /// `poly6_kernel(dist_sq, h)`.
+    #[inline(always)]
+    pub fn poly6(&self, dist_sq: f32) -> f32 {
+        if dist_sq >= self.h_sq {
+            0.0
+        } else if self.poly6_table.is_empty() {
+            let diff = self.h_sq - dist_sq;
+            self.poly6_coeff * diff * diff * diff
+        } else {
+            lerp_table(&self.poly6_table, dist_sq / self.h_sq)
+        }
+    }
+
+    /// `spiky_kernel_gradient(dist, h)`.
+    #[inline(always)]
+    pub fn spiky_gradient(&self, dist: f32) -> f32 {
+        if dist >= self.h {
+            0.0
+        } else if self.spiky_table.is_empty() {
+            let diff = self.h - dist;
+            self.spiky_coeff * diff * diff
+        } else {
+            lerp_table(&self.spiky_table, dist / self.h)
+        }
+    }
+
+    /// `viscosity_laplacian(dist, h)`, always exact since it is linear in `r`.
+    #[inline(always)]
+    pub fn viscosity_laplacian(&self, dist: f32) -> f32 {
+        if dist < self.h {
+            self.viscosity_coeff * (self.h - dist)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Linearly interpolates `table`, sampled evenly over `t` in `[0, 1]`, at `t` in `[0, 1)`.
+#[inline(always)]
+fn lerp_table(table: &[f32], t: f32) -> f32 {
+    let x = t * (table.len() - 1) as f32;
+    let k = (x as usize).min(table.len() - 2);
+    let frac = x - k as f32;
+    table[k] + (table[k + 1] - table[k]) * frac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Distances the tables are compared at, not a multiple of common table sizes so most
+    /// fall between table entries.
+    const SAMPLES: usize = 10_007;
+
+    /// Largest difference between `cache` and the exact kernels at `SAMPLES` evenly spaced
+    /// distances in `[0, h]`, relative to each kernel's peak at `r = 0`.
+    fn max_table_error(cache: &KernelCache) -> f32 {
+        let h = cache.h;
+        let (poly6_peak, spiky_peak) = (poly6_kernel(0.0, h), spiky_kernel_gradient(0.0, h));
+        (0..SAMPLES)
+            .map(|k| {
+                let dist = h * k as f32 / (SAMPLES - 1) as f32;
+                let poly6 = (cache.poly6(dist * dist) - poly6_kernel(dist * dist, h)).abs();
+                let spiky = (cache.spiky_gradient(dist) - spiky_kernel_gradient(dist, h)).abs();
+                (poly6 / poly6_peak).max(spiky / spiky_peak)
+            })
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn exact_cache_matches_free_kernels() {
+        let h = 20.0;
+        let cache = KernelCache::new(h, 0);
+        for k in 0..=100 {
+            let dist = h * 1.1 * k as f32 / 100.0;
+            let close = |a: f32, b: f32| (a - b).abs() <= b.abs() * 1e-5;
+            assert!(close(
+                cache.poly6(dist * dist),
+                poly6_kernel(dist * dist, h)
+            ));
+            assert!(close(
+                cache.spiky_gradient(dist),
+                spiky_kernel_gradient(dist, h)
+            ));
+            assert!(close(
+                cache.viscosity_laplacian(dist),
+                viscosity_laplacian(dist, h)
+            ));
+        }
+    }
+
+    #[test]
+    fn tables_match_exact_kernels() {
+        // Linear interpolation error falls with the square of the size.
+        for size in [256, 1024] {
+            let error = max_table_error(&KernelCache::new(20.0, size));
+            assert!(
+                error <= 1e-4,
+                "{size} samples are off by {error} of the peak"
+            );
+        }
+    }
+}
@@ -0,0 +1,20 @@
+//! 2D SPH fluid simulation on Bevy. The `fluid2d` binary is the interactive app; the library
+//! exposes the simulation state, its per-particle attributes and the systems that step it.
+
+mod components;
+mod contour;
+mod geometry;
+pub mod gpu;
+mod grid;
+mod kernels;
+pub mod materials;
+pub mod resources;
+pub mod scene;
+#[cfg(feature = "simd")]
+mod simd;
+mod spectrum;
+mod stats;
+pub mod systems;
+mod toml_dump;
+
+pub use resources::{FluidConfig, FluidSimulation, ParticleAttributes};
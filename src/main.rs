@@ -1,45 +1,302 @@
-mod components;
-mod kernels;
-mod resources;
-mod systems;
-
 use bevy::{
     diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
     prelude::*,
+    sprite_render::Material2dPlugin,
     window::{PresentMode, WindowResolution},
 };
 use bevy_inspector_egui::{bevy_egui::EguiPlugin, quick::ResourceInspectorPlugin};
 
-use resources::{FluidConfig, FluidSimulation, BOUNDARY_HEIGHT, BOUNDARY_WIDTH};
-use systems::*;
+use fluid2d::{
+    gpu,
+    materials::AdditiveParticleMaterial,
+    resources::{
+        AdaptiveParticleCount, BOUNDARY_HEIGHT, BOUNDARY_WIDTH, ComparisonConfig, ComparisonSim,
+        EnergyHistory, FlowMeter, FluidConfig, FluidSimulation, PARTICLE_RADIUS, ParticleProbe,
+        ParticleRemoved, ParticleSpawned, PhaseProfiler, RenderConfig, SI_WORLD_SCALE,
+        SceneElements, SelectedInitialCondition, SettleDetector, SimStats, SimulationSettled,
+        SweepRecorder, WindowUnfocused, split_domain,
+    },
+    scene,
+    systems::*,
+};
+
+/// Returns the value of `--<name> <value>` (or `--<name>=<value>`) from the command line.
+fn arg_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix(flag) {
+            Some("") => return args.next(),
+            Some(rest) if rest.starts_with('=') => return Some(rest[1..].to_owned()),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parses `--seed <u64>` (or `--seed=<u64>`) from the command line.
+fn parse_seed_arg() -> Option<u64> {
+    let value = arg_value("--seed")?;
+    match value.parse() {
+        Ok(seed) => Some(seed),
+        Err(_) => {
+            eprintln!("Ignoring invalid --seed value, expected an unsigned integer");
+            None
+        }
+    }
+}
+
+/// Returns whether a bare flag such as `--si` was passed on the command line.
+fn has_flag(flag: &str) -> bool {
+    std::env::args().skip(1).any(|arg| arg == flag)
+}
 
 /// Main entry point for the fluid simulation application.
 fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "2D Fluid Simulation".into(),
-                resolution: WindowResolution::new(
-                    BOUNDARY_WIDTH as u32 + 50,
-                    BOUNDARY_HEIGHT as u32 + 50,
-                ),
-                present_mode: PresentMode::AutoNoVsync,
-                ..default()
-            }),
+    let seed = parse_seed_arg().unwrap_or_else(rand::random);
+    // `--si` runs the physics in metres and renders at `SI_WORLD_SCALE` px/m.
+    let (mut config, mut sim, render_config) = if has_flag("--si") {
+        let config = FluidConfig {
+            seed,
+            ..FluidConfig::si_like()
+        };
+        let length = 1.0 / SI_WORLD_SCALE;
+        let sim = FluidSimulation::with_domain(
+            seed,
+            Rect::from_center_size(
+                Vec2::ZERO,
+                Vec2::new(BOUNDARY_WIDTH, BOUNDARY_HEIGHT) * length,
+            ),
+            25.0 * length,
+            PARTICLE_RADIUS * length,
+        );
+        let render_config = RenderConfig {
+            world_scale: SI_WORLD_SCALE,
+            ..default()
+        };
+        (config, sim, render_config)
+    } else {
+        let config = FluidConfig { seed, ..default() };
+        (config, FluidSimulation::new(seed), RenderConfig::default())
+    };
+    // `--compare` splits the domain: the main instance runs on the left half and a second,
+    // independently configurable one on the right, both from the same seed.
+    let mut comparison = None;
+    if has_flag("--compare") {
+        let [left, right] = split_domain(sim.domain);
+        let half = |domain| {
+            FluidSimulation::with_domain(seed, domain, sim.base_cell_size, sim.particle_radius)
+        };
+        comparison = Some(half(right));
+        sim = half(left);
+    }
+    // `--image <path>` starts with particles on the image's dark pixels instead.
+    if let Some(path) = arg_value("--image") {
+        match sim.reset_from_image(&path, &config) {
+            Ok(0) => eprintln!("'{path}' has no dark pixels, using a random layout"),
+            Ok(_) => {
+                sim.assign_ids(render_config.id_pattern);
+                if let Some(other) = &mut comparison
+                    && other.reset_from_image(&path, &config).is_ok()
+                {
+                    other.assign_ids(render_config.id_pattern);
+                }
+            }
+            Err(e) => eprintln!("Cannot load '{path}': {e}, using a random layout"),
+        }
+    }
+    // `--csv <path>` starts with the particles listed in a CSV file (`x,y[,vx,vy]` rows).
+    if let Some(path) = arg_value("--csv") {
+        match sim.reset_from_csv(&path) {
+            Ok(_) => {
+                sim.assign_ids(render_config.id_pattern);
+                if let Some(other) = &mut comparison
+                    && other.reset_from_csv(&path).is_ok()
+                {
+                    other.assign_ids(render_config.id_pattern);
+                }
+            }
+            Err(e) => eprintln!("Cannot load '{path}': {e}, using a random layout"),
+        }
+    }
+    sim.apply_initial_velocity(config.initial_velocity, config.seed);
+    if let Some(other) = &mut comparison {
+        other.apply_initial_velocity(config.initial_velocity, config.seed);
+    }
+    if config.auto_calibrate_target_density {
+        calibrate_target_density(&mut sim, &mut config);
+    }
+    // `--scene <path>` restores a file saved with 'O' into the main instance.
+    let mut elements = SceneElements::default();
+    if let Some(path) = arg_value("--scene") {
+        match scene::load_scene(&path) {
+            Ok(scene) => (config, elements) = scene.restore(&mut sim),
+            Err(e) => eprintln!("Cannot load '{path}': {e}, using the default scene"),
+        }
+    }
+
+    // `--wave-speed` sends a pressure pulse across a weightless layout, prints its measured
+    // speed against the equation of state's sound speed and exits.
+    if has_flag("--wave-speed") {
+        let sound_speed = config
+            .pressure_eos
+            .sound_speed(config.target_density, config.pressure_multiplier);
+        match measure_wave_speed(&sim, &config) {
+            Some(speed) => println!(
+                "Wave speed {speed:.3}, EOS sound speed {sound_speed:.3}, ratio {:.3}",
+                speed / sound_speed
+            ),
+            None => {
+                println!("No pressure peak travelled between the probes");
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
+
+    // `--time-reversal <steps>` runs forward, reverses all velocities, runs back and exits.
+    if let Some(value) = arg_value("--time-reversal") {
+        let Ok(steps) = value.parse::<usize>() else {
+            eprintln!("Invalid --time-reversal value, expected a step count");
+            std::process::exit(2);
+        };
+        // Inviscid, undamped and with elastic walls, so an ideal solver would return exactly.
+        let inviscid = FluidConfig {
+            viscosity_strength: 0.0,
+            linear_damping: 1.0,
+            boundary_damping: 1.0,
+            boundary_friction: 0.0,
+            ..config.clone()
+        };
+        let (mean, max) = time_reversal_error(&sim, &inviscid, steps);
+        let h = inviscid.smoothing_radius;
+        println!(
+            "Time reversal over {steps} + {steps} steps: mean return error {mean:e} ({:.3} h), \
+             max {max:e} ({:.3} h)",
+            mean / h,
+            max / h
+        );
+        std::process::exit(0);
+    }
+
+    // The comparison instance starts with a copy of the main config.
+    let comparison =
+        comparison.map(|other| (ComparisonSim(other), ComparisonConfig(config.clone())));
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins.set(WindowPlugin {
+        primary_window: Some(Window {
+            title: "2D Fluid Simulation".into(),
+            resolution: WindowResolution::new(
+                BOUNDARY_WIDTH as u32 + 50,
+                BOUNDARY_HEIGHT as u32 + 50,
+            ),
+            present_mode: PresentMode::AutoNoVsync,
             ..default()
-        }))
-        .add_plugins(EguiPlugin::default())
-        .add_plugins(ResourceInspectorPlugin::<FluidConfig>::default())
-        .add_plugins((
-            LogDiagnosticsPlugin::default(),
-            FrameTimeDiagnosticsPlugin::default(),
-        ))
-        .init_resource::<FluidConfig>()
-        .insert_resource(FluidSimulation::new())
-        .add_systems(Startup, setup_scene)
-        .add_systems(
-            Update,
-            (handle_input, update_physics_rayon, sync_rendering).chain(),
+        }),
+        ..default()
+    }))
+    .add_plugins(EguiPlugin::default())
+    .add_plugins(Material2dPlugin::<AdditiveParticleMaterial>::default())
+    .add_plugins(ResourceInspectorPlugin::<FluidConfig>::default())
+    .add_plugins(ResourceInspectorPlugin::<RenderConfig>::default())
+    .add_plugins(ResourceInspectorPlugin::<SceneElements>::default())
+    .add_plugins(ResourceInspectorPlugin::<SimStats>::default())
+    .add_plugins(ResourceInspectorPlugin::<FlowMeter>::default())
+    .add_plugins(ResourceInspectorPlugin::<ParticleProbe>::default())
+    .add_plugins((
+        LogDiagnosticsPlugin::default(),
+        FrameTimeDiagnosticsPlugin::default(),
+    ))
+    .insert_resource(config)
+    .insert_resource(sim)
+    .insert_resource(render_config)
+    .insert_resource(elements)
+    .init_resource::<EnergyHistory>()
+    .init_resource::<SimStats>()
+    .init_resource::<AdaptiveParticleCount>()
+    .init_resource::<SelectedInitialCondition>()
+    .init_resource::<SettleDetector>()
+    .init_resource::<SweepRecorder>()
+    .init_resource::<PhaseProfiler>()
+    .init_resource::<ParticleProbe>()
+    .init_resource::<FlowMeter>()
+    .init_resource::<WindowUnfocused>()
+    .add_message::<SimulationSettled>()
+    .add_message::<ParticleSpawned>()
+    .add_message::<ParticleRemoved>()
+    .add_systems(Startup, (setup_scene, gpu::init_gpu_solver))
+    .add_systems(PostUpdate, capture_screenshots)
+    .add_systems(
+        PreUpdate,
+        apply_physics_rate.run_if(resource_changed::<FluidConfig>),
+    )
+    // Physics runs at the fixed `physics_rate`, independent of the frame rate, except in
+    // capture mode, which steps once per recorded frame below.
+    .add_systems(
+        FixedUpdate,
+        update_physics_rayon
+            .run_if(physics_running)
+            .run_if(not(capturing)),
+    )
+    .add_systems(
+        Update,
+        (
+            // Simulation.
+            (
+                handle_input,
+                select_probe_particle,
+                track_window_focus,
+                spawn_particles.run_if(resource_changed::<RenderConfig>),
+                update_physics_rayon
+                    .run_if(physics_running)
+                    .run_if(capturing),
+                interpolate_fixed_steps.run_if(not(capturing)),
+                adapt_particle_count,
+                refine_particles,
+                update_divergence,
+                update_separation,
+            )
+                .chain(),
+            // Diagnostics and exports.
+            (
+                emit_flow_events,
+                record_energy,
+                record_probe_history,
+                (detect_settling, log_settling, run_parameter_sweep).chain(),
+                update_sim_stats,
+                dump_config,
+                export_velocity_field,
+                save_load_scene,
+            )
+                .chain(),
+            // Rendering and overlays.
+            (
+                auto_frame_camera,
+                sync_rendering,
+                update_occupancy_heatmap,
+                update_trails,
+                draw_segments,
+                draw_gravity_regions,
+                draw_obstacles,
+                draw_domain_walls,
+                draw_comparison_divider,
+                draw_particle_forces,
+                draw_surface_contour,
+                draw_energy_plot,
+                draw_probe_history,
+                draw_cell_histogram,
+                draw_color_legend,
+                update_diagnostics_label,
+                update_initial_condition_label.run_if(resource_changed::<SelectedInitialCondition>),
+                report_profile,
+            )
+                .chain(),
         )
-        .run();
+            .chain(),
+    );
+    if let Some((other, other_config)) = comparison {
+        app.insert_resource(other)
+            .insert_resource(other_config)
+            .add_plugins(ResourceInspectorPlugin::<ComparisonConfig>::default());
+    }
+    app.run();
 }
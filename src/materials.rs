@@ -0,0 +1,62 @@
+use bevy::{
+    mesh::MeshVertexBufferLayoutRef,
+    prelude::*,
+    render::render_resource::{
+        AsBindGroup, BlendComponent, BlendFactor, BlendOperation, BlendState,
+        RenderPipelineDescriptor, SpecializedMeshPipelineError,
+    },
+    shader::ShaderRef,
+    sprite_render::{AlphaMode2d, Material2d, Material2dKey},
+};
+
+const ADDITIVE_PARTICLE_SHADER: &str = "shaders/additive_particle.wgsl";
+
+/// Particle material that adds its color to the framebuffer, so overlapping particles glow.
+/// The per-particle color is packed into each entity's `MeshTag` (see [`pack_color`]).
+#[derive(Asset, TypePath, AsBindGroup, Clone)]
+pub struct AdditiveParticleMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    pub texture: Handle<Image>,
+}
+
+impl Material2d for AdditiveParticleMaterial {
+    fn vertex_shader() -> ShaderRef {
+        ADDITIVE_PARTICLE_SHADER.into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        ADDITIVE_PARTICLE_SHADER.into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode2d {
+        AlphaMode2d::Blend
+    }
+
+    fn specialize(
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayoutRef,
+        _key: Material2dKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let additive = BlendState {
+            color: BlendComponent {
+                src_factor: BlendFactor::SrcAlpha,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
+            },
+            alpha: BlendComponent::OVER,
+        };
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            for target in fragment.targets.iter_mut().flatten() {
+                target.blend = Some(additive);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Packs a color as linear RGBA8 (red in the lowest byte), matching `unpack4x8unorm` in the shader.
+#[inline(always)]
+pub fn pack_color(color: Color) -> u32 {
+    u32::from_le_bytes(LinearRgba::from(color).to_u8_array())
+}
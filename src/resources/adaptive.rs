@@ -0,0 +1,26 @@
+use bevy::prelude::*;
+
+/// Fewest particles the adaptive controller will leave active.
+pub const MIN_ACTIVE_PARTICLES: usize = 200;
+
+/// Weight of the newest sample in the step-time moving average.
+const STEP_TIME_SMOOTHING: f32 = 0.1;
+
+/// State of the controller that scales the active particle count to fit
+/// `FluidConfig::target_frame_ms`.
+#[derive(Resource, Default)]
+pub struct AdaptiveParticleCount {
+    /// Exponential moving average of the physics step time, in milliseconds.
+    pub smoothed_step_ms: f32,
+}
+
+impl AdaptiveParticleCount {
+    /// Folds a measured physics step time into the moving average.
+    pub fn record(&mut self, step_ms: f32) {
+        self.smoothed_step_ms = if self.smoothed_step_ms == 0.0 {
+            step_ms
+        } else {
+            self.smoothed_step_ms + (step_ms - self.smoothed_step_ms) * STEP_TIME_SMOOTHING
+        };
+    }
+}
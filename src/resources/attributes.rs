@@ -0,0 +1,103 @@
+use std::any::Any;
+
+/// A per-particle column of `ParticleAttributes`, type-erased so columns of different
+/// value types can be kept, moved and reset together.
+trait AttributeColumn: Any + Send + Sync {
+    /// Copies the value of slot `from` to slot `to`.
+    fn copy_slot(&mut self, from: usize, to: usize);
+    /// Resets slot `slot` to the default value.
+    fn reset_slot(&mut self, slot: usize);
+    /// Resets every slot to the default value.
+    fn reset_all(&mut self);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Clone + Default + Send + Sync + 'static> AttributeColumn for Vec<T> {
+    fn copy_slot(&mut self, from: usize, to: usize) {
+        self[to] = self[from].clone();
+    }
+
+    fn reset_slot(&mut self, slot: usize) {
+        self[slot] = T::default();
+    }
+
+    fn reset_all(&mut self) {
+        self.fill(T::default());
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Named per-particle values of any `Clone + Default` type (e.g. a `u32` group id or an
+/// `f32` color index), one per slot like the built-in particle buffers.
+/// `FluidSimulation` carries them along when particles change slots; spawned particles and
+/// resets start from `T::default()`. They are not saved with scenes.
+#[derive(Default)]
+pub struct ParticleAttributes {
+    columns: Vec<(String, Box<dyn AttributeColumn>)>,
+}
+
+impl ParticleAttributes {
+    /// Adds a column `name` of `capacity` default values, replacing any column of that name,
+    /// and returns it for filling in.
+    pub fn insert<T: Clone + Default + Send + Sync + 'static>(
+        &mut self,
+        name: &str,
+        capacity: usize,
+    ) -> &mut [T] {
+        self.columns.retain(|(existing, _)| existing != name);
+        self.columns
+            .push((name.to_owned(), Box::new(vec![T::default(); capacity])));
+        let (_, column) = self.columns.last_mut().expect("column was just pushed");
+        column
+            .as_any_mut()
+            .downcast_mut::<Vec<T>>()
+            .expect("column was just created with this type")
+    }
+
+    /// The values of column `name`, or `None` if there is none or it holds another type.
+    pub fn get<T: 'static>(&self, name: &str) -> Option<&[T]> {
+        self.columns
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .and_then(|(_, column)| column.as_any().downcast_ref::<Vec<T>>())
+            .map(Vec::as_slice)
+    }
+
+    /// Mutable form of `get`.
+    pub fn get_mut<T: 'static>(&mut self, name: &str) -> Option<&mut [T]> {
+        self.columns
+            .iter_mut()
+            .find(|(existing, _)| existing == name)
+            .and_then(|(_, column)| column.as_any_mut().downcast_mut::<Vec<T>>())
+            .map(Vec::as_mut_slice)
+    }
+
+    /// Copies every column's value from slot `from` to slot `to`.
+    pub(crate) fn copy_slot(&mut self, from: usize, to: usize) {
+        for (_, column) in &mut self.columns {
+            column.copy_slot(from, to);
+        }
+    }
+
+    /// Resets every column's value in `slot`, for a newly spawned particle.
+    pub(crate) fn reset_slot(&mut self, slot: usize) {
+        for (_, column) in &mut self.columns {
+            column.reset_slot(slot);
+        }
+    }
+
+    /// Resets every column to default values, for a new layout.
+    pub(crate) fn reset_all(&mut self) {
+        for (_, column) in &mut self.columns {
+            column.reset_all();
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use bevy::prelude::*;
+
+use super::{config::FluidConfig, simulation::FluidSimulation};
+
+/// Second simulation instance run beside the main one with `--compare`: the main instance
+/// takes the left half of the domain and this one the right half, from the same seed.
+/// It is stepped, reset and stirred together with the main instance.
+#[derive(Resource)]
+pub struct ComparisonSim(pub FluidSimulation);
+
+/// Parameters of the comparison instance, edited in its own inspector panel.
+#[derive(Reflect, Resource)]
+#[reflect(Resource)]
+pub struct ComparisonConfig(pub FluidConfig);
+
+/// Pairs the particles of two instances by index and stores each pair's distance in both
+/// instances' `separations`. Positions are measured from each domain's `min` corner, so
+/// two instances reset from the same seed start at zero. Particles without a counterpart
+/// get zero. Returns the mean over the pairs.
+pub fn update_separations(a: &mut FluidSimulation, b: &mut FluidSimulation) -> f32 {
+    let n = a
+        .active_count
+        .min(b.active_count)
+        .min(a.positions.len())
+        .min(b.positions.len());
+    let (origin_a, origin_b) = (a.domain.min, b.domain.min);
+    let mut sum = 0.0;
+    for i in 0..n {
+        let separation = (a.positions[i] - origin_a).distance(b.positions[i] - origin_b);
+        a.separations[i] = separation;
+        b.separations[i] = separation;
+        sum += separation;
+    }
+    a.separations[n..].fill(0.0);
+    b.separations[n..].fill(0.0);
+    if n == 0 { 0.0 } else { sum / n as f32 }
+}
+
+/// Splits `domain` into equal left and right halves.
+pub fn split_domain(domain: Rect) -> [Rect; 2] {
+    let mid = domain.center().x;
+    [
+        Rect::new(domain.min.x, domain.min.y, mid, domain.max.y),
+        Rect::new(mid, domain.min.y, domain.max.x, domain.max.y),
+    ]
+}
@@ -1,7 +1,11 @@
 use bevy::prelude::*;
 
+use crate::geometry::GravityRegion;
+
 /// Number of particles in the simulation.
 pub const PARTICLE_COUNT: usize = 4_000;
+/// Preallocated particle slots; the slots beyond `PARTICLE_COUNT` hold spawned particles.
+pub const PARTICLE_CAPACITY: usize = PARTICLE_COUNT * 2;
 /// Visual radius of each particle.
 pub const PARTICLE_RADIUS: f32 = 2.0;
 /// Width of the simulation boundary.
@@ -9,9 +13,352 @@ pub const BOUNDARY_WIDTH: f32 = 1280.0;
 /// Height of the simulation boundary.
 pub const BOUNDARY_HEIGHT: f32 = 720.0;
 
+/// Pixels per metre used by the SI-like preset.
+pub const SI_WORLD_SCALE: f32 = 100.0;
+
+/// Densities below this are raised to it wherever they divide, however low `density_floor` is.
+pub const MIN_DENSITY: f32 = 0.0001;
+
+/// Particle texture used when none is configured or the configured file is missing.
+pub const DEFAULT_PARTICLE_TEXTURE: &str = "circle.png";
+
+/// How gravity enters the integration.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GravityMode {
+    /// Added as a body force `gravity * density`, later divided by density.
+    #[default]
+    Force,
+    /// Applied directly as `vel += gravity * dt`, bypassing the density division.
+    /// More robust for low-density surface particles.
+    Acceleration,
+}
+
+/// When the per-step velocity damping is applied relative to the position update.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IntegrationOrder {
+    /// `vel += a*dt; vel *= damping; pos += vel*dt`: the position moves with the damped velocity.
+    #[default]
+    DampBeforePosition,
+    /// `vel += a*dt; pos += vel*dt; vel *= damping`: damping only affects the next step.
+    DampAfterPosition,
+}
+
+/// Equation of state turning a particle's density into pressure.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq)]
+pub enum PressureEos {
+    /// `p = pressure_multiplier * (ρ - ρ0)`, soft and linear in the density error.
+    #[default]
+    Linear,
+    /// Tait equation `p = b * ((ρ / ρ0)^gamma - 1)`, the standard weakly-compressible law.
+    /// With `gamma` around 7 it stiffens sharply above rest density, so compression stays
+    /// small, but stable runs need a smaller time step. Ignores `pressure_multiplier`.
+    Tait { gamma: f32, b: f32 },
+}
+
+impl PressureEos {
+    /// Pressure at `density` for rest density `target_density` and stiffness `k`
+    /// (the `Linear` gas constant).
+    #[inline(always)]
+    pub fn pressure(self, density: f32, target_density: f32, k: f32) -> f32 {
+        match self {
+            Self::Linear => k * (density - target_density),
+            Self::Tait { gamma, b } if target_density > 0.0 => {
+                b * ((density / target_density).powf(gamma) - 1.0)
+            }
+            Self::Tait { .. } => 0.0,
+        }
+    }
+
+    /// Sound speed `c = √(dp/dρ)` at rest density: `√k` for `Linear`, `√(b γ / ρ0)` for
+    /// `Tait`. Pressure waves cross a smoothing radius in `h / c`, which bounds the time step.
+    pub fn sound_speed(self, target_density: f32, k: f32) -> f32 {
+        let stiffness = match self {
+            Self::Linear => k,
+            Self::Tait { gamma, b } if target_density > 0.0 => b * gamma / target_density,
+            Self::Tait { .. } => 0.0,
+        };
+        stiffness.max(0.0).sqrt()
+    }
+}
+
+/// How the viscosity coefficient responds to the local shear rate.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ViscosityModel {
+    /// Constant `viscosity_strength`.
+    #[default]
+    Newtonian,
+    /// Power law `μ_eff = μ * shear_rate^(n - 1)`: `n < 1` shear-thins (ketchup),
+    /// `n > 1` shear-thickens (oobleck). The factor is clamped for stability.
+    PowerLaw { n: f32 },
+}
+
+/// Kernel formulation of the viscous force, independent of the density and pressure kernels.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ViscosityKernel {
+    /// Müller et al. viscosity kernel Laplacian `∇²W`, scaled by `viscosity_strength`
+    /// and the `viscosity_model`.
+    #[default]
+    Laplacian,
+    /// Monaghan artificial viscosity: damps only approaching pairs, with strength `alpha`
+    /// (typically 0.01–0.5) and the sound speed of the `pressure_eos`. Ignores
+    /// `viscosity_strength` and `viscosity_model`.
+    Artificial { alpha: f32 },
+}
+
+/// Where the density and neighbor-force passes run.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Rayon-parallel passes on the CPU.
+    #[default]
+    Cpu,
+    /// WGSL compute shaders on the render device, for the neighbor forces of a share of the
+    /// particles. Falls back to the CPU without a compute capable device and for power-law
+    /// or artificial viscosity, kernel tables or the Shepard correction.
+    Gpu,
+}
+
+/// Adaptive particle refinement: particles split in two where the flow shears strongly and
+/// neighboring pairs merge where it is calm, conserving mass and momentum. The smoothing
+/// radius stays fixed, so this is a basic form of the technique.
+#[derive(Reflect, Clone, Copy, Debug)]
+pub struct Refinement {
+    pub enabled: bool,
+    /// Shear rate `|∇v|` (1/time) above which a particle splits into two half-mass particles.
+    pub split_threshold: f32,
+    /// Shear rate below which two nearby particles merge into one.
+    pub merge_threshold: f32,
+    /// Lightest particle a split may produce, as a multiple of `particle_mass`.
+    pub min_mass: f32,
+    /// Heaviest particle a merge may produce, as a multiple of `particle_mass`.
+    pub max_mass: f32,
+    /// Only pairs closer than this fraction of `smoothing_radius` merge.
+    pub merge_distance: f32,
+}
+
+impl Default for Refinement {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            split_threshold: 6.0,
+            merge_threshold: 0.6,
+            min_mass: 0.25,
+            max_mass: 2.0,
+            merge_distance: 0.5,
+        }
+    }
+}
+
+/// What happens to a particle that crosses a domain wall.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BoundaryBehavior {
+    /// Clamped back inside and bounced with `boundary_damping` and `boundary_friction`.
+    #[default]
+    Reflect,
+    /// Re-enters through the opposite wall. Neighbors are not searched across the seam, so
+    /// density dips slightly there; make both opposite walls periodic for a closed loop.
+    Periodic,
+    /// Leaves the simulation: the particle is removed at the start of the next step,
+    /// freeing its slot for spawns.
+    Open,
+}
+
+impl BoundaryBehavior {
+    /// The behavior after this one when cycling a wall with the '1'-'4' keys.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Reflect => Self::Open,
+            Self::Open => Self::Periodic,
+            Self::Periodic => Self::Reflect,
+        }
+    }
+
+    /// Name shown in the HUD.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Reflect => "closed",
+            Self::Open => "open",
+            Self::Periodic => "periodic",
+        }
+    }
+}
+
+/// Per-wall `BoundaryBehavior` of the domain rectangle.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DomainBoundaries {
+    pub left: BoundaryBehavior,
+    pub right: BoundaryBehavior,
+    pub bottom: BoundaryBehavior,
+    pub top: BoundaryBehavior,
+}
+
+impl DomainBoundaries {
+    /// Whether any wall removes the particles crossing it.
+    pub fn any_open(&self) -> bool {
+        [self.left, self.right, self.bottom, self.top].contains(&BoundaryBehavior::Open)
+    }
+}
+
+/// Velocity field given to particles placed by a reset.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq)]
+pub enum InitialVelocity {
+    /// Particles start at rest.
+    #[default]
+    Still,
+    /// A seeded, divergence-free random field: the curl of a random stream function built
+    /// from large-scale Fourier modes, scaled so the mean of `½|v|²` equals `energy`.
+    Turbulent { energy: f32 },
+}
+
+/// Scripted interaction point that circles `center`, used instead of the cursor when enabled.
+#[derive(Reflect, Clone, Copy, Debug)]
+pub struct AutoStir {
+    pub enabled: bool,
+    /// Center of the stirring circle.
+    pub center: Vec2,
+    /// Radius of the stirring circle.
+    pub radius: f32,
+    /// Angular speed in radians per unit of simulated time (negative stirs clockwise).
+    pub rate: f32,
+    /// 1.0 attracts, -1.0 repels, like the mouse buttons.
+    pub factor: f32,
+}
+
+impl Default for AutoStir {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            center: Vec2::ZERO,
+            radius: 200.0,
+            rate: 2.0,
+            factor: 1.0,
+        }
+    }
+}
+
+/// Rotational velocity impulse applied with 'Q': particles within `radius` of the cursor (or
+/// the domain center) gain the solid-body velocity `ω × r`, perpendicular to their offset
+/// `r` from it. Unlike `AutoStir` it changes velocities directly, once per key press.
+#[derive(Reflect, Clone, Copy, Debug)]
+pub struct SwirlImpulse {
+    /// Angular velocity `ω` added, in radians per unit of simulated time (negative spins
+    /// clockwise).
+    pub angular_velocity: f32,
+    /// Radius of the disc that is spun up.
+    pub radius: f32,
+}
+
+impl Default for SwirlImpulse {
+    fn default() -> Self {
+        Self {
+            angular_velocity: 0.5,
+            radius: 150.0,
+        }
+    }
+}
+
+/// One-shot explosion fired with 'K': active particles within `radius` of the cursor (or
+/// the domain center) are pushed radially outward, by `speed` at the center fading
+/// linearly to nothing at `radius`. Unlike the repelling mouse force it changes
+/// velocities directly, once per key press.
+#[derive(Reflect, Clone, Copy, Debug)]
+pub struct BlastImpulse {
+    /// Outward speed added at the blast center.
+    pub speed: f32,
+    /// Radius of the disc that is pushed.
+    pub radius: f32,
+}
+
+impl Default for BlastImpulse {
+    fn default() -> Self {
+        Self {
+            speed: 300.0,
+            radius: 120.0,
+        }
+    }
+}
+
+/// `FluidConfig` field stepped by a `ParameterSweep`.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SweepParameter {
+    #[default]
+    SmoothingRadius,
+    ViscosityStrength,
+    PressureMultiplier,
+    ParticleMass,
+}
+
+impl SweepParameter {
+    /// The field's name, used as the CSV column header.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::SmoothingRadius => "smoothing_radius",
+            Self::ViscosityStrength => "viscosity_strength",
+            Self::PressureMultiplier => "pressure_multiplier",
+            Self::ParticleMass => "particle_mass",
+        }
+    }
+
+    pub fn get(self, config: &FluidConfig) -> f32 {
+        match self {
+            Self::SmoothingRadius => config.smoothing_radius,
+            Self::ViscosityStrength => config.viscosity_strength,
+            Self::PressureMultiplier => config.pressure_multiplier,
+            Self::ParticleMass => config.particle_mass,
+        }
+    }
+
+    pub fn set(self, config: &mut FluidConfig, value: f32) {
+        match self {
+            Self::SmoothingRadius => config.smoothing_radius = value,
+            Self::ViscosityStrength => config.viscosity_strength = value,
+            Self::PressureMultiplier => config.pressure_multiplier = value,
+            Self::ParticleMass => config.particle_mass = value,
+        }
+    }
+}
+
+/// Values a sweep started with 'M' steps `parameter` through: `start`, `start + step`, ...
+/// up to `end`. Each value runs from a fresh reset until the fluid settles.
+#[derive(Reflect, Clone, Copy, Debug)]
+pub struct ParameterSweep {
+    pub parameter: SweepParameter,
+    pub start: f32,
+    pub end: f32,
+    /// Increment between values; zero or negative runs `start` alone.
+    pub step: f32,
+}
+
+impl ParameterSweep {
+    /// Every value of the sweep, in order.
+    pub fn values(&self) -> Vec<f32> {
+        if self.step <= 0.0 {
+            return vec![self.start];
+        }
+        // Tolerates rounding so `end` itself is included when the steps land on it.
+        let count = ((self.end - self.start) / self.step + 1e-4)
+            .floor()
+            .max(0.0) as usize
+            + 1;
+        (0..count)
+            .map(|k| self.start + self.step * k as f32)
+            .collect()
+    }
+}
+
+impl Default for ParameterSweep {
+    fn default() -> Self {
+        Self {
+            parameter: SweepParameter::SmoothingRadius,
+            start: 15.0,
+            end: 30.0,
+            step: 2.5,
+        }
+    }
+}
+
 /// Configuration parameters for the fluid simulation.
 /// This resource is automatically exposed to the Bevy Inspector for runtime tweaking.
-#[derive(Reflect, Resource)]
+#[derive(Reflect, Resource, Clone)]
 #[reflect(Resource)]
 pub struct FluidConfig {
     /// Smoothing radius for SPH kernels (h).
@@ -20,20 +367,238 @@ pub struct FluidConfig {
     pub particle_mass: f32,
     /// Target rest density for pressure calculations.
     pub target_density: f32,
-    /// Gas constant multiplier for pressure forces (K).
+    /// On every reset, set `target_density` to the mean density of the new layout before it
+    /// moves, so the fluid starts at rest instead of expanding or collapsing.
+    pub auto_calibrate_target_density: bool,
+    /// Gas constant multiplier for pressure forces (K), the square of the `Linear` sound speed.
     pub pressure_multiplier: f32,
+    /// Density-to-pressure law.
+    pub pressure_eos: PressureEos,
+    /// Clamp pressure to be non-negative so under-dense regions never attract neighbors.
+    /// Recommended for free-surface flows, where negative pressure clumps the surface.
+    pub clamp_negative_pressure: bool,
+    /// Constant added to every particle's pressure after clamping, a baseline repulsion that
+    /// keeps particles spaced out where the pressure alone would let them clump, such as
+    /// under strong `surface_cohesion` (0.0 = none).
+    pub background_pressure: f32,
+    /// Density/force evaluations per step. Above 1, later passes use positions predicted from
+    /// the previous pass, which limits density overshoot at extra cost (1 = single pass).
+    pub pressure_iterations: usize,
+    /// Evaluate the first density/force pass at `pos + vel * dt` instead of `pos`, so pressure
+    /// reacts to compression before it happens. Integration still starts from `pos`.
+    pub predict_positions: bool,
+    /// Minimum pair distance as a fraction of `smoothing_radius`; closer pairs use the kernel
+    /// value at this distance, which softens the clumping instability (0.0 = disabled).
+    pub min_dist_fraction: f32,
+    /// Normalize each summed density by `Σ_j (m_j / ρ_j) W_ij` (a Shepard filter), which
+    /// restores part of the deficit near free surfaces where the kernel support is truncated.
+    /// Costs a second neighbor pass; the GPU backend falls back to the CPU with it.
+    pub shepard_correction: bool,
+    /// Fraction of `target_density` that densities are raised to wherever they divide: in the
+    /// pressure and viscosity terms and the force-to-acceleration step. Nearly isolated
+    /// particles otherwise have tiny densities whose `1/ρ²` pressure terms blow up
+    /// (0.0 = only the `MIN_DENSITY` guard).
+    pub density_floor: f32,
+    /// Particles whose density falls below this fraction of `target_density` are treated as free surface.
+    pub surface_threshold: f32,
+    /// Strength of the inward cohesive force applied to free-surface particles.
+    pub surface_cohesion: f32,
     /// Viscosity coefficient for viscous forces (μ).
     pub viscosity_strength: f32,
-    /// Gravitational acceleration vector.
-    pub gravity: Vec2,
+    /// Constant or shear-rate dependent (non-Newtonian) viscosity.
+    pub viscosity_model: ViscosityModel,
+    /// Kernel the viscous force is computed with.
+    pub viscosity_kernel: ViscosityKernel,
+    /// Strength of the gravitational acceleration.
+    pub gravity_magnitude: f32,
+    /// Tilt of gravity in degrees, counter-clockwise from straight down; rotated with
+    /// ',' / '.'.
+    pub gravity_angle: f32,
+    /// When false, neither `gravity` nor gravity regions apply; toggled with 'Z'.
+    pub gravity_enabled: bool,
+    /// Whether gravity is applied as a body force or directly as acceleration.
+    pub gravity_mode: GravityMode,
     /// Time step scaling factor.
     pub time_scale: f32,
+    /// Playback speed: physics steps run per physics tick, accumulated across ticks when
+    /// fractional. Unlike `time_scale` this leaves each step unchanged (1.0 = one step).
+    pub steps_per_frame: f32,
+    /// Physics ticks per second of real time. The physics runs in `FixedUpdate`, so the
+    /// simulation advances at the same rate however fast frames render; rendering
+    /// interpolates between steps.
+    pub physics_rate: f32,
+    /// Pause the physics while the window is unfocused, so it does not drain the battery in
+    /// the background. Steps resume where they stopped, without catching up.
+    pub pause_on_unfocus: bool,
+    /// Velocity multiplier applied every step for numerical stability (1.0 = no damping).
+    pub linear_damping: f32,
+    /// Whether damping is applied before or after the position update.
+    pub integration_order: IntegrationOrder,
+    /// When the fastest particle exceeds this speed, every velocity is scaled down by the
+    /// same factor at the end of the step, preserving the relative flow field.
+    pub global_speed_cap: Option<f32>,
     /// Velocity damping factor for boundary collisions (0.0 = no bounce, 1.0 = perfect bounce).
     pub boundary_damping: f32,
+    /// Fraction of the tangential velocity removed on each wall, segment or obstacle contact
+    /// (0.0 = frictionless sliding, 1.0 = no-slip).
+    pub boundary_friction: f32,
+    /// Maximum passes over the segment walls, obstacles and domain walls per particle and
+    /// step. A push out of one can land inside another, so wedged particles need several
+    /// passes; the loop stops early once a pass finds no overlap or wrap. 0 behaves like 1.
+    pub collision_iterations: u32,
+    /// Behavior of each domain wall; e.g. an `Open` top lets a fountain spray out.
+    pub boundaries: DomainBoundaries,
     /// Radius of mouse interaction influence.
     pub mouse_radius: f32,
     /// Strength of mouse interaction forces.
     pub mouse_strength: f32,
+    /// Radius of the cluster dropped with 'B'.
+    pub blob_radius: f32,
+    /// Number of particles in a dropped cluster.
+    pub blob_particle_count: usize,
+    /// Hands-free stirring with the mouse interaction force.
+    pub auto_stir: AutoStir,
+    /// Vortex spun up with 'Q'.
+    pub swirl: SwirlImpulse,
+    /// Explosion fired with 'K'.
+    pub blast: BlastImpulse,
+    /// Split and merge particles by local shear rate.
+    pub refinement: Refinement,
+    /// Total kinetic energy below which the fluid counts as settled (0.0 = detection disabled).
+    pub settle_energy: f32,
+    /// Seconds the kinetic energy must stay below `settle_energy` before `SimulationSettled`
+    /// is sent.
+    pub settle_duration: f32,
+    /// Parameter study run with 'M'; needs `settle_energy` to detect settling.
+    pub sweep: ParameterSweep,
+    /// Physics step budget in milliseconds. When positive, the active particle count is
+    /// adjusted gradually to keep the measured step time under it (0.0 = disabled).
+    pub target_frame_ms: f32,
+    /// When the `target_frame_ms` controller changes the active particle count, rescale
+    /// `particle_mass` so the total active mass, and with it the volume the fluid settles
+    /// to, stays put: `mass ∝ 1 / count` with uniform mass factors.
+    pub auto_mass_scaling: bool,
+    /// Run the physics passes on a single thread so results are bit-identical across runs.
+    /// Costs the full multi-core speedup; use for reproducible experiments only.
+    pub deterministic: bool,
+    /// Samples per kernel lookup table in the CPU passes; poly6 and the spiky gradient are
+    /// then interpolated instead of evaluated per pair (0 = exact); 256 samples are within
+    /// 1e-4 of the peak. The SIMD density pass always uses the exact kernels, and the GPU
+    /// backend falls back to the CPU with tables.
+    pub kernel_table_size: usize,
+    /// Device running the neighbor force pass alongside the CPU; densities, grid building,
+    /// integration and collisions always run on the CPU.
+    pub backend: Backend,
+    /// Cross-check a few particles' grid neighbors against a brute-force scan each step
+    /// and warn on mismatches. Expensive; for debugging the neighbor search only.
+    pub verify_neighbors: bool,
+    /// Cell rings scanned around each particle's grid cell. Above 1 the grid is rebuilt with
+    /// cells of `smoothing_radius / grid_scan_radius`: less wasted scan area, more memory.
+    pub grid_scan_radius: usize,
+    /// Neighbor grid cell size while `grid_scan_radius` is 1. Smaller cells hold fewer
+    /// particles but widen the scan to cover `smoothing_radius`; a warning is logged below
+    /// the smoothing radius or above twice it.
+    pub grid_cell_size: f32,
+    /// Distance the neighbor grid extends past each side of the domain, rounded up to whole
+    /// cells; must cover particles pushed or spawned up to `max speed * dt` outside a wall.
+    pub grid_margin: f32,
+    /// Distance particles may move before the neighbor grid is rebuilt (0.0 = rebuild every
+    /// step). Neighbor queries scan enough extra rings to cover `smoothing_radius +
+    /// neighbor_skin`, so reusing the grid finds every neighbor: only the candidate order
+    /// changes. Pays off with several steps per frame when a skin within `grid_cell_size -
+    /// smoothing_radius` (or about half a cell) adds no ring.
+    pub neighbor_skin: f32,
+    /// Seed for random resets; change it and press 'R' for a new reproducible layout.
+    pub seed: u64,
+    /// Velocity field particles start with after a reset.
+    pub initial_velocity: InitialVelocity,
+    /// Random displacement of grid-reset particles, as a fraction of the lattice spacing in
+    /// each axis, seeded by `seed`. Breaks the lattice symmetry that makes perfectly regular
+    /// layouts collapse in artificial patterns (0.0 = exact lattice).
+    pub init_jitter: f32,
+    /// Interpolate sprite positions between the previous and current physics step.
+    pub interpolate_rendering: bool,
+    /// Color free-surface particles distinctly for debugging.
+    pub highlight_surface: bool,
+}
+
+impl FluidConfig {
+    /// Gravitational acceleration vector of `gravity_magnitude` tilted by `gravity_angle`.
+    pub fn gravity(&self) -> Vec2 {
+        let (sin, cos) = self.gravity_angle.to_radians().sin_cos();
+        self.gravity_magnitude * Vec2::new(sin, -cos)
+    }
+
+    /// Gravity outside every gravity region: `gravity`, or zero while gravity is toggled off.
+    pub fn current_gravity(&self) -> Vec2 {
+        if self.gravity_enabled {
+            self.gravity()
+        } else {
+            Vec2::ZERO
+        }
+    }
+
+    /// Smallest density divided by: `density_floor` of `target_density`, at least `MIN_DENSITY`.
+    pub fn min_density(&self) -> f32 {
+        (self.density_floor * self.target_density).max(MIN_DENSITY)
+    }
+
+    /// The subset of `regions` in effect: none while gravity is toggled off.
+    pub fn active_gravity_regions<'a>(&self, regions: &'a [GravityRegion]) -> &'a [GravityRegion] {
+        if self.gravity_enabled { regions } else { &[] }
+    }
+
+    /// The default configuration re-expressed in SI-like units (metres, g = 9.81 m/s²),
+    /// for a domain of `BOUNDARY_WIDTH x BOUNDARY_HEIGHT` pixels at `SI_WORLD_SCALE` px/m.
+    ///
+    /// Lengths are scaled by 1/100 and time by √9.81, so the motion matches the default preset.
+    pub fn si_like() -> Self {
+        let time = 9.81f32.sqrt();
+        let length = 1.0 / SI_WORLD_SCALE;
+        let default = Self::default();
+        Self {
+            smoothing_radius: default.smoothing_radius * length,
+            grid_cell_size: default.grid_cell_size * length,
+            grid_margin: default.grid_margin * length,
+            neighbor_skin: default.neighbor_skin * length,
+            target_density: default.target_density / (length * length),
+            pressure_multiplier: default.pressure_multiplier * (length * time).powi(2),
+            background_pressure: default.background_pressure * time * time,
+            // The viscosity Laplacian's `1/h⁶` leaves an extra length in the coefficient.
+            viscosity_strength: default.viscosity_strength * length * time,
+            gravity_magnitude: 9.81,
+            time_scale: default.time_scale / time,
+            mouse_radius: default.mouse_radius * length,
+            mouse_strength: default.mouse_strength * time * time / length,
+            blob_radius: default.blob_radius * length,
+            auto_stir: AutoStir {
+                radius: default.auto_stir.radius * length,
+                rate: default.auto_stir.rate * time,
+                ..default.auto_stir
+            },
+            swirl: SwirlImpulse {
+                angular_velocity: default.swirl.angular_velocity * time,
+                radius: default.swirl.radius * length,
+            },
+            blast: BlastImpulse {
+                speed: default.blast.speed * length * time,
+                radius: default.blast.radius * length,
+            },
+            refinement: Refinement {
+                split_threshold: default.refinement.split_threshold * time,
+                merge_threshold: default.refinement.merge_threshold * time,
+                ..default.refinement
+            },
+            // The default sweep steps `smoothing_radius`, a length.
+            sweep: ParameterSweep {
+                start: default.sweep.start * length,
+                end: default.sweep.end * length,
+                step: default.sweep.step * length,
+                ..default.sweep
+            },
+            ..default
+        }
+    }
 }
 
 impl Default for FluidConfig {
@@ -42,13 +607,235 @@ impl Default for FluidConfig {
             smoothing_radius: 20.0,
             particle_mass: 1.0,
             target_density: 0.01,
-            pressure_multiplier: 200.0,
+            auto_calibrate_target_density: false,
+            pressure_multiplier: 40000.0,
+            pressure_eos: PressureEos::Linear,
+            clamp_negative_pressure: false,
+            background_pressure: 0.0,
+            pressure_iterations: 1,
+            predict_positions: false,
+            min_dist_fraction: 0.0,
+            shepard_correction: false,
+            density_floor: 0.0,
+            surface_threshold: 0.0,
+            surface_cohesion: 0.0,
             viscosity_strength: 50.0,
-            gravity: Vec2::new(0.0, -100.0),
+            viscosity_model: ViscosityModel::Newtonian,
+            viscosity_kernel: ViscosityKernel::Laplacian,
+            gravity_magnitude: 100.0,
+            gravity_angle: 0.0,
+            gravity_enabled: true,
+            gravity_mode: GravityMode::Force,
             time_scale: 10.0,
+            steps_per_frame: 1.0,
+            physics_rate: 60.0,
+            pause_on_unfocus: true,
+            linear_damping: 0.99,
+            integration_order: IntegrationOrder::DampBeforePosition,
+            global_speed_cap: None,
             boundary_damping: 0.4,
+            boundary_friction: 0.0,
+            collision_iterations: 4,
+            boundaries: DomainBoundaries::default(),
             mouse_radius: 200.0,
             mouse_strength: 10.0,
+            blob_radius: 60.0,
+            blob_particle_count: 300,
+            auto_stir: AutoStir::default(),
+            swirl: SwirlImpulse::default(),
+            blast: BlastImpulse::default(),
+            refinement: Refinement::default(),
+            settle_energy: 0.0,
+            settle_duration: 1.0,
+            sweep: ParameterSweep::default(),
+            target_frame_ms: 0.0,
+            auto_mass_scaling: false,
+            deterministic: false,
+            kernel_table_size: 0,
+            backend: Backend::Cpu,
+            verify_neighbors: false,
+            grid_scan_radius: 1,
+            grid_cell_size: 25.0,
+            grid_margin: 50.0,
+            neighbor_skin: 0.0,
+            seed: 0,
+            initial_velocity: InitialVelocity::Still,
+            init_jitter: 0.0,
+            interpolate_rendering: true,
+            highlight_surface: false,
+        }
+    }
+}
+
+/// Quantity particles are colored by.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorField {
+    /// Blue for the slowest particles, fading to white for the fastest.
+    #[default]
+    Velocity,
+    /// A fixed color per tag from `FluidSimulation::ids`, for watching regions mix.
+    Id,
+    /// Velocity divergence: red where the flow compresses, blue where it expands,
+    /// white where it is incompressible.
+    Divergence,
+    /// Distance from the same-index particle of the `--compare` instance: white where the
+    /// two parameterizations agree, magenta where they have diverged.
+    Separation,
+}
+
+/// Values mapped to the two ends of the color field's colormap.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorRange {
+    /// Rescale every frame to the active particles' min and max (symmetric about zero for
+    /// `Divergence`, so white stays incompressible).
+    #[default]
+    Auto,
+    /// A fixed range, so colors are comparable across frames and runs. Widened to be
+    /// symmetric about zero for `Divergence`; a `min` above `max` is swapped with a warning.
+    Fixed { min: f32, max: f32 },
+}
+
+/// How resets tag particles with ids for `ColorField::Id`.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IdPattern {
+    /// Left half 0, right half 1.
+    #[default]
+    Halves,
+    /// Alternating 0/1 tiles, `tiles` per side of the domain.
+    Checkerboard { tiles: u32 },
+}
+
+impl IdPattern {
+    /// Id of a particle starting at `pos` inside `domain`.
+    pub fn id_at(self, pos: Vec2, domain: Rect) -> u32 {
+        match self {
+            Self::Halves => u32::from(pos.x >= domain.center().x),
+            Self::Checkerboard { tiles } => {
+                let tiles = tiles.max(1) as f32;
+                let tile = ((pos - domain.min) / domain.size() * tiles).floor();
+                (tile.x + tile.y).rem_euclid(2.0) as u32
+            }
+        }
+    }
+}
+
+/// Visual configuration for particle rendering.
+/// Changing the texture or blend mode respawns the particle entities.
+#[derive(Reflect, Resource)]
+#[reflect(Resource)]
+pub struct RenderConfig {
+    /// Particle texture path, relative to the `assets` directory.
+    pub particle_texture: String,
+    /// Blend particles additively so dense regions glow instead of occluding each other.
+    pub additive_blend: bool,
+    /// Pixels per simulation unit used when converting particle positions to screen space.
+    pub world_scale: f32,
+    /// Rotate each particle so the texture's +X axis faces its velocity, for directional
+    /// textures. Nearly stationary particles keep their last rotation.
+    pub rotate_to_velocity: bool,
+    /// Quantity particles are colored by.
+    pub color_field: ColorField,
+    /// Draw a scale bar for `color_field` with its endpoint values.
+    pub show_color_legend: bool,
+    /// Range of `color_field` spanned by the colormap. `ColorField::Id` always uses its ids.
+    pub color_range: ColorRange,
+    /// Id layout assigned on the next reset (`R`/`G`) for `ColorField::Id`.
+    pub id_pattern: IdPattern,
+    /// Simulated time over which newly placed particles fade in (0.0 = appear instantly).
+    pub fade_in_time: f32,
+    /// Hide particles outside the camera's view and skip syncing them. Physics still runs
+    /// for every particle; culled sprites are refreshed as soon as they re-enter the view.
+    pub cull_offscreen: bool,
+    /// Most particle sprites shown per instance (0 = unlimited). Above it only every k-th
+    /// particle is drawn, a coarse level of detail for very large particle counts.
+    pub max_sprites: usize,
+    /// Draw the free surface as a marching-squares contour of the density field.
+    pub show_surface_contour: bool,
+    /// Spacing of the density sampling grid used for the contour (smaller = finer, slower).
+    pub contour_resolution: f32,
+    /// Spacing in pixels of the grid the velocity field is exported on (`X`).
+    pub velocity_field_resolution: f32,
+    /// Cells per side of the grid the energy spectrum is computed on (`Shift+X`), rounded up
+    /// to a power of two.
+    pub spectrum_resolution: usize,
+    /// Draw a histogram of grid cell occupancy and outline the densest cell.
+    pub show_cell_histogram: bool,
+    /// Tint the domain behind the particles by grid cell occupancy, one texel per cell, so
+    /// crowded cells stand out at a glance. Toggle with 'H'.
+    pub show_occupancy_heatmap: bool,
+    /// Opacity of the fullest cell in the occupancy heatmap; emptier cells fade towards clear.
+    pub occupancy_heatmap_opacity: f32,
+    /// Splat particle positions into a slowly fading texture behind the particles, a long
+    /// exposure in which moving fluid leaves glowing streaks. Toggle with 'U'.
+    pub show_trails: bool,
+    /// Size in pixels of one trail texel (smaller = sharper streaks, more texels to fade).
+    pub trail_resolution: f32,
+    /// Rate per second at which trails fade; each frame keeps `exp(-trail_fade_rate * dt)`
+    /// of the accumulated exposure (0.0 = never fade).
+    pub trail_fade_rate: f32,
+    /// Exposure each particle adds to its texel per frame; the texel's opacity is
+    /// `1 - exp(-exposure)`, so overlapping particles saturate instead of clipping.
+    pub trail_intensity: f32,
+    /// Color of the trails at full exposure.
+    pub trail_color: Color,
+    /// Outline the domain walls, so the container is visible before particles reach it.
+    /// Toggle with 'D'.
+    pub show_domain_box: bool,
+    /// Move and zoom the camera to keep the fluid in view: centered on its center of mass,
+    /// with its bounding box inside the window. Toggle with 'A'; turning it off returns to
+    /// the whole-domain view.
+    pub auto_frame: bool,
+    /// Exponential smoothing rate of auto-framing, in 1/s; higher follows more tightly.
+    pub auto_frame_rate: f32,
+    /// Extra room around the fluid's bounding box when auto-framing, as a fraction of its size.
+    pub auto_frame_margin: f32,
+    /// Frames between captures while burst screenshots are on (Shift+P).
+    pub screenshot_interval: u32,
+    /// Capture mode (Ctrl+P): every frame runs exactly `capture_substeps` physics steps,
+    /// ignoring `steps_per_frame`, and is saved as a screenshot, for temporally dense
+    /// recordings that are smooth when slowed down.
+    pub capture: bool,
+    /// Physics steps between two recorded frames in capture mode.
+    pub capture_substeps: u32,
+    /// Frame rate the captured frames will be played back at, independent of the physics
+    /// rate; only used to report the resulting playback speed when capture starts.
+    pub capture_fps: f32,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            particle_texture: DEFAULT_PARTICLE_TEXTURE.to_owned(),
+            additive_blend: false,
+            world_scale: 1.0,
+            rotate_to_velocity: false,
+            color_field: ColorField::Velocity,
+            show_color_legend: false,
+            color_range: ColorRange::Auto,
+            id_pattern: IdPattern::Halves,
+            fade_in_time: 0.2,
+            cull_offscreen: true,
+            max_sprites: 0,
+            show_surface_contour: false,
+            contour_resolution: 8.0,
+            velocity_field_resolution: 20.0,
+            spectrum_resolution: 64,
+            show_cell_histogram: false,
+            show_occupancy_heatmap: false,
+            occupancy_heatmap_opacity: 0.5,
+            show_trails: false,
+            trail_resolution: 2.0,
+            trail_fade_rate: 1.5,
+            trail_intensity: 0.15,
+            trail_color: Color::srgb(0.4, 0.8, 1.0),
+            show_domain_box: true,
+            auto_frame: false,
+            auto_frame_rate: 3.0,
+            auto_frame_margin: 0.2,
+            screenshot_interval: 10,
+            capture: false,
+            capture_substeps: 4,
+            capture_fps: 60.0,
         }
     }
 }
@@ -0,0 +1,47 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+/// Number of frames of energy history kept for the plot.
+pub const ENERGY_HISTORY_LEN: usize = 300;
+
+/// Rolling history of total kinetic and potential energy, one sample per frame.
+/// Drawn as a scrolling plot to spot instability (growth) or over-damping (decay).
+#[derive(Resource)]
+pub struct EnergyHistory {
+    pub kinetic: VecDeque<f32>,
+    pub potential: VecDeque<f32>,
+    /// Whether the plot overlay is drawn. Samples are recorded either way.
+    pub visible: bool,
+}
+
+impl Default for EnergyHistory {
+    fn default() -> Self {
+        Self {
+            kinetic: VecDeque::with_capacity(ENERGY_HISTORY_LEN),
+            potential: VecDeque::with_capacity(ENERGY_HISTORY_LEN),
+            visible: false,
+        }
+    }
+}
+
+impl EnergyHistory {
+    /// Appends a sample, dropping the oldest one once the buffer is full.
+    pub fn push(&mut self, kinetic: f32, potential: f32) {
+        if self.kinetic.len() == ENERGY_HISTORY_LEN {
+            self.kinetic.pop_front();
+            self.potential.pop_front();
+        }
+        self.kinetic.push_back(kinetic);
+        self.potential.push_back(potential);
+    }
+
+    /// Largest sample of either series, used to normalize the plot.
+    pub fn max_value(&self) -> f32 {
+        self.kinetic
+            .iter()
+            .chain(&self.potential)
+            .copied()
+            .fold(0.0, f32::max)
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+/// Simulated seconds of history the inflow and outflow rates are averaged over.
+const FLOW_RATE_WINDOW: f32 = 2.0;
+
+/// What created a particle.
+#[derive(Reflect, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ParticleSource {
+    /// A blob dropped with 'B'; counts as inflow.
+    Blob,
+    /// Adaptive refinement split a parent particle.
+    Split,
+}
+
+impl ParticleSource {
+    pub const ALL: [Self; 2] = [Self::Blob, Self::Split];
+
+    /// Whether particles from this source add fluid to the system, rather than
+    /// redistributing existing mass.
+    pub fn is_inflow(self) -> bool {
+        self == Self::Blob
+    }
+}
+
+/// What removed a particle.
+#[derive(Reflect, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ParticleSink {
+    /// Left through an open wall; counts as outflow.
+    LeftWall,
+    RightWall,
+    BottomWall,
+    TopWall,
+    /// Adaptive refinement merged it into a neighbor.
+    Merge,
+}
+
+impl ParticleSink {
+    pub const ALL: [Self; 5] = [
+        Self::LeftWall,
+        Self::RightWall,
+        Self::BottomWall,
+        Self::TopWall,
+        Self::Merge,
+    ];
+
+    /// Whether particles removed here take fluid out of the system.
+    pub fn is_outflow(self) -> bool {
+        self != Self::Merge
+    }
+}
+
+/// One creation, removal or reset recorded by a simulation instance during a frame, with the
+/// simulated time it happened at.
+#[derive(Clone, Copy, Debug)]
+pub enum FlowEvent {
+    Spawned {
+        source: ParticleSource,
+        time: f32,
+    },
+    /// With the particle's age at removal.
+    Removed {
+        sink: ParticleSink,
+        time: f32,
+        age: f32,
+    },
+    /// The layout was replaced, so the counts restart.
+    Reset,
+}
+
+/// Sent for each particle created. Only the primary simulation is reported.
+#[derive(Message, Clone, Copy, Debug)]
+pub struct ParticleSpawned {
+    pub source: ParticleSource,
+    /// Simulated time of the creation, in seconds since the last reset.
+    pub time: f32,
+}
+
+/// Sent for each particle removed. Only the primary simulation is reported.
+#[derive(Message, Clone, Copy, Debug)]
+pub struct ParticleRemoved {
+    pub sink: ParticleSink,
+    /// Simulated time of the removal, in seconds since the last reset.
+    pub time: f32,
+    /// Simulated time the particle spent in the domain since it was placed.
+    pub residence_time: f32,
+}
+
+/// Running particle throughput, fed by `ParticleSpawned` and `ParticleRemoved`.
+/// Read-only in the inspector; cleared by resets.
+#[derive(Reflect, Resource, Default)]
+#[reflect(Resource)]
+pub struct FlowMeter {
+    /// Particles created per source, in `ParticleSource::ALL` order.
+    pub spawned: [usize; 2],
+    /// Particles removed per sink, in `ParticleSink::ALL` order.
+    pub removed: [usize; 5],
+    /// Inflow particles per simulated second over the last `FLOW_RATE_WINDOW`.
+    pub inflow_rate: f32,
+    /// Outflow particles per simulated second over the last `FLOW_RATE_WINDOW`.
+    pub outflow_rate: f32,
+    /// Mean residence time of the particles that flowed out, in simulated seconds.
+    pub mean_residence_time: f32,
+    residence_sum: f32,
+    /// Times of recent inflow (`true`) and outflow (`false`) events.
+    #[reflect(ignore)]
+    recent: VecDeque<(f32, bool)>,
+}
+
+impl FlowMeter {
+    pub fn record_spawn(&mut self, event: &ParticleSpawned) {
+        let index = ParticleSource::ALL.iter().position(|&s| s == event.source);
+        self.spawned[index.unwrap_or(0)] += 1;
+        if event.source.is_inflow() {
+            self.recent.push_back((event.time, true));
+        }
+    }
+
+    pub fn record_removal(&mut self, event: &ParticleRemoved) {
+        let index = ParticleSink::ALL.iter().position(|&s| s == event.sink);
+        self.removed[index.unwrap_or(0)] += 1;
+        if event.sink.is_outflow() {
+            self.recent.push_back((event.time, false));
+            self.residence_sum += event.residence_time;
+            let outflow: usize = ParticleSink::ALL
+                .iter()
+                .zip(self.removed)
+                .filter_map(|(sink, count)| sink.is_outflow().then_some(count))
+                .sum();
+            self.mean_residence_time = self.residence_sum / outflow as f32;
+        }
+    }
+
+    /// Drops events older than the rate window before `now` and refreshes the rates.
+    pub fn update_rates(&mut self, now: f32) {
+        while self
+            .recent
+            .front()
+            .is_some_and(|&(t, _)| t < now - FLOW_RATE_WINDOW)
+        {
+            self.recent.pop_front();
+        }
+        let inflow = self.recent.iter().filter(|&&(_, inflow)| inflow).count();
+        let window = now.clamp(f32::EPSILON, FLOW_RATE_WINDOW);
+        self.inflow_rate = inflow as f32 / window;
+        self.outflow_rate = (self.recent.len() - inflow) as f32 / window;
+    }
+}
@@ -0,0 +1,6 @@
+use bevy::prelude::*;
+
+/// Whether the primary window has lost focus, tracked from `WindowFocused` messages.
+/// While set, physics is paused if `FluidConfig::pause_on_unfocus` is on.
+#[derive(Resource, Default)]
+pub struct WindowUnfocused(pub bool);
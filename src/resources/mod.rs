@@ -1,5 +1,29 @@
+pub mod adaptive;
+pub mod attributes;
+pub mod comparison;
 pub mod config;
+pub mod energy;
+pub mod flow;
+pub mod focus;
+pub mod probe;
+pub mod profiler;
+pub mod scene_elements;
+pub mod settling;
+pub mod sim_stats;
 pub mod simulation;
+pub mod sweep;
 
+pub use adaptive::*;
+pub use attributes::*;
+pub use comparison::*;
 pub use config::*;
+pub use energy::*;
+pub use flow::*;
+pub use focus::*;
+pub use probe::*;
+pub use profiler::*;
+pub use scene_elements::*;
+pub use settling::*;
+pub use sim_stats::*;
 pub use simulation::*;
+pub use sweep::*;
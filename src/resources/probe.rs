@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+/// Number of frames of the selected particle's history kept for its plot.
+pub const PROBE_HISTORY_LEN: usize = 300;
+
+/// The force terms acting on one particle in the latest step, before dividing by density.
+#[derive(Reflect, Clone, Copy, Debug, Default)]
+pub struct ForceBreakdown {
+    pub pressure: Vec2,
+    pub viscosity: Vec2,
+    /// Equivalent force `ρ g` of the gravity acting on the particle, also recorded when
+    /// gravity is applied as an acceleration.
+    pub gravity: Vec2,
+    pub interaction: Vec2,
+    pub cohesion: Vec2,
+}
+
+impl ForceBreakdown {
+    /// Each term with its arrow color: pressure red, viscosity green, gravity blue,
+    /// interaction yellow and cohesion magenta.
+    pub fn terms(&self) -> [(Vec2, Color); 5] {
+        [
+            (self.pressure, Color::srgb(1.0, 0.3, 0.3)),
+            (self.viscosity, Color::srgb(0.3, 1.0, 0.4)),
+            (self.gravity, Color::srgb(0.4, 0.6, 1.0)),
+            (self.interaction, Color::srgb(1.0, 0.9, 0.2)),
+            (self.cohesion, Color::srgb(1.0, 0.4, 1.0)),
+        ]
+    }
+
+    /// Sum of all terms.
+    pub fn total(&self) -> Vec2 {
+        self.pressure + self.viscosity + self.gravity + self.interaction + self.cohesion
+    }
+}
+
+/// The selected particle's state in one frame.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProbeSample {
+    pub density: f32,
+    pub pressure: f32,
+    pub speed: f32,
+}
+
+impl ProbeSample {
+    /// Density, pressure and speed, in the order they are plotted.
+    pub fn values(&self) -> [f32; 3] {
+        [self.density, self.pressure, self.speed]
+    }
+}
+
+/// Single-particle inspector: middle-click a particle to select it, middle-click empty space
+/// to clear. The physics step records the selected particle's state and force terms, which
+/// are drawn as arrows from it. Selection follows the particle slot, so it may jump to
+/// another particle when particles are removed, merged or parked.
+#[derive(Reflect, Resource, Default)]
+#[reflect(Resource)]
+pub struct ParticleProbe {
+    /// Slot of the selected active particle.
+    pub selected: Option<usize>,
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub density: f32,
+    pub pressure: f32,
+    pub forces: ForceBreakdown,
+    /// The last `PROBE_HISTORY_LEN` frames of the selected particle, oldest first, plotted
+    /// to tell a particle that oscillates from one that settles. Cleared when the
+    /// selection changes.
+    #[reflect(ignore)]
+    pub history: VecDeque<ProbeSample>,
+}
+
+impl ParticleProbe {
+    /// Appends the current state to the history, dropping the oldest sample once full.
+    pub fn record(&mut self) {
+        if self.history.len() == PROBE_HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(ProbeSample {
+            density: self.density,
+            pressure: self.pressure,
+            speed: self.velocity.length(),
+        });
+    }
+}
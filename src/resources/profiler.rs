@@ -0,0 +1,104 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+
+/// Frames older than this are dropped from the profiler's averages.
+const PROFILE_WINDOW: Duration = Duration::from_secs(1);
+
+/// A separately timed part of each frame's simulation work.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Escaped-particle removal and the neighbor grid rebuild.
+    Grid,
+    /// Density, pressure and shear-rate passes.
+    Density,
+    /// Force passes and position prediction between pressure iterations.
+    Forces,
+    /// Integration, collisions and the speed cap.
+    Integration,
+    /// Copying particle state to the sprites.
+    RenderSync,
+}
+
+impl Phase {
+    /// Every phase, in report order.
+    pub const ALL: [Self; 5] = [
+        Self::Grid,
+        Self::Density,
+        Self::Forces,
+        Self::Integration,
+        Self::RenderSync,
+    ];
+
+    /// Name shown in the report.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Grid => "Grid rebuild",
+            Self::Density => "Density",
+            Self::Forces => "Forces",
+            Self::Integration => "Integration",
+            Self::RenderSync => "Render sync",
+        }
+    }
+}
+
+/// Milliseconds spent in each `Phase`, indexed like `Phase::ALL`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PhaseTimes(pub [f32; Phase::ALL.len()]);
+
+impl PhaseTimes {
+    /// Adds the time elapsed since `start` to `phase` and returns a new start instant.
+    pub fn lap(&mut self, phase: Phase, start: Instant) -> Instant {
+        let now = Instant::now();
+        self.0[phase as usize] += (now - start).as_secs_f32() * 1000.0;
+        now
+    }
+}
+
+/// Per-frame phase times over the last second, printed with 'F'.
+/// Recording is a few `Instant::now` calls per step; only the report costs more.
+#[derive(Resource, Default)]
+pub struct PhaseProfiler {
+    frames: VecDeque<(Instant, PhaseTimes)>,
+    current: PhaseTimes,
+}
+
+impl PhaseProfiler {
+    /// Adds `times` to the current frame.
+    pub fn record(&mut self, times: &PhaseTimes) {
+        for (total, t) in self.current.0.iter_mut().zip(times.0) {
+            *total += t;
+        }
+    }
+
+    /// Closes the current frame and drops frames that left the window.
+    pub fn end_frame(&mut self, now: Instant) {
+        self.frames
+            .push_back((now, std::mem::take(&mut self.current)));
+        while self
+            .frames
+            .front()
+            .is_some_and(|&(t, _)| now - t > PROFILE_WINDOW)
+        {
+            self.frames.pop_front();
+        }
+    }
+
+    /// Mean milliseconds per frame of each phase over the window, and the frame count.
+    pub fn averages(&self) -> (PhaseTimes, usize) {
+        let mut mean = PhaseTimes::default();
+        for (_, times) in &self.frames {
+            for (total, t) in mean.0.iter_mut().zip(times.0) {
+                *total += t;
+            }
+        }
+        let count = self.frames.len();
+        for total in &mut mean.0 {
+            *total /= count.max(1) as f32;
+        }
+        (mean, count)
+    }
+}
@@ -0,0 +1,22 @@
+use bevy::prelude::*;
+
+use crate::geometry::{FreezeRegion, GravityRegion, Obstacle, RotatingObstacle, Segment};
+
+/// Scene elements read by the physics step every frame, shared by the `--compare` instance.
+/// Kept out of `FluidSimulation` as one resource so it can be reflected and the whole scene
+/// built in the inspector. A step borrows it for its whole duration, so every particle
+/// sees the same elements even while they are being edited.
+#[derive(Reflect, Resource, Default, Clone)]
+#[reflect(Resource)]
+pub struct SceneElements {
+    /// Solid obstacles particles are pushed out of.
+    pub obstacles: Vec<Obstacle>,
+    /// Line-segment walls particles collide with.
+    pub segments: Vec<Segment>,
+    /// Paddle wheels spinning at a scripted rate, pushing the particles they hit.
+    pub rotating_obstacles: Vec<RotatingObstacle>,
+    /// Regions overriding `FluidConfig::gravity`; the first containing region wins.
+    pub gravity_regions: Vec<GravityRegion>,
+    /// Regions whose particles are held at rest; removing one releases them.
+    pub freeze_regions: Vec<FreezeRegion>,
+}
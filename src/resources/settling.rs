@@ -0,0 +1,35 @@
+use bevy::prelude::*;
+
+/// Sent once each time the fluid settles: total kinetic energy has stayed below
+/// `FluidConfig::settle_energy` for `FluidConfig::settle_duration` seconds.
+#[derive(Message, Clone, Copy, Debug)]
+pub struct SimulationSettled {
+    /// Total kinetic energy when the settling was detected.
+    pub kinetic_energy: f32,
+    /// App time of the detection, in seconds.
+    pub time: f32,
+}
+
+/// Tracks how long the fluid has been calm. `settled` stays set until the kinetic energy
+/// rises above the threshold again.
+#[derive(Resource, Default)]
+pub struct SettleDetector {
+    /// Seconds the kinetic energy has continuously been below the threshold.
+    pub calm_time: f32,
+    pub settled: bool,
+}
+
+impl SettleDetector {
+    /// Feeds one frame's kinetic energy. Returns `true` on the frame the fluid becomes settled.
+    pub fn update(&mut self, kinetic_energy: f32, threshold: f32, duration: f32, dt: f32) -> bool {
+        if kinetic_energy >= threshold {
+            self.calm_time = 0.0;
+            self.settled = false;
+            return false;
+        }
+        self.calm_time += dt;
+        let just_settled = !self.settled && self.calm_time >= duration;
+        self.settled |= just_settled;
+        just_settled
+    }
+}
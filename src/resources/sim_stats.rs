@@ -0,0 +1,39 @@
+use bevy::prelude::*;
+
+/// Read-only live diagnostics, refreshed every frame and shown in the inspector.
+/// Edits made in the inspector are overwritten on the next frame.
+#[derive(Reflect, Resource, Default)]
+#[reflect(Resource)]
+pub struct SimStats {
+    /// Particles that exist, simulated or parked.
+    pub particle_count: usize,
+    /// Particles currently simulated.
+    pub active_count: usize,
+    pub mean_density: f32,
+    pub max_density: f32,
+    pub mean_speed: f32,
+    pub max_speed: f32,
+    /// Sound speed of the `pressure_eos` at rest density, `√(dp/dρ)`: the speed the pressure
+    /// force carries waves at, which `--wave-speed` measures.
+    pub sound_speed: f32,
+    /// Courant number `(sound_speed + max_speed) * dt / smoothing_radius`: the fraction of a
+    /// smoothing radius the fastest signal crosses per step. WCSPH usually keeps it below
+    /// about 0.4; lower `time_scale` or the pressure stiffness when runs blow up near it.
+    pub cfl: f32,
+    pub kinetic_energy: f32,
+    pub potential_energy: f32,
+    pub total_energy: f32,
+    /// Sum of particle masses; changes only when particles are added or removed, not when
+    /// adaptive refinement splits or merges them.
+    pub total_mass: f32,
+    /// Sum of `m * v`; refinement conserves it.
+    pub momentum: Vec2,
+    /// Mass-weighted mean position of the active particles.
+    pub center_of_mass: Vec2,
+    /// Lower-left and upper-right corners of the box around the active particles.
+    pub bounds_min: Vec2,
+    pub bounds_max: Vec2,
+    /// Mean distance between same-index particles of the main and `--compare` instances
+    /// (`ColorField::Separation`); zero without `--compare`.
+    pub mean_separation: f32,
+}
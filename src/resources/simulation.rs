@@ -1,69 +1,852 @@
 use bevy::prelude::*;
-use rand::{rng, Rng};
+use std::{fmt, path::Path, sync::Arc};
 
-use super::config::{BOUNDARY_HEIGHT, BOUNDARY_WIDTH, PARTICLE_COUNT};
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::index};
+use rayon::prelude::*;
+
+use crate::{
+    geometry::Obstacle,
+    grid::GridView,
+    kernels::{KernelCache, poly6_kernel},
+    stats::{par_bounds, par_weighted_mean},
+};
+
+use super::ParticleAttributes;
+use super::flow::{FlowEvent, ParticleSource};
+
+use super::config::{
+    BOUNDARY_HEIGHT, BOUNDARY_WIDTH, FluidConfig, IdPattern, InitialVelocity, PARTICLE_CAPACITY,
+    PARTICLE_COUNT, PARTICLE_RADIUS,
+};
+
+/// Random candidate positions tried per particle before `reset_random` gives up.
+const MAX_PLACEMENT_ATTEMPTS: usize = 100;
+
+/// Whether a particle of `radius` at `pos` would start inside an obstacle.
+fn blocked(obstacles: &[Obstacle], pos: Vec2, radius: f32) -> bool {
+    obstacles.iter().any(|o| o.resolve(pos, radius).is_some())
+}
+
+/// Fourier modes summed into the stream function of `InitialVelocity::Turbulent`.
+const TURBULENCE_MODES: usize = 32;
+/// Turbulent mode wavelengths range from the domain's shorter side down to this fraction of it.
+const TURBULENCE_MIN_WAVELENGTH: f32 = 1.0 / 8.0;
+
+/// Id given to particles added by `spawn`, distinct from every `IdPattern` id.
+pub const SPAWNED_PARTICLE_ID: u32 = 2;
+
+/// Particle layouts a reset can produce.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InitialCondition {
+    /// Uniformly random positions from the configured seed.
+    #[default]
+    Random,
+    /// A centered square lattice.
+    Grid,
+}
+
+impl InitialCondition {
+    /// Every preset, in cycling order.
+    pub const ALL: [Self; 2] = [Self::Random, Self::Grid];
+
+    /// Returns the preset after this one, wrapping around.
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&c| c == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    /// Human-readable name shown in the HUD.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Random => "Random",
+            Self::Grid => "Grid",
+        }
+    }
+}
+
+/// Initial condition applied by the next reset.
+#[derive(Resource, Default)]
+pub struct SelectedInitialCondition(pub InitialCondition);
 
 /// Core simulation data structure containing all particle state.
 /// Uses pre-allocated vectors for performance and memory efficiency.
 #[derive(Resource)]
 pub struct FluidSimulation {
     pub positions: Vec<Vec2>,
+    /// Positions at the start of the most recent physics step, used for render interpolation.
+    pub prev_positions: Vec<Vec2>,
     pub velocities: Vec<Vec2>,
+    /// Simulated time since each particle was placed by a reset or spawn.
+    pub ages: Vec<f32>,
+    /// Passive tags from the reset layout (see `IdPattern`), carried with each particle
+    /// for mixing visualization. They have no effect on the physics.
+    pub ids: Vec<u32>,
+    /// Per-particle mass as a multiple of `FluidConfig::particle_mass`; 1.0 unless changed by
+    /// adaptive refinement.
+    pub mass_factors: Vec<f32>,
+    /// Positions predicted within a step for extra pressure iterations.
+    pub predicted_positions: Vec<Vec2>,
     pub forces: Vec<Vec2>,
     pub densities: Vec<f32>,
     pub pressures: Vec<f32>,
+    /// Per-particle viscosity multipliers from the power-law model, `shear_rate^(n - 1)`.
+    pub viscosity_factors: Vec<f32>,
+    /// Velocity divergence `∇·v`, only computed while coloring by `ColorField::Divergence`.
+    pub divergences: Vec<f32>,
+    /// Distance from the same-index particle of the other `--compare` instance, for
+    /// `ColorField::Separation`; zero without one.
+    pub separations: Vec<f32>,
+    /// Free-surface flags computed in the density pass.
+    pub is_surface: Vec<bool>,
+    /// Caller-defined per-particle values, moved with the particles like `ids`; see
+    /// `add_attribute`.
+    pub attributes: ParticleAttributes,
+    /// Axis-aligned simulation domain, in simulation units. Need not be centered on the origin.
+    pub domain: Rect,
+    /// Collision radius of each particle, in simulation units.
+    pub particle_radius: f32,
     pub grid_map: Vec<Vec<usize>>,
+    /// Cell size the domain was created with; the length unit of reset layouts.
+    pub base_cell_size: f32,
     pub grid_cell_size: f32,
+    /// Cell rings scanned by neighbor queries, enough to cover the support radius.
+    pub grid_scan_radius: usize,
     pub grid_width_cells: usize,
     pub grid_height_cells: usize,
     pub grid_offset_x: f32,
     pub grid_offset_y: f32,
+    /// Distance the grid extends past the domain on each side; see `FluidConfig::grid_margin`.
+    pub grid_margin: f32,
+    /// Particles that fell outside the grid in the latest rebuild.
+    pub grid_overflow: usize,
+    /// Position of each slot when the grid was last rebuilt, checked against
+    /// `FluidConfig::neighbor_skin`. Empty while the grid has no valid contents.
+    pub binned_positions: Vec<Vec2>,
+    /// Grid rebuilds since the simulation was created.
+    pub grid_rebuilds: usize,
+    /// Fraction of a physics step elapsed since the last step (1.0 = render the latest step).
+    pub interpolation_alpha: f32,
+    /// Fraction of a step owed by a fractional `steps_per_frame` but not run yet.
+    pub step_carry: f32,
+    /// Simulated seconds since the last reset.
+    pub elapsed: f32,
+    /// Particle creations and removals since `emit_flow_events` last drained it. Each
+    /// instance keeps its own, so stepping instances in parallel needs no shared log.
+    pub flow_log: Vec<FlowEvent>,
+    /// Particles `0..active_count` are simulated and drawn; the rest are parked.
+    pub active_count: usize,
+    /// Slots `0..live_count` hold existing particles (active or parked); the rest are free
+    /// for spawning. Every buffer is `PARTICLE_CAPACITY` long.
+    pub live_count: usize,
+    /// Kernels of the latest step, kept until its smoothing radius or table size changes.
+    kernels: Option<Arc<KernelCache>>,
+}
+
+/// Why `reset_from_csv` rejected a file.
+#[derive(Debug)]
+pub enum CsvError {
+    Io(std::io::Error),
+    /// A malformed row: its 1-based line number and the problem.
+    Row(usize, String),
+    /// The number of particle rows, which is zero or above `PARTICLE_CAPACITY`.
+    Count(usize),
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Row(line, e) => write!(f, "invalid row on line {line}: {e}"),
+            Self::Count(count) => write!(
+                f,
+                "expected 1 to {PARTICLE_CAPACITY} particle rows, found {count}"
+            ),
+        }
+    }
 }
 
 impl FluidSimulation {
-    /// Creates a new fluid simulation with pre-allocated data structures.
-    pub fn new() -> Self {
-        let max_h = 25.0;
-        let grid_w_cells = (BOUNDARY_WIDTH / max_h).ceil() as usize + 4;
-        let grid_h_cells = (BOUNDARY_HEIGHT / max_h).ceil() as usize + 4;
+    /// Creates a new fluid simulation with pre-allocated data structures,
+    /// randomly initialized from `seed`, over a `BOUNDARY_WIDTH x BOUNDARY_HEIGHT` box
+    /// centered on the origin.
+    pub fn new(seed: u64) -> Self {
+        Self::with_domain(
+            seed,
+            Rect::from_center_size(Vec2::ZERO, Vec2::new(BOUNDARY_WIDTH, BOUNDARY_HEIGHT)),
+            25.0,
+            PARTICLE_RADIUS,
+        )
+    }
 
+    /// Creates a simulation over `domain` with a spatial grid of `cell_size`
+    /// (at least the smoothing radius).
+    pub fn with_domain(seed: u64, domain: Rect, cell_size: f32, particle_radius: f32) -> Self {
         let mut sim = Self {
-            positions: Vec::with_capacity(PARTICLE_COUNT),
-            velocities: Vec::with_capacity(PARTICLE_COUNT),
-            forces: vec![Vec2::ZERO; PARTICLE_COUNT],
-            densities: vec![0.0; PARTICLE_COUNT],
-            pressures: vec![0.0; PARTICLE_COUNT],
-            grid_map: vec![Vec::with_capacity(20); grid_w_cells * grid_h_cells],
-            grid_cell_size: max_h,
-            grid_width_cells: grid_w_cells,
-            grid_height_cells: grid_h_cells,
-            grid_offset_x: BOUNDARY_WIDTH / 2.0 + max_h * 2.0,
-            grid_offset_y: BOUNDARY_HEIGHT / 2.0 + max_h * 2.0,
+            positions: Vec::with_capacity(PARTICLE_CAPACITY),
+            prev_positions: Vec::with_capacity(PARTICLE_CAPACITY),
+            velocities: Vec::with_capacity(PARTICLE_CAPACITY),
+            ages: vec![0.0; PARTICLE_CAPACITY],
+            ids: vec![0; PARTICLE_CAPACITY],
+            mass_factors: vec![1.0; PARTICLE_CAPACITY],
+            predicted_positions: vec![Vec2::ZERO; PARTICLE_CAPACITY],
+            forces: vec![Vec2::ZERO; PARTICLE_CAPACITY],
+            densities: vec![0.0; PARTICLE_CAPACITY],
+            pressures: vec![0.0; PARTICLE_CAPACITY],
+            viscosity_factors: vec![1.0; PARTICLE_CAPACITY],
+            divergences: vec![0.0; PARTICLE_CAPACITY],
+            separations: vec![0.0; PARTICLE_CAPACITY],
+            is_surface: vec![false; PARTICLE_CAPACITY],
+            attributes: ParticleAttributes::default(),
+            domain,
+            particle_radius,
+            grid_map: Vec::new(),
+            base_cell_size: cell_size,
+            grid_cell_size: 0.0,
+            grid_scan_radius: 1,
+            grid_width_cells: 0,
+            grid_height_cells: 0,
+            grid_offset_x: 0.0,
+            grid_offset_y: 0.0,
+            grid_margin: cell_size * 2.0,
+            grid_overflow: 0,
+            binned_positions: Vec::with_capacity(PARTICLE_CAPACITY),
+            grid_rebuilds: 0,
+            interpolation_alpha: 1.0,
+            step_carry: 0.0,
+            elapsed: 0.0,
+            flow_log: Vec::new(),
+            active_count: PARTICLE_COUNT,
+            live_count: PARTICLE_COUNT,
+            kernels: None,
         };
-        sim.reset_random();
+        sim.set_grid_cell_size(cell_size);
+        sim.reset_random(seed, &[]);
+        sim.assign_ids(IdPattern::default());
         sim
     }
 
+    /// Kernels for smoothing radius `h` tabulated at `table_size` samples (see
+    /// `KernelCache::new`), rebuilt only when either differs from the previous call.
+    pub fn kernels(&mut self, h: f32, table_size: usize) -> Arc<KernelCache> {
+        match &self.kernels {
+            Some(kernels) if kernels.h == h && kernels.table_size == table_size => {
+                Arc::clone(kernels)
+            }
+            _ => Arc::clone(
+                self.kernels
+                    .insert(Arc::new(KernelCache::new(h, table_size))),
+            ),
+        }
+    }
+
+    /// Rebuilds the (empty) grid for cells of `cell_size`, with `grid_margin` rounded up to
+    /// whole cells around the domain. The next step refills it; the scan radius is set by
+    /// `configure_grid`.
+    pub fn set_grid_cell_size(&mut self, cell_size: f32) {
+        let size = self.domain.size();
+        let margin_cells = (self.grid_margin / cell_size).ceil() as usize;
+        let grid_w_cells = (size.x / cell_size).ceil() as usize + 2 * margin_cells;
+        let grid_h_cells = (size.y / cell_size).ceil() as usize + 2 * margin_cells;
+        self.grid_map = vec![Vec::with_capacity(20); grid_w_cells * grid_h_cells];
+        self.binned_positions.clear();
+        self.grid_cell_size = cell_size;
+        self.grid_width_cells = grid_w_cells;
+        self.grid_height_cells = grid_h_cells;
+        let margin = margin_cells as f32 * cell_size;
+        self.grid_offset_x = -self.domain.min.x + margin;
+        self.grid_offset_y = -self.domain.min.y + margin;
+    }
+
+    /// Matches the grid to the requested scan radius. One ring uses cells of `cell_size`
+    /// (falling back to the base cell size if it is not positive); `r` rings use cells of
+    /// `smoothing_radius / r`. The effective radius is raised when needed so the scanned
+    /// block always covers the support radius. The grid is also rebuilt when `margin` changes.
+    pub fn configure_grid(
+        &mut self,
+        smoothing_radius: f32,
+        scan_radius: usize,
+        cell_size: f32,
+        margin: f32,
+    ) {
+        let scan_radius = scan_radius.max(1);
+        let cell_size = if scan_radius > 1 && smoothing_radius > 0.0 {
+            smoothing_radius / scan_radius as f32
+        } else if cell_size > 0.0 {
+            cell_size
+        } else {
+            self.base_cell_size
+        };
+        let needed = (smoothing_radius / cell_size).ceil() as usize;
+        if cell_size != self.grid_cell_size {
+            if scan_radius == 1 && cell_size < smoothing_radius {
+                warn!(
+                    "Grid cell size {cell_size} is below the smoothing radius {smoothing_radius}; \
+                     scanning {needed} cell rings per neighbor query"
+                );
+            } else if scan_radius == 1 && cell_size > smoothing_radius * 2.0 {
+                warn!(
+                    "Grid cell size {cell_size} is over twice the smoothing radius \
+                     {smoothing_radius}; most scanned candidates are out of range"
+                );
+            }
+        }
+        let margin = margin.max(0.0);
+        if cell_size != self.grid_cell_size || margin != self.grid_margin {
+            self.grid_margin = margin;
+            self.set_grid_cell_size(cell_size);
+        }
+        self.grid_scan_radius = scan_radius.max(needed);
+    }
+
+    /// Rebuilds the grid cells from the first `n` particles and returns how many lie outside
+    /// the grid. Those go to the nearest edge cell instead of another row.
+    pub fn bin_particles(&mut self, n: usize) -> usize {
+        self.grid_map.par_iter_mut().for_each(|cell| cell.clear());
+        self.binned_positions.clear();
+        self.binned_positions
+            .extend_from_slice(&self.positions[..n]);
+        self.grid_rebuilds += 1;
+        let grid_w = self.grid_width_cells;
+        let grid_h = self.grid_height_cells;
+        let mut overflow = 0;
+        for (i, pos) in self.positions[..n].iter().enumerate() {
+            let x = (pos.x + self.grid_offset_x) / self.grid_cell_size;
+            let y = (pos.y + self.grid_offset_y) / self.grid_cell_size;
+            if !(0.0..grid_w as f32).contains(&x) || !(0.0..grid_h as f32).contains(&y) {
+                overflow += 1;
+            }
+            let gx = (x as usize).min(grid_w - 1);
+            let gy = (y as usize).min(grid_h - 1);
+            self.grid_map[gy * grid_w + gx].push(i);
+        }
+        overflow
+    }
+
+    /// Whether the grid still holds the first `n` particles, none of them farther than
+    /// `skin` from where it was binned.
+    pub fn grid_within_skin(&self, n: usize, skin: f32) -> bool {
+        skin > 0.0
+            && self.binned_positions.len() == n
+            && self.binned_positions[..]
+                .par_iter()
+                .zip(&self.positions[..n])
+                .all(|(binned, pos)| binned.distance_squared(*pos) <= skin * skin)
+    }
+
+    /// Returns a borrowed view of the spatial grid for neighbor queries.
+    pub fn grid_view(&self) -> GridView<'_> {
+        GridView {
+            cells: &self.grid_map,
+            width: self.grid_width_cells,
+            height: self.grid_height_cells,
+            cell_size: self.grid_cell_size,
+            offset_x: self.grid_offset_x,
+            offset_y: self.grid_offset_y,
+            scan_radius: self.grid_scan_radius,
+        }
+    }
+
+    /// Reconstructs the SPH density at an arbitrary point, `Σ m W(|x - x_j|, h)`.
+    /// Uses the grid from the most recent step, so it is exact only for that step's positions.
+    pub fn sample_density_at(&self, pos: Vec2, config: &FluidConfig) -> f32 {
+        let h = config.smoothing_radius;
+        let h_sq = h * h;
+        let mut density = 0.0;
+        self.grid_view().for_each_candidate(pos, |j| {
+            let dist_sq = pos.distance_squared(self.positions[j]);
+            if dist_sq < h_sq {
+                density += config.particle_mass * self.mass_factors[j] * poly6_kernel(dist_sq, h);
+            }
+        });
+        density
+    }
+
+    /// Reconstructs the SPH velocity at an arbitrary point, `Σ (m / ρ_j) v_j W(|x - x_j|, h)`.
+    /// Like `sample_density_at`, it uses the most recent step's grid and densities.
+    pub fn sample_velocity_at(&self, pos: Vec2, config: &FluidConfig) -> Vec2 {
+        let h = config.smoothing_radius;
+        let h_sq = h * h;
+        let mut velocity = Vec2::ZERO;
+        self.grid_view().for_each_candidate(pos, |j| {
+            let dist_sq = pos.distance_squared(self.positions[j]);
+            if dist_sq < h_sq {
+                velocity += self.velocities[j] * config.particle_mass * self.mass_factors[j]
+                    / self.densities[j].max(config.min_density())
+                    * poly6_kernel(dist_sq, h);
+            }
+        });
+        velocity
+    }
+
+    /// Reconstructs the SPH pressure at an arbitrary point, `Σ (m / ρ_j) p_j W(|x - x_j|, h)`.
+    /// Like `sample_density_at`, it uses the most recent step's grid, densities and pressures.
+    #[cfg(test)]
+    pub fn sample_pressure_at(&self, pos: Vec2, config: &FluidConfig) -> f32 {
+        let h = config.smoothing_radius;
+        let h_sq = h * h;
+        let mut pressure = 0.0;
+        self.grid_view().for_each_candidate(pos, |j| {
+            let dist_sq = pos.distance_squared(self.positions[j]);
+            if dist_sq < h_sq {
+                pressure += self.pressures[j] * config.particle_mass * self.mass_factors[j]
+                    / self.densities[j].max(config.min_density())
+                    * poly6_kernel(dist_sq, h);
+            }
+        });
+        pressure
+    }
+
+    /// Samples `sample_velocity_at` on a regular grid over the domain with `resolution`
+    /// spacing (simulation units), returning `(point, velocity)` pairs row by row.
+    pub fn sample_velocity_field(
+        &self,
+        resolution: f32,
+        config: &FluidConfig,
+    ) -> Vec<(Vec2, Vec2)> {
+        let spacing = resolution.max(1e-3);
+        let origin = self.domain.min;
+        let cols = (self.domain.width() / spacing).floor() as usize + 1;
+        let rows = (self.domain.height() / spacing).floor() as usize + 1;
+        (0..cols * rows)
+            .into_par_iter()
+            .map(|k| {
+                let p = origin + Vec2::new((k % cols) as f32, (k / cols) as f32) * spacing;
+                (p, self.sample_velocity_at(p, config))
+            })
+            .collect()
+    }
+
+    /// Samples `sample_velocity_at` at the centers of `cols x rows` equal cells covering the
+    /// domain, row by row from its minimum corner.
+    pub fn sample_velocity_cells(
+        &self,
+        cols: usize,
+        rows: usize,
+        config: &FluidConfig,
+    ) -> Vec<Vec2> {
+        let cell = self.domain.size() / Vec2::new(cols as f32, rows as f32);
+        (0..cols * rows)
+            .into_par_iter()
+            .map(|k| {
+                let p = self.domain.min
+                    + (Vec2::new((k % cols) as f32, (k / cols) as f32) + 0.5) * cell;
+                self.sample_velocity_at(p, config)
+            })
+            .collect()
+    }
+
+    /// Mass-weighted mean position of the active particles, so split and merged particles
+    /// count by their mass factors. The domain center when no particle is active.
+    pub fn center_of_mass(&self) -> Vec2 {
+        let n = self.active_count.min(self.positions.len());
+        par_weighted_mean(&self.positions[..n], &self.mass_factors[..n])
+            .unwrap_or(self.domain.center())
+    }
+
+    /// Corners `(min, max)` of the smallest axis-aligned box around the active particles'
+    /// centers. Both are the domain center when no particle is active.
+    pub fn bounding_box(&self) -> (Vec2, Vec2) {
+        let n = self.active_count.min(self.positions.len());
+        if n == 0 {
+            let center = self.domain.center();
+            return (center, center);
+        }
+        par_bounds(&self.positions[..n])
+    }
+
+    /// Adds a per-particle attribute `name` holding `T::default()` in every slot, replacing
+    /// any attribute of that name, and returns its values (indexed like `positions`) for
+    /// filling in. Read and update it later through `attributes.get` and `get_mut`.
+    pub fn add_attribute<T: Clone + Default + Send + Sync + 'static>(
+        &mut self,
+        name: &str,
+    ) -> &mut [T] {
+        self.attributes.insert(name, PARTICLE_CAPACITY)
+    }
+
+    /// Changes how many particles are simulated. Reactivated particles resume from where
+    /// they were parked, at rest, and snap there instead of interpolating.
+    pub fn set_active_count(&mut self, count: usize) {
+        let count = count.min(self.live_count);
+        for i in self.active_count.min(count)..count {
+            self.velocities[i] = Vec2::ZERO;
+            self.prev_positions[i] = self.positions[i];
+        }
+        self.active_count = count;
+    }
+
+    /// Adds active particles at rest at `positions`, using free slots. Parked particles are
+    /// moved out of the way so the new ones are active immediately.
+    /// Returns how many were added, which is fewer than requested once capacity runs out.
+    pub fn spawn(&mut self, positions: &[Vec2]) -> usize {
+        let mut added = 0;
+        for &pos in positions {
+            let Some(slot) = self.claim_active_slot() else {
+                break;
+            };
+            self.positions[slot] = pos;
+            self.prev_positions[slot] = pos;
+            self.velocities[slot] = Vec2::ZERO;
+            self.ages[slot] = 0.0;
+            self.ids[slot] = SPAWNED_PARTICLE_ID;
+            self.mass_factors[slot] = 1.0;
+            self.attributes.reset_slot(slot);
+            added += 1;
+        }
+        added
+    }
+
+    /// Copies every persistent per-particle value from slot `from` to slot `to`.
+    /// Per-step buffers are recomputed by the next step and are not copied.
+    fn copy_particle(&mut self, from: usize, to: usize) {
+        self.positions[to] = self.positions[from];
+        self.prev_positions[to] = self.prev_positions[from];
+        self.velocities[to] = self.velocities[from];
+        self.ages[to] = self.ages[from];
+        self.ids[to] = self.ids[from];
+        self.mass_factors[to] = self.mass_factors[from];
+        self.attributes.copy_slot(from, to);
+    }
+
+    /// Makes room for one more active particle by moving the first parked particle to the
+    /// first free slot. Returns the new active slot, whose values the caller overwrites,
+    /// or `None` at capacity.
+    fn claim_active_slot(&mut self) -> Option<usize> {
+        if self.live_count >= self.positions.len() {
+            return None;
+        }
+        let (slot, free) = (self.active_count, self.live_count);
+        self.copy_particle(slot, free);
+        self.active_count += 1;
+        self.live_count += 1;
+        Some(slot)
+    }
+
+    /// Deletes active particle `i`. The last active particle takes its slot and the last
+    /// parked one takes that, so indices above `i` may change.
+    pub fn remove_active(&mut self, i: usize) {
+        let last_active = self.active_count - 1;
+        self.copy_particle(last_active, i);
+        self.copy_particle(self.live_count - 1, last_active);
+        self.active_count -= 1;
+        self.live_count -= 1;
+    }
+
+    /// Splits active particle `i` into two half-mass particles at `±offset` around it, with
+    /// its velocity, so mass, momentum and center of mass are unchanged.
+    /// Returns `false` (leaving `i` untouched) at capacity. The new particle is appended
+    /// to the active range; lower indices are unchanged.
+    pub fn split_particle(&mut self, i: usize, offset: Vec2) -> bool {
+        let Some(child) = self.claim_active_slot() else {
+            return false;
+        };
+        self.copy_particle(i, child);
+        let center = self.positions[i];
+        let mass = self.mass_factors[i] * 0.5;
+        for (slot, pos) in [(i, center - offset), (child, center + offset)] {
+            self.positions[slot] = pos;
+            self.prev_positions[slot] = pos;
+            self.mass_factors[slot] = mass;
+        }
+        self.flow_log.push(FlowEvent::Spawned {
+            source: ParticleSource::Split,
+            time: self.elapsed,
+        });
+        true
+    }
+
+    /// Folds active particle `other` into `keep` at their center of mass, with the
+    /// mass-weighted velocity, conserving mass and momentum. `other` is left in place for
+    /// the caller to `remove_active`. `keep` keeps its own `attributes`.
+    pub fn merge_into(&mut self, keep: usize, other: usize) {
+        let (m_a, m_b) = (self.mass_factors[keep], self.mass_factors[other]);
+        let mass = m_a + m_b;
+        let pos = (self.positions[keep] * m_a + self.positions[other] * m_b) / mass;
+        self.velocities[keep] = (self.velocities[keep] * m_a + self.velocities[other] * m_b) / mass;
+        self.positions[keep] = pos;
+        self.prev_positions[keep] = pos;
+        self.mass_factors[keep] = mass;
+    }
+
+    /// Spawns a circular cluster of about `count` particles on a square lattice, just below
+    /// the top-center of the domain. Returns how many particles fit in the free slots.
+    pub fn drop_blob(&mut self, radius: f32, count: usize) -> usize {
+        if count == 0 || radius <= 0.0 {
+            return 0;
+        }
+        // Lattice spacing that packs `count` points into the circle's area.
+        let spacing = radius * (std::f32::consts::PI / count as f32).sqrt();
+        let steps = (radius / spacing).ceil() as i32;
+        let center = Vec2::new(
+            self.domain.center().x,
+            self.domain.max.y - radius - self.base_cell_size,
+        );
+        let mut positions = Vec::with_capacity(count);
+        for row in -steps..=steps {
+            for col in -steps..=steps {
+                let offset = Vec2::new(col as f32, row as f32) * spacing;
+                if offset.length_squared() <= radius * radius && positions.len() < count {
+                    positions.push(center + offset);
+                }
+            }
+        }
+        let added = self.spawn(&positions);
+        let time = self.elapsed;
+        self.flow_log.extend((0..added).map(|_| FlowEvent::Spawned {
+            source: ParticleSource::Blob,
+            time,
+        }));
+        added
+    }
+
+    /// Resets the simulation to the given initial condition, with grid layouts jittered by
+    /// `jitter` (see `FluidConfig::init_jitter`).
+    /// No particle is placed inside `obstacles`; returns how many particles were placed,
+    /// which is below `PARTICLE_COUNT` when the obstacles leave too little room.
+    pub fn reset(
+        &mut self,
+        condition: InitialCondition,
+        seed: u64,
+        jitter: f32,
+        obstacles: &[Obstacle],
+    ) -> usize {
+        match condition {
+            InitialCondition::Random => self.reset_random(seed, obstacles),
+            InitialCondition::Grid => self.reset_to_grid(seed, jitter, obstacles),
+        }
+    }
+
+    /// Tags every particle with `pattern`'s id for its current position; call after a reset.
+    pub fn assign_ids(&mut self, pattern: IdPattern) {
+        let domain = self.domain;
+        for (id, &pos) in self.ids.iter_mut().zip(&self.positions) {
+            *id = pattern.id_at(pos, domain);
+        }
+    }
+
+    /// Adds the solid-body rotation `ω × r` about `center` to every active particle within
+    /// `radius` of it, `r` being the particle's offset from `center`. Returns how many
+    /// particles were spun.
+    pub fn apply_swirl(&mut self, center: Vec2, radius: f32, angular_velocity: f32) -> usize {
+        let n = self.active_count.min(self.positions.len());
+        let mut spun = 0;
+        for (vel, &pos) in self.velocities[..n].iter_mut().zip(&self.positions[..n]) {
+            let offset = pos - center;
+            if offset.length_squared() <= radius * radius {
+                *vel += angular_velocity * offset.perp();
+                spun += 1;
+            }
+        }
+        spun
+    }
+
+    /// Adds an outward velocity to every active particle within `radius` of `center`,
+    /// `speed` at the center and falling off linearly to zero at `radius`. Particles exactly
+    /// at the center get a direction drawn from `seed`. Affected particles are found through
+    /// the grid, rebuilt first since particles may have moved or spawned since it was last
+    /// built. Returns how many particles were pushed.
+    pub fn apply_blast(&mut self, center: Vec2, radius: f32, speed: f32, seed: u64) -> usize {
+        if radius <= 0.0 {
+            return 0;
+        }
+        let n = self.active_count.min(self.positions.len());
+        self.bin_particles(n);
+        let mut hits = Vec::new();
+        self.grid_view().for_each_within(center, radius, |j| {
+            if j < n && self.positions[j].distance_squared(center) < radius * radius {
+                hits.push(j);
+            }
+        });
+        let mut rng = StdRng::seed_from_u64(seed);
+        for &j in &hits {
+            let offset = self.positions[j] - center;
+            let distance = offset.length();
+            let direction = if distance > 1e-6 {
+                offset / distance
+            } else {
+                Vec2::from_angle(rng.random_range(0.0..std::f32::consts::TAU))
+            };
+            self.velocities[j] += direction * speed * (1.0 - distance / radius);
+        }
+        hits.len()
+    }
+
+    /// Sets every live particle's velocity from `velocity`; call after a reset.
+    ///
+    /// The turbulent field is `v = (∂ψ/∂y, -∂ψ/∂x)` for a stream function
+    /// `ψ = Σ a·sin(k·x + φ)` with seeded random wave vectors and phases, so it is
+    /// divergence-free by construction. Amplitudes fall off as `1/|k|²`, leaving most of the
+    /// energy in the largest eddies.
+    pub fn apply_initial_velocity(&mut self, velocity: InitialVelocity, seed: u64) {
+        let live = 0..self.live_count;
+        let InitialVelocity::Turbulent { energy } = velocity else {
+            self.velocities[live].fill(Vec2::ZERO);
+            return;
+        };
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let base = std::f32::consts::TAU / self.domain.width().min(self.domain.height());
+        let modes: Vec<(Vec2, f32, f32)> = (0..TURBULENCE_MODES)
+            .map(|_| {
+                let k = Vec2::from_angle(rng.random_range(0.0..std::f32::consts::TAU))
+                    * base
+                    * rng.random_range(1.0..1.0 / TURBULENCE_MIN_WAVELENGTH);
+                let phase = rng.random_range(0.0..std::f32::consts::TAU);
+                (k, phase, 1.0 / k.length_squared())
+            })
+            .collect();
+
+        for (vel, &pos) in self.velocities[live.clone()]
+            .iter_mut()
+            .zip(&self.positions[live.clone()])
+        {
+            *vel = modes
+                .iter()
+                .map(|&(k, phase, amplitude)| {
+                    amplitude * (k.dot(pos) + phase).cos() * Vec2::new(k.y, -k.x)
+                })
+                .sum();
+        }
+
+        let live_len = live.len().max(1) as f32;
+        let mean_energy = self.velocities[live.clone()]
+            .iter()
+            .map(|v| 0.5 * v.length_squared())
+            .sum::<f32>()
+            / live_len;
+        if mean_energy > 0.0 {
+            let scale = (energy.max(0.0) / mean_energy).sqrt();
+            for vel in &mut self.velocities[live] {
+                *vel *= scale;
+            }
+        }
+    }
+
     /// Resets the simulation with random particle positions.
-    pub fn reset_random(&mut self) {
+    /// The same seed always produces the same layout.
+    /// Positions inside `obstacles` are rejected; after `MAX_PLACEMENT_ATTEMPTS` candidates
+    /// per particle it gives up and returns how many were placed.
+    pub fn reset_random(&mut self, seed: u64, obstacles: &[Obstacle]) -> usize {
+        self.positions.clear();
+        self.velocities.clear();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let area = self.domain.inflate(-self.base_cell_size * 0.8);
+        for _ in 0..PARTICLE_COUNT * MAX_PLACEMENT_ATTEMPTS {
+            if self.positions.len() == PARTICLE_COUNT {
+                break;
+            }
+            let pos = Vec2::new(
+                rng.random_range(area.min.x..area.max.x),
+                rng.random_range(area.min.y..area.max.y),
+            );
+            if !blocked(obstacles, pos, self.particle_radius) {
+                self.positions.push(pos);
+                self.velocities.push(Vec2::ZERO);
+            }
+        }
+        self.clear_derived_state();
+        self.live_count
+    }
+
+    /// Resets the simulation to the shape of an image: `PARTICLE_COUNT` particles are placed
+    /// on its dark pixels (luma below half), with the image fitted to 90% of the domain.
+    /// When there are more dark pixels than particles a seeded random subset is used, otherwise
+    /// pixels are reused with a random offset inside each pixel.
+    /// Returns the number of dark pixels; on zero or an error the simulation is unchanged.
+    pub fn reset_from_image(
+        &mut self,
+        path: impl AsRef<Path>,
+        config: &FluidConfig,
+    ) -> Result<usize, image::ImageError> {
+        let image = image::open(path)?.to_luma8();
+        let (width, height) = image.dimensions();
+        let dark: Vec<(u32, u32)> = image
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel.0[0] < 128)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        if dark.is_empty() {
+            return Ok(0);
+        }
+
+        let pixel_size = (self.domain.width() * 0.9 / width as f32)
+            .min(self.domain.height() * 0.9 / height as f32);
+        let top_left =
+            self.domain.center() + Vec2::new(-(width as f32), height as f32) * pixel_size * 0.5;
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let picks: Vec<usize> = if dark.len() >= PARTICLE_COUNT {
+            index::sample(&mut rng, dark.len(), PARTICLE_COUNT).into_vec()
+        } else {
+            (0..PARTICLE_COUNT).map(|k| k % dark.len()).collect()
+        };
+
         self.positions.clear();
         self.velocities.clear();
-        let mut rng = rng();
-        let w = BOUNDARY_WIDTH / 2.0 - 20.0;
-        let h = BOUNDARY_HEIGHT / 2.0 - 20.0;
-        for _ in 0..PARTICLE_COUNT {
-            self.positions
-                .push(Vec2::new(rng.random_range(-w..w), rng.random_range(-h..h)));
+        for pick in picks {
+            let (x, y) = dark[pick];
+            // Image rows grow downwards, the domain's y axis upwards.
+            let jitter = Vec2::new(rng.random(), rng.random());
+            let pixel = Vec2::new(x as f32 + jitter.x, -(y as f32 + jitter.y));
+            self.positions.push(top_left + pixel * pixel_size);
             self.velocities.push(Vec2::ZERO);
         }
-        self.forces.fill(Vec2::ZERO);
-        self.densities.fill(0.0);
-        self.pressures.fill(0.0);
+        self.clear_derived_state();
+        Ok(dark.len())
+    }
+
+    /// Resets the simulation to the particles listed in a CSV file, one `x,y` or `x,y,vx,vy`
+    /// row each, all of them active. A first row that is not numeric is skipped as a header,
+    /// so files written by the velocity field export (`X`) load too; blank lines are ignored.
+    /// Returns the number of particles; on an error the simulation is unchanged.
+    pub fn reset_from_csv(&mut self, path: impl AsRef<Path>) -> Result<usize, CsvError> {
+        let text = std::fs::read_to_string(path).map_err(CsvError::Io)?;
+        let mut positions = Vec::new();
+        let mut velocities = Vec::new();
+        for (index, row) in text.lines().enumerate() {
+            let row = row.trim();
+            if row.is_empty() {
+                continue;
+            }
+            let values: Result<Vec<f32>, _> =
+                row.split(',').map(|field| field.trim().parse()).collect();
+            let values = match values {
+                Ok(values) => values,
+                Err(_) if index == 0 => continue,
+                Err(e) => return Err(CsvError::Row(index + 1, e.to_string())),
+            };
+            if values.iter().any(|v| !v.is_finite()) {
+                return Err(CsvError::Row(index + 1, "non-finite value".to_owned()));
+            }
+            match values[..] {
+                [x, y] => {
+                    positions.push(Vec2::new(x, y));
+                    velocities.push(Vec2::ZERO);
+                }
+                [x, y, vx, vy] => {
+                    positions.push(Vec2::new(x, y));
+                    velocities.push(Vec2::new(vx, vy));
+                }
+                _ => {
+                    return Err(CsvError::Row(
+                        index + 1,
+                        format!("expected 2 or 4 fields, found {}", values.len()),
+                    ));
+                }
+            }
+        }
+        if positions.is_empty() || positions.len() > PARTICLE_CAPACITY {
+            return Err(CsvError::Count(positions.len()));
+        }
+        Ok(self.load_particles(&positions, &velocities, positions.len()))
     }
 
     /// Resets the simulation with particles arranged in a grid pattern.
     /// Calculates optimal grid dimensions and spacing to fit within 70% of boundary dimensions.
-    pub fn reset_to_grid(&mut self) {
+    /// Lattice points inside `obstacles` are skipped and made up with extra rows above the
+    /// lattice while they fit in the domain; returns how many particles were placed.
+    /// Each point is displaced by up to `jitter` spacings per axis, uniformly and reproducibly
+    /// from `seed`, and kept inside the domain.
+    pub fn reset_to_grid(&mut self, seed: u64, jitter: f32, obstacles: &[Obstacle]) -> usize {
         self.positions.clear();
         self.velocities.clear();
 
@@ -72,8 +855,8 @@ impl FluidSimulation {
         let cols = grid_size;
         let rows = PARTICLE_COUNT.div_ceil(cols);
 
-        let available_width = BOUNDARY_WIDTH * 0.7;
-        let available_height = BOUNDARY_HEIGHT * 0.7;
+        let available_width = self.domain.width() * 0.7;
+        let available_height = self.domain.height() * 0.7;
 
         let spacing_x = if cols > 1 {
             available_width / (cols - 1) as f32
@@ -86,30 +869,222 @@ impl FluidSimulation {
             available_height
         };
 
-        let spacing = spacing_x.min(spacing_y).clamp(12.0, 20.0);
+        // 12..20 units at the default 25-unit grid cell.
+        let cell = self.base_cell_size;
+        let spacing = spacing_x.min(spacing_y).clamp(cell * 0.48, cell * 0.8);
         let total_width = (cols - 1) as f32 * spacing;
         let total_height = (rows - 1) as f32 * spacing;
 
-        let start_x = -total_width / 2.0;
-        let start_y = -total_height / 2.0;
+        let center = self.domain.center();
+        let start_x = center.x - total_width / 2.0;
+        let start_y = center.y - total_height / 2.0;
 
-        for row in 0..rows {
+        let top = self.domain.max.y - self.particle_radius;
+        let inside = self.domain.inflate(-self.particle_radius);
+        let max_offset = spacing * jitter.max(0.0);
+        let mut rng = StdRng::seed_from_u64(seed);
+        for row in 0.. {
+            let y = start_y + row as f32 * spacing;
+            if self.positions.len() >= PARTICLE_COUNT || y > top {
+                break;
+            }
             for col in 0..cols {
-                let particle_index = row * cols + col;
-                if particle_index >= PARTICLE_COUNT {
+                if self.positions.len() >= PARTICLE_COUNT {
                     break;
                 }
 
                 let x = start_x + col as f32 * spacing;
-                let y = start_y + row as f32 * spacing;
-
-                self.positions.push(Vec2::new(x, y));
-                self.velocities.push(Vec2::ZERO);
+                let mut pos = Vec2::new(x, y);
+                if max_offset > 0.0 {
+                    let offset = Vec2::new(
+                        rng.random_range(-max_offset..=max_offset),
+                        rng.random_range(-max_offset..=max_offset),
+                    );
+                    pos = (pos + offset).clamp(inside.min, inside.max);
+                }
+                if !blocked(obstacles, pos, self.particle_radius) {
+                    self.positions.push(pos);
+                    self.velocities.push(Vec2::ZERO);
+                }
             }
         }
 
+        self.clear_derived_state();
+        self.live_count
+    }
+
+    /// Replaces every particle with `positions`, at `velocities` (zero where missing), and
+    /// makes the first `active_count` of them active; used to restore a saved scene.
+    /// Ages, ids and mass factors are not taken from the arguments; the caller restores them.
+    /// Returns how many particles were kept, at most `PARTICLE_CAPACITY`.
+    pub fn load_particles(
+        &mut self,
+        positions: &[Vec2],
+        velocities: &[Vec2],
+        active_count: usize,
+    ) -> usize {
+        let count = positions.len().min(PARTICLE_CAPACITY);
+        self.positions.clear();
+        self.positions.extend_from_slice(&positions[..count]);
+        self.velocities.clear();
+        self.velocities
+            .extend((0..count).map(|i| velocities.get(i).copied().unwrap_or(Vec2::ZERO)));
+        self.clear_derived_state();
+        self.active_count = active_count.min(self.live_count);
+        count
+    }
+
+    /// Clears per-step buffers after a reset and snaps the render history to the new layout,
+    /// so resets teleport particles instead of interpolating across the domain.
+    /// Spawned particles are discarded; the layout's particles are all live and active again,
+    /// however many open walls drained or the frame budget parked.
+    fn clear_derived_state(&mut self) {
+        self.live_count = self.positions.len();
+        self.active_count = self.live_count;
+        self.positions
+            .resize(PARTICLE_CAPACITY, self.domain.center());
+        self.velocities.resize(PARTICLE_CAPACITY, Vec2::ZERO);
         self.forces.fill(Vec2::ZERO);
         self.densities.fill(0.0);
         self.pressures.fill(0.0);
+        self.divergences.fill(0.0);
+        self.separations.fill(0.0);
+        self.is_surface.fill(false);
+        self.ages.fill(0.0);
+        self.mass_factors.fill(1.0);
+        self.attributes.reset_all();
+        self.prev_positions.clone_from(&self.positions);
+        self.interpolation_alpha = 1.0;
+        self.elapsed = 0.0;
+        // Earlier events belong to the discarded layout.
+        self.flow_log.clear();
+        self.flow_log.push(FlowEvent::Reset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::total_momentum;
+
+    /// Total mass factor, momentum and center of mass of the active particles.
+    fn totals(sim: &FluidSimulation) -> (f32, Vec2, Vec2) {
+        let n = sim.active_count;
+        let mass: f32 = sim.mass_factors[..n].iter().sum();
+        let momentum = total_momentum(&sim.velocities[..n], &sim.mass_factors[..n], 1.0);
+        let center = par_weighted_mean(&sim.positions[..n], &sim.mass_factors[..n]).unwrap();
+        (mass, momentum, center)
+    }
+
+    fn assert_conserved(before: (f32, Vec2, Vec2), after: (f32, Vec2, Vec2)) {
+        let (mass, momentum, center) = before;
+        assert!(
+            (after.0 - mass).abs() <= mass * 1e-5,
+            "mass {mass} became {}",
+            after.0
+        );
+        assert!(
+            after.1.abs_diff_eq(momentum, momentum.length() * 1e-4),
+            "momentum {momentum} became {}",
+            after.1
+        );
+        assert!(
+            after.2.abs_diff_eq(center, 1e-2),
+            "center of mass {center} became {}",
+            after.2
+        );
+    }
+
+    /// A random layout with every particle moving, and room left for split children.
+    fn moving_sim() -> FluidSimulation {
+        let mut sim = FluidSimulation::new(7);
+        let mut rng = StdRng::seed_from_u64(7);
+        let count = sim.active_count / 2;
+        sim.set_active_count(count);
+        for vel in &mut sim.velocities[..count] {
+            *vel = Vec2::new(rng.random_range(-50.0..50.0), rng.random_range(-50.0..50.0));
+        }
+        sim
+    }
+
+    #[test]
+    fn split_conserves_mass_and_momentum() {
+        let mut sim = moving_sim();
+        let before = totals(&sim);
+        for i in (0..sim.active_count).step_by(3) {
+            assert!(sim.split_particle(i, Vec2::new(2.0, -1.0)));
+        }
+        assert_conserved(before, totals(&sim));
+    }
+
+    #[test]
+    fn merge_conserves_mass_and_momentum() {
+        let mut sim = moving_sim();
+        let before = totals(&sim);
+        let count = sim.active_count;
+        // Pairs (i, i + 1) from the top down, so removals never move an unmerged partner.
+        let pairs = count / 2;
+        for keep in (0..pairs).rev().map(|k| 2 * k) {
+            sim.merge_into(keep, keep + 1);
+            sim.remove_active(keep + 1);
+        }
+        assert_eq!(sim.active_count, count - pairs);
+        assert_conserved(before, totals(&sim));
+    }
+
+    #[test]
+    fn blast_finds_particles_moved_since_the_grid_was_built() {
+        let mut sim = FluidSimulation::new(7);
+        let n = sim.active_count;
+        sim.bin_particles(n);
+        let center = sim.domain.center();
+        for (i, pos) in sim.positions[..n].iter_mut().enumerate() {
+            *pos = center + Vec2::from_angle(i as f32) * 10.0;
+        }
+        assert_eq!(sim.apply_blast(center, 20.0, 100.0, 7), n);
+    }
+
+    #[test]
+    fn attributes_follow_their_particles() {
+        let mut sim = FluidSimulation::new(7);
+        let placed = sim.positions[..sim.live_count].to_vec();
+        sim.add_attribute::<u32>("tag");
+        let tags = sim
+            .attributes
+            .get_mut::<u32>("tag")
+            .expect("the tag attribute was just added");
+        for (slot, tag) in tags[..placed.len()].iter_mut().enumerate() {
+            *tag = slot as u32 + 1;
+        }
+
+        // Park a quarter, then remove every third active particle and spawn as many, so
+        // particles change slots.
+        sim.set_active_count(sim.active_count * 3 / 4);
+        let mut removed = 0;
+        let mut i = sim.active_count;
+        while i >= 3 {
+            i -= 3;
+            sim.remove_active(i);
+            removed += 1;
+        }
+        let spawn_at = sim.domain.center();
+        let spawned = sim.spawn(&vec![spawn_at; removed]);
+
+        let tags = sim
+            .attributes
+            .get::<u32>("tag")
+            .expect("the tag attribute was added above");
+        for (slot, (&tag, &pos)) in tags
+            .iter()
+            .zip(&sim.positions[..sim.live_count])
+            .enumerate()
+        {
+            match tag {
+                0 => assert_eq!(pos, spawn_at, "untagged particle in slot {slot}"),
+                tag => assert_eq!(pos, placed[tag as usize - 1], "wrong tag in slot {slot}"),
+            }
+        }
+        let untagged = tags[..sim.live_count].iter().filter(|&&t| t == 0).count();
+        assert_eq!(untagged, spawned);
     }
 }
@@ -0,0 +1,49 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use super::config::SweepParameter;
+
+/// Diagnostics recorded once the fluid settled at one sweep value.
+#[derive(Clone, Copy, Debug)]
+pub struct SweepRow {
+    pub value: f32,
+    /// Mean density of the active particles.
+    pub mean_density: f32,
+    /// Total kinetic energy when settling was detected.
+    pub kinetic_energy: f32,
+    /// Simulated seconds from the reset until settling was detected.
+    pub settle_time: f32,
+}
+
+/// Progress of the parameter sweep started with 'M' (see `FluidConfig::sweep`).
+#[derive(Resource, Default)]
+pub struct SweepRecorder {
+    /// Values still to run, the current one first; empty while no sweep is running.
+    pub pending: VecDeque<f32>,
+    pub rows: Vec<SweepRow>,
+    pub parameter: SweepParameter,
+    /// Value of `parameter` before the sweep, restored when it ends.
+    pub original: f32,
+}
+
+impl SweepRecorder {
+    pub fn is_running(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// The recorded rows as CSV, headed by the parameter's name.
+    pub fn to_csv(&self) -> String {
+        let mut csv = format!(
+            "{},mean_density,kinetic_energy,settle_time\n",
+            self.parameter.name()
+        );
+        for row in &self.rows {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                row.value, row.mean_density, row.kinetic_energy, row.settle_time
+            ));
+        }
+        csv
+    }
+}
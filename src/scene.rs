@@ -0,0 +1,233 @@
+use std::{any::TypeId, fmt, path::Path};
+
+use bevy::{
+    prelude::*,
+    reflect::{
+        TypeRegistry,
+        serde::{TypedReflectDeserializer, TypedReflectSerializer},
+    },
+};
+use ron::ser::PrettyConfig;
+
+use crate::{
+    geometry::{FreezeRegion, GravityRegion, Obstacle, RotatingObstacle, Segment},
+    resources::{FluidConfig, FluidSimulation, SceneElements},
+};
+
+/// Format version written to new scene files. Files of older versions still load: fields
+/// they lack keep their `SceneFile::default` values.
+/// Version 2 replaced the config's `gravity` vector with `gravity_magnitude` and
+/// `gravity_angle`; see `migrate_gravity_vector`.
+pub const SCENE_VERSION: u32 = 2;
+
+/// File saved with 'O' and loaded with 'L', relative to the working directory.
+pub const DEFAULT_SCENE_PATH: &str = "scenes/scene.ron";
+
+/// Persistent state of the live particles, one entry per particle.
+#[derive(Reflect, Default)]
+pub struct ParticleState {
+    pub positions: Vec<Vec2>,
+    pub velocities: Vec<Vec2>,
+    pub ages: Vec<f32>,
+    pub ids: Vec<u32>,
+    pub mass_factors: Vec<f32>,
+    /// The first `active_count` particles are simulated; the rest are parked.
+    pub active_count: usize,
+}
+
+/// Everything needed to restart a simulation where it was saved: the config, the domain,
+/// the particles and every scene element. Stored as RON through reflection, so new fields
+/// only need a sensible default to keep older files loading.
+#[derive(Reflect, Default)]
+pub struct SceneFile {
+    pub version: u32,
+    pub config: FluidConfig,
+    /// Simulation domain; an empty rectangle keeps the current domain on load.
+    pub domain: Rect,
+    pub particle_radius: f32,
+    pub base_cell_size: f32,
+    pub particles: ParticleState,
+    pub obstacles: Vec<Obstacle>,
+    pub segments: Vec<Segment>,
+    pub rotating_obstacles: Vec<RotatingObstacle>,
+    pub gravity_regions: Vec<GravityRegion>,
+    pub freeze_regions: Vec<FreezeRegion>,
+}
+
+/// Why a scene could not be saved or loaded.
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    /// The file is not valid RON or does not match the scene layout.
+    Format(String),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Format(e) => write!(f, "invalid scene file: {e}"),
+        }
+    }
+}
+
+impl SceneFile {
+    /// Captures the live particles of `sim` with `config` and the scene `elements`.
+    pub fn capture(sim: &FluidSimulation, config: &FluidConfig, elements: &SceneElements) -> Self {
+        let live = sim.live_count.min(sim.positions.len());
+        Self {
+            version: SCENE_VERSION,
+            config: config.clone(),
+            domain: sim.domain,
+            particle_radius: sim.particle_radius,
+            base_cell_size: sim.base_cell_size,
+            particles: ParticleState {
+                positions: sim.positions[..live].to_vec(),
+                velocities: sim.velocities[..live].to_vec(),
+                ages: sim.ages[..live].to_vec(),
+                ids: sim.ids[..live].to_vec(),
+                mass_factors: sim.mass_factors[..live].to_vec(),
+                active_count: sim.active_count.min(live),
+            },
+            obstacles: elements.obstacles.clone(),
+            segments: elements.segments.clone(),
+            rotating_obstacles: elements.rotating_obstacles.clone(),
+            gravity_regions: elements.gravity_regions.clone(),
+            freeze_regions: elements.freeze_regions.clone(),
+        }
+    }
+
+    /// Moves the scene into `sim`, rebuilding it if the domain or particle size differ, and
+    /// returns the saved config and scene elements.
+    pub fn restore(self, sim: &mut FluidSimulation) -> (FluidConfig, SceneElements) {
+        let domain = if self.domain.is_empty() {
+            sim.domain
+        } else {
+            self.domain
+        };
+        let radius = positive_or(self.particle_radius, sim.particle_radius);
+        let cell_size = positive_or(self.base_cell_size, sim.base_cell_size);
+        if domain != sim.domain || radius != sim.particle_radius || cell_size != sim.base_cell_size
+        {
+            *sim = FluidSimulation::with_domain(self.config.seed, domain, cell_size, radius);
+        }
+
+        let particles = &self.particles;
+        let count = sim.load_particles(
+            &particles.positions,
+            &particles.velocities,
+            particles.active_count,
+        );
+        for i in 0..count {
+            sim.ages[i] = particles.ages.get(i).copied().unwrap_or(0.0);
+            sim.ids[i] = particles.ids.get(i).copied().unwrap_or(0);
+            sim.mass_factors[i] = particles.mass_factors.get(i).copied().unwrap_or(1.0);
+        }
+        let elements = SceneElements {
+            obstacles: self.obstacles,
+            segments: self.segments,
+            rotating_obstacles: self.rotating_obstacles,
+            gravity_regions: self.gravity_regions,
+            freeze_regions: self.freeze_regions,
+        };
+        (self.config, elements)
+    }
+}
+
+/// Returns `value` if it is positive, otherwise `fallback`.
+fn positive_or(value: f32, fallback: f32) -> f32 {
+    if value > 0.0 { value } else { fallback }
+}
+
+/// Registry of every type reachable from `SceneFile`, for the reflection serializers.
+fn scene_registry() -> TypeRegistry {
+    let mut registry = TypeRegistry::default();
+    registry.register::<SceneFile>();
+    registry
+}
+
+/// Writes `scene` to `path` as RON, creating its parent directory if needed.
+pub fn save_scene(path: impl AsRef<Path>, scene: &SceneFile) -> Result<(), SceneError> {
+    let registry = scene_registry();
+    let serializer = TypedReflectSerializer::new(scene, &registry);
+    // Deep levels (particle vectors, obstacle fields) stay on one line each.
+    let text = ron::ser::to_string_pretty(&serializer, PrettyConfig::default().depth_limit(3))
+        .map_err(|e| SceneError::Format(e.to_string()))?;
+    if let Some(dir) = path.as_ref().parent() {
+        std::fs::create_dir_all(dir).map_err(SceneError::Io)?;
+    }
+    std::fs::write(path, text).map_err(SceneError::Io)
+}
+
+/// Reads a scene written by `save_scene`. Fields missing from the file keep their defaults,
+/// so files from older versions load; files from newer versions may not.
+pub fn load_scene(path: impl AsRef<Path>) -> Result<SceneFile, SceneError> {
+    let mut text = std::fs::read_to_string(path).map_err(SceneError::Io)?;
+    if file_version(&text) < 2 {
+        text = migrate_gravity_vector(&text).unwrap_or(text);
+    }
+    let registry = scene_registry();
+    let registration = registry
+        .get(TypeId::of::<SceneFile>())
+        .expect("SceneFile is registered");
+    let value = ron::Options::default()
+        .from_str_seed(
+            &text,
+            TypedReflectDeserializer::new(registration, &registry),
+        )
+        .map_err(|e| SceneError::Format(e.to_string()))?;
+    let mut scene = SceneFile::default();
+    scene
+        .try_apply(value.as_ref())
+        .map_err(|e| SceneError::Format(e.to_string()))?;
+    if scene.version > SCENE_VERSION {
+        warn!(
+            "Scene version {} is newer than the supported version {SCENE_VERSION}",
+            scene.version
+        );
+    }
+    Ok(scene)
+}
+
+/// Reads the `version` a scene file was written with before it is deserialized, so older
+/// layouts can be migrated first. `save_scene` writes it as the first field; a file without
+/// one predates versioning and counts as 0, like `SceneFile::default`.
+fn file_version(text: &str) -> u32 {
+    text.find("version:")
+        .and_then(|start| {
+            let value = text[start + "version:".len()..].trim_start();
+            let end = value
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(value.len());
+            value[..end].parse().ok()
+        })
+        .unwrap_or(0)
+}
+
+/// Rewrites the config's `gravity: (x: X, y: Y)` vector of a version 1 scene as the
+/// equivalent `gravity_magnitude` and `gravity_angle`, or returns `None` if `text` has no
+/// such vector. The config's gravity is the field right before `gravity_enabled`.
+fn migrate_gravity_vector(text: &str) -> Option<String> {
+    let enabled = text.find("gravity_enabled:")?;
+    let field = text[..enabled].rfind("gravity:")?;
+    let value = text[field + "gravity:".len()..enabled].trim();
+    let components = value.strip_prefix('(')?.trim_end_matches(',').trim_end();
+    let components = components.strip_suffix(')')?;
+    let mut gravity = Vec2::ZERO;
+    for component in components.split(',').filter(|c| !c.trim().is_empty()) {
+        let (name, value) = component.split_once(':')?;
+        let value: f32 = value.trim().parse().ok()?;
+        match name.trim() {
+            "x" => gravity.x = value,
+            "y" => gravity.y = value,
+            _ => return None,
+        }
+    }
+    let angle = gravity.x.atan2(-gravity.y).to_degrees();
+    Some(format!(
+        "{}gravity_magnitude: {:?}, gravity_angle: {angle:?}, {}",
+        &text[..field],
+        gravity.length(),
+        &text[enabled..]
+    ))
+}
@@ -0,0 +1,188 @@
+//! Four-lane SIMD version of the density pass, compiled with the `simd` feature.
+//! Uses SSE2 on x86_64, where it is always available, and plain arrays elsewhere.
+
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+
+use crate::{grid::GridView, kernels::poly6_kernel};
+
+const LANES: usize = 4;
+
+#[cfg(target_arch = "x86_64")]
+mod lanes {
+    use std::arch::x86_64::*;
+
+    /// Four `f32` lanes in an SSE register.
+    #[derive(Clone, Copy)]
+    pub struct F32x4(__m128);
+
+    // SAFETY (all blocks below): SSE2 is part of the x86_64 baseline.
+    impl F32x4 {
+        #[inline(always)]
+        pub fn splat(v: f32) -> Self {
+            Self(unsafe { _mm_set1_ps(v) })
+        }
+
+        #[inline(always)]
+        pub fn from_array(v: [f32; 4]) -> Self {
+            Self(unsafe { _mm_loadu_ps(v.as_ptr()) })
+        }
+
+        #[inline(always)]
+        pub fn to_array(self) -> [f32; 4] {
+            let mut out = [0.0; 4];
+            unsafe { _mm_storeu_ps(out.as_mut_ptr(), self.0) };
+            out
+        }
+
+        #[inline(always)]
+        pub fn add(self, o: Self) -> Self {
+            Self(unsafe { _mm_add_ps(self.0, o.0) })
+        }
+
+        #[inline(always)]
+        pub fn sub(self, o: Self) -> Self {
+            Self(unsafe { _mm_sub_ps(self.0, o.0) })
+        }
+
+        #[inline(always)]
+        pub fn mul(self, o: Self) -> Self {
+            Self(unsafe { _mm_mul_ps(self.0, o.0) })
+        }
+
+        #[inline(always)]
+        pub fn max(self, o: Self) -> Self {
+            Self(unsafe { _mm_max_ps(self.0, o.0) })
+        }
+
+        /// Lanes of `self` where `value < bound`, zero elsewhere.
+        #[inline(always)]
+        pub fn keep_below(self, value: Self, bound: Self) -> Self {
+            Self(unsafe { _mm_and_ps(self.0, _mm_cmplt_ps(value.0, bound.0)) })
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+mod lanes {
+    /// Four `f32` lanes, left to the compiler to vectorize.
+    #[derive(Clone, Copy)]
+    pub struct F32x4([f32; 4]);
+
+    impl F32x4 {
+        #[inline(always)]
+        pub fn splat(v: f32) -> Self {
+            Self([v; 4])
+        }
+
+        #[inline(always)]
+        pub fn from_array(v: [f32; 4]) -> Self {
+            Self(v)
+        }
+
+        #[inline(always)]
+        pub fn to_array(self) -> [f32; 4] {
+            self.0
+        }
+
+        #[inline(always)]
+        pub fn add(self, o: Self) -> Self {
+            Self(std::array::from_fn(|k| self.0[k] + o.0[k]))
+        }
+
+        #[inline(always)]
+        pub fn sub(self, o: Self) -> Self {
+            Self(std::array::from_fn(|k| self.0[k] - o.0[k]))
+        }
+
+        #[inline(always)]
+        pub fn mul(self, o: Self) -> Self {
+            Self(std::array::from_fn(|k| self.0[k] * o.0[k]))
+        }
+
+        #[inline(always)]
+        pub fn max(self, o: Self) -> Self {
+            Self(std::array::from_fn(|k| self.0[k].max(o.0[k])))
+        }
+
+        /// Lanes of `self` where `value < bound`, zero elsewhere.
+        #[inline(always)]
+        pub fn keep_below(self, value: Self, bound: Self) -> Self {
+            Self(std::array::from_fn(|k| {
+                if value.0[k] < bound.0[k] {
+                    self.0[k]
+                } else {
+                    0.0
+                }
+            }))
+        }
+    }
+}
+
+use lanes::F32x4;
+
+/// Density of particle `i`, `Σ m_j W(|x_i - x_j|, h)` over its grid neighbors, evaluating
+/// four candidates per instruction. Matches the scalar pass term by term, including the
+/// `min_dist_sq` softening of neighbor pairs; only the summation order differs, so results
+/// agree to float rounding (see `simd_density_matches_scalar`).
+#[inline(always)]
+pub fn density(
+    grid: &GridView,
+    positions: &[Vec2],
+    mass_factors: &[f32],
+    i: usize,
+    h: f32,
+    min_dist_sq: f32,
+    particle_mass: f32,
+) -> f32 {
+    let pos = positions[i];
+    let h2 = h * h;
+    let h_sq = F32x4::splat(h2);
+    let coeff = F32x4::splat(4.0 / (PI * h.powi(8)));
+    let min_sq = F32x4::splat(min_dist_sq);
+    let px = F32x4::splat(pos.x);
+    let py = F32x4::splat(pos.y);
+
+    let mut self_term = 0.0;
+    let mut sum = F32x4::splat(0.0);
+    let (gx, gy) = grid.cell_coords(pos);
+    let r = grid.scan_radius as isize;
+    for dy in -r..=r {
+        for dx in -r..=r {
+            let cx = (gx as isize + dx) as usize;
+            let cy = (gy as isize + dy) as usize;
+            if cx >= grid.width || cy >= grid.height {
+                continue;
+            }
+            let Some(cell) = grid.cells.get(cy * grid.width + cx) else {
+                continue;
+            };
+            for chunk in cell.chunks(LANES) {
+                // Unused and self lanes are parked at infinity, beyond the support radius.
+                let mut x = [f32::INFINITY; LANES];
+                let mut y = [f32::INFINITY; LANES];
+                let mut m = [0.0; LANES];
+                for (k, &j) in chunk.iter().enumerate() {
+                    if j == i {
+                        // The self term stays unsoftened at r = 0, as in the scalar pass.
+                        self_term = particle_mass * mass_factors[i] * poly6_kernel(0.0, h);
+                    } else {
+                        x[k] = positions[j].x;
+                        y[k] = positions[j].y;
+                        m[k] = particle_mass * mass_factors[j];
+                    }
+                }
+                let ox = F32x4::from_array(x).sub(px);
+                let oy = F32x4::from_array(y).sub(py);
+                let dist_sq = ox.mul(ox).add(oy.mul(oy));
+                let diff = h_sq.sub(dist_sq.max(min_sq));
+                let w = coeff.mul(diff).mul(diff).mul(diff);
+                let term = F32x4::from_array(m).mul(w);
+                sum = sum.add(term.keep_below(dist_sq, h_sq));
+            }
+        }
+    }
+    let [a, b, c, d] = sum.to_array();
+    self_term + (a + b) + (c + d)
+}
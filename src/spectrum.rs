@@ -0,0 +1,204 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use rayon::prelude::*;
+
+/// In-place radix-2 FFT of the complex sequence `re + i im`, whose length must be a power
+/// of two. Unnormalized, with the `e^{-i 2π k n / N}` sign convention.
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let half = len / 2;
+        let angle = -TAU / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..half {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + half);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Transposes a row-major `cols x rows` grid into a row-major `rows x cols` one.
+fn transpose(values: &[f32], cols: usize, rows: usize) -> Vec<f32> {
+    (0..cols * rows)
+        .map(|k| values[(k % rows) * cols + k / rows])
+        .collect()
+}
+
+/// In-place 2D FFT of a row-major `cols x rows` complex grid: every row, then every column.
+fn fft_2d(re: &mut Vec<f32>, im: &mut Vec<f32>, cols: usize, rows: usize) {
+    for (line_len, count) in [(cols, rows), (rows, cols)] {
+        re.par_chunks_mut(line_len)
+            .zip(im.par_chunks_mut(line_len))
+            .for_each(|(re, im)| fft(re, im));
+        *re = transpose(re, line_len, count);
+        *im = transpose(im, line_len, count);
+    }
+}
+
+/// Signed frequency index of FFT bin `i` out of `n`: `0..n/2` then `-n/2..0`.
+fn signed_index(i: usize, n: usize) -> f32 {
+    if i < n / 2 {
+        i as f32
+    } else {
+        i as f32 - n as f32
+    }
+}
+
+/// Kinetic energy spectrum of a velocity field sampled on a row-major `cols x rows` grid of
+/// cells covering `size` (both counts powers of two), treated as periodic.
+/// Returns `(k, E(k))` shells of width `2π / size.max_element()`, where `E(k)` sums
+/// `|û|² / 2` over the wavevectors nearest `k` and `û` is normalized so the shells add up to
+/// the mean of `|u|² / 2` (Parseval).
+pub fn energy_spectrum(
+    velocities: &[Vec2],
+    cols: usize,
+    rows: usize,
+    size: Vec2,
+) -> Vec<(f32, f32)> {
+    assert!(cols.is_power_of_two() && rows.is_power_of_two());
+    assert_eq!(velocities.len(), cols * rows);
+    let n = cols * rows;
+    let mut u_re: Vec<f32> = velocities.iter().map(|v| v.x).collect();
+    let mut v_re: Vec<f32> = velocities.iter().map(|v| v.y).collect();
+    let (mut u_im, mut v_im) = (vec![0.0; n], vec![0.0; n]);
+    fft_2d(&mut u_re, &mut u_im, cols, rows);
+    fft_2d(&mut v_re, &mut v_im, cols, rows);
+
+    // The transforms come back transposed twice, so they are row-major `cols x rows` again.
+    let dk = TAU / size.max_element();
+    let norm = 1.0 / (n as f32 * n as f32);
+    let mut shells: Vec<f32> = Vec::new();
+    for row in 0..rows {
+        let ky = TAU * signed_index(row, rows) / size.y;
+        for col in 0..cols {
+            let kx = TAU * signed_index(col, cols) / size.x;
+            let shell = (Vec2::new(kx, ky).length() / dk).round() as usize;
+            if shell >= shells.len() {
+                shells.resize(shell + 1, 0.0);
+            }
+            let k = row * cols + col;
+            shells[shell] += 0.5
+                * norm
+                * (u_re[k] * u_re[k] + u_im[k] * u_im[k] + v_re[k] * v_re[k] + v_im[k] * v_im[k]);
+        }
+    }
+    shells
+        .into_iter()
+        .enumerate()
+        .map(|(shell, energy)| (shell as f32 * dk, energy))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    /// Direct O(n²) DFT with the same sign convention as `fft`, computed in `f64`.
+    fn dft(re: &[f32], im: &[f32]) -> (Vec<f32>, Vec<f32>) {
+        let n = re.len();
+        (0..n)
+            .map(|k| {
+                (0..n).fold((0.0f64, 0.0f64), |(sum_re, sum_im), j| {
+                    let angle = -std::f64::consts::TAU * (k * j) as f64 / n as f64;
+                    let (sin, cos) = angle.sin_cos();
+                    let (x_re, x_im) = (re[j] as f64, im[j] as f64);
+                    (
+                        sum_re + x_re * cos - x_im * sin,
+                        sum_im + x_re * sin + x_im * cos,
+                    )
+                })
+            })
+            .map(|(sum_re, sum_im)| (sum_re as f32, sum_im as f32))
+            .unzip()
+    }
+
+    #[test]
+    fn fft_matches_direct_transform() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for n in [1, 2, 8, 64] {
+            let re: Vec<f32> = (0..n).map(|_| rng.random_range(-1.0..1.0)).collect();
+            let im: Vec<f32> = (0..n).map(|_| rng.random_range(-1.0..1.0)).collect();
+            let (expected_re, expected_im) = dft(&re, &im);
+            let (mut got_re, mut got_im) = (re, im);
+            fft(&mut got_re, &mut got_im);
+            for k in 0..n {
+                let error = Vec2::new(got_re[k] - expected_re[k], got_im[k] - expected_im[k]);
+                assert!(error.length() < 1e-4, "bin {k} of {n} is off by {error}");
+            }
+        }
+    }
+
+    #[test]
+    fn cosine_transforms_to_two_bins() {
+        const N: usize = 16;
+        const MODE: usize = 3;
+        let mut re: Vec<f32> = (0..N)
+            .map(|j| (TAU * (MODE * j) as f32 / N as f32).cos())
+            .collect();
+        let mut im = vec![0.0; N];
+        fft(&mut re, &mut im);
+        for k in 0..N {
+            let expected = if k == MODE || k == N - MODE {
+                N as f32 / 2.0
+            } else {
+                0.0
+            };
+            assert!(
+                (re[k] - expected).abs() < 1e-4 && im[k].abs() < 1e-4,
+                "bin {k} is {} + {}i, expected {expected}",
+                re[k],
+                im[k]
+            );
+        }
+    }
+
+    #[test]
+    fn shear_wave_spectrum_peaks_in_its_shell() {
+        const CELLS: usize = 32;
+        const MODE: usize = 5;
+        let size = Vec2::splat(100.0);
+        let velocities: Vec<Vec2> = (0..CELLS * CELLS)
+            .map(|k| {
+                let x = (k % CELLS) as f32 / CELLS as f32;
+                Vec2::new(0.0, (TAU * MODE as f32 * x).sin())
+            })
+            .collect();
+        let spectrum = energy_spectrum(&velocities, CELLS, CELLS, size);
+        let peak = spectrum
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.1.total_cmp(&b.1))
+            .map_or(0, |(shell, _)| shell);
+        assert_eq!(peak, MODE);
+
+        // `sin²` averages to 1/2, so the mean of `|u|² / 2` is 1/4.
+        let total: f32 = spectrum.iter().map(|(_, energy)| energy).sum();
+        assert!((total - 0.25).abs() < 1e-4, "shells add up to {total}");
+        let (k, _) = spectrum[MODE];
+        assert!((k - TAU * MODE as f32 / size.x).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,287 @@
+use bevy::prelude::*;
+use rayon::prelude::*;
+
+/// Returns the minimum and maximum of a slice using a parallel reduction.
+/// An empty slice yields `(0.0, 0.0)`.
+pub fn par_min_max(values: &[f32]) -> (f32, f32) {
+    if values.is_empty() {
+        return (0.0, 0.0);
+    }
+    values
+        .par_iter()
+        .fold(
+            || (f32::INFINITY, f32::NEG_INFINITY),
+            |(lo, hi), &v| (lo.min(v), hi.max(v)),
+        )
+        .reduce(
+            || (f32::INFINITY, f32::NEG_INFINITY),
+            |(lo_a, hi_a), (lo_b, hi_b)| (lo_a.min(lo_b), hi_a.max(hi_b)),
+        )
+}
+
+/// Chunk length for ordered parallel sums. Fixed so the summation tree does not
+/// depend on the thread count or work stealing.
+const SUM_CHUNK: usize = 1024;
+
+/// Sums a slice in parallel with a fixed chunking and ordered combination,
+/// so the result is bit-identical across runs and thread counts.
+pub fn par_sum(values: &[f32]) -> f32 {
+    let partials: Vec<f32> = values
+        .par_chunks(SUM_CHUNK)
+        .map(|chunk| chunk.iter().sum::<f32>())
+        .collect();
+    partials.iter().sum()
+}
+
+/// Returns the arithmetic mean of a slice using a deterministic parallel sum.
+/// An empty slice yields `0.0`.
+pub fn par_mean(values: &[f32]) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    par_sum(values) / values.len() as f32
+}
+
+/// Returns the mean vector length in a slice (e.g. the mean particle speed), summed with
+/// the same fixed chunking as `par_sum`. An empty slice yields `0.0`.
+pub fn par_mean_vec2_len(values: &[Vec2]) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let partials: Vec<f32> = values
+        .par_chunks(SUM_CHUNK)
+        .map(|chunk| chunk.iter().map(|v| v.length()).sum::<f32>())
+        .collect();
+    partials.iter().sum::<f32>() / values.len() as f32
+}
+
+/// Returns the largest vector length in a slice (e.g. the maximum particle speed).
+/// An empty slice yields `0.0`.
+pub fn par_max_vec2_len(values: &[Vec2]) -> f32 {
+    values
+        .par_iter()
+        .map(|v| v.length_squared())
+        .reduce(|| 0.0, f32::max)
+        .sqrt()
+}
+
+/// Component-wise minimum and maximum of a slice of points using a parallel reduction.
+/// An empty slice yields `(Vec2::ZERO, Vec2::ZERO)`.
+pub fn par_bounds(points: &[Vec2]) -> (Vec2, Vec2) {
+    if points.is_empty() {
+        return (Vec2::ZERO, Vec2::ZERO);
+    }
+    points
+        .par_iter()
+        .fold(
+            || (Vec2::INFINITY, Vec2::NEG_INFINITY),
+            |(lo, hi), &p| (lo.min(p), hi.max(p)),
+        )
+        .reduce(
+            || (Vec2::INFINITY, Vec2::NEG_INFINITY),
+            |(lo_a, hi_a), (lo_b, hi_b)| (lo_a.min(lo_b), hi_a.max(hi_b)),
+        )
+}
+
+/// Mean of `points` weighted by `weights`, summed with the same fixed chunking as
+/// `par_sum`. Returns `None` when the weights sum to zero.
+pub fn par_weighted_mean(points: &[Vec2], weights: &[f32]) -> Option<Vec2> {
+    let partials: Vec<(Vec2, f32)> = points
+        .par_chunks(SUM_CHUNK)
+        .zip(weights.par_chunks(SUM_CHUNK))
+        .map(|(points, weights)| {
+            points
+                .iter()
+                .zip(weights)
+                .fold((Vec2::ZERO, 0.0), |(sum, total), (&p, &w)| {
+                    (sum + p * w, total + w)
+                })
+        })
+        .collect();
+    let (sum, total) = partials
+        .iter()
+        .fold((Vec2::ZERO, 0.0), |(sum, total), &(s, t)| {
+            (sum + s, total + t)
+        });
+    (total > 0.0).then(|| sum / total)
+}
+
+/// Total kinetic energy `sum(0.5 * m * f_i * |v_i|^2)` of particles with mass factors `f_i`,
+/// summed with the same fixed chunking as `par_sum` so it is deterministic.
+pub fn total_kinetic_energy(velocities: &[Vec2], mass_factors: &[f32], mass: f32) -> f32 {
+    let partials: Vec<f32> = velocities
+        .par_chunks(SUM_CHUNK)
+        .zip(mass_factors.par_chunks(SUM_CHUNK))
+        .map(|(vels, factors)| {
+            vels.iter()
+                .zip(factors)
+                .map(|(v, f)| f * v.length_squared())
+                .sum::<f32>()
+        })
+        .collect();
+    0.5 * mass * partials.iter().sum::<f32>()
+}
+
+/// Total gravitational potential energy `sum(-m * f_i * g . (p_i - reference))`, where
+/// `reference` is the zero-energy point (e.g. the domain floor).
+pub fn total_potential_energy(
+    positions: &[Vec2],
+    mass_factors: &[f32],
+    mass: f32,
+    gravity: Vec2,
+    reference: Vec2,
+) -> f32 {
+    let partials: Vec<f32> = positions
+        .par_chunks(SUM_CHUNK)
+        .zip(mass_factors.par_chunks(SUM_CHUNK))
+        .map(|(points, factors)| {
+            points
+                .iter()
+                .zip(factors)
+                .map(|(&p, f)| -f * gravity.dot(p - reference))
+                .sum::<f32>()
+        })
+        .collect();
+    mass * partials.iter().sum::<f32>()
+}
+
+/// Total momentum `sum(m * f_i * v_i)`, with the same fixed chunking as `par_sum`.
+pub fn total_momentum(velocities: &[Vec2], mass_factors: &[f32], mass: f32) -> Vec2 {
+    let partials: Vec<Vec2> = velocities
+        .par_chunks(SUM_CHUNK)
+        .zip(mass_factors.par_chunks(SUM_CHUNK))
+        .map(|(vels, factors)| vels.iter().zip(factors).map(|(&v, &f)| v * f).sum::<Vec2>())
+        .collect();
+    mass * partials.iter().sum::<Vec2>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    /// Enough values to span several `SUM_CHUNK`s plus a partial one.
+    const LEN: usize = SUM_CHUNK * 5 + 37;
+
+    /// Positive, so sums do not cancel and a relative tolerance is meaningful.
+    fn values(seed: u64) -> Vec<f32> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..LEN).map(|_| rng.random_range(0.0..50.0)).collect()
+    }
+
+    fn points(seed: u64) -> Vec<Vec2> {
+        let values = values(seed);
+        values
+            .chunks_exact(2)
+            .map(|c| Vec2::new(c[0], c[1]))
+            .collect()
+    }
+
+    fn factors(len: usize) -> Vec<f32> {
+        (0..len).map(|i| 0.5 + (i % 3) as f32 * 0.25).collect()
+    }
+
+    /// Serial sums are accumulated in f64 so they serve as the reference.
+    fn assert_close(actual: f32, expected: f64) {
+        let tolerance = 1e-4 * expected.abs().max(1.0);
+        assert!(
+            (actual as f64 - expected).abs() <= tolerance,
+            "{actual} differs from the serial {expected}"
+        );
+    }
+
+    #[test]
+    fn min_max_matches_serial() {
+        let values = values(1);
+        let lo = values.iter().copied().fold(f32::INFINITY, f32::min);
+        let hi = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        assert_eq!(par_min_max(&values), (lo, hi));
+        assert_eq!(par_min_max(&[]), (0.0, 0.0));
+    }
+
+    #[test]
+    fn sum_and_mean_match_serial() {
+        let values = values(2);
+        let sum: f64 = values.iter().map(|&v| v as f64).sum();
+        assert_close(par_sum(&values), sum);
+        assert_close(par_mean(&values), sum / LEN as f64);
+        assert_eq!(par_mean(&[]), 0.0);
+    }
+
+    #[test]
+    fn sum_is_bit_identical_across_thread_counts() {
+        let values = values(3);
+        let single = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap()
+            .install(|| par_sum(&values));
+        let several = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap()
+            .install(|| par_sum(&values));
+        assert_eq!(single.to_bits(), several.to_bits());
+    }
+
+    #[test]
+    fn vector_lengths_match_serial() {
+        let points = points(4);
+        let mean: f64 = points.iter().map(|p| p.length() as f64).sum::<f64>() / points.len() as f64;
+        let max = points.iter().map(|p| p.length()).fold(0.0, f32::max);
+        assert_close(par_mean_vec2_len(&points), mean);
+        assert_close(par_max_vec2_len(&points), max as f64);
+        assert_eq!(par_mean_vec2_len(&[]), 0.0);
+        assert_eq!(par_max_vec2_len(&[]), 0.0);
+    }
+
+    #[test]
+    fn bounds_match_serial() {
+        let points = points(5);
+        let lo = points.iter().fold(Vec2::INFINITY, |lo, &p| lo.min(p));
+        let hi = points.iter().fold(Vec2::NEG_INFINITY, |hi, &p| hi.max(p));
+        assert_eq!(par_bounds(&points), (lo, hi));
+        assert_eq!(par_bounds(&[]), (Vec2::ZERO, Vec2::ZERO));
+    }
+
+    #[test]
+    fn weighted_mean_matches_serial() {
+        let points = points(6);
+        let weights = factors(points.len());
+        let (mut x, mut y, mut total) = (0.0f64, 0.0f64, 0.0f64);
+        for (p, &w) in points.iter().zip(&weights) {
+            x += (p.x * w) as f64;
+            y += (p.y * w) as f64;
+            total += w as f64;
+        }
+        let mean = par_weighted_mean(&points, &weights).unwrap();
+        assert_close(mean.x, x / total);
+        assert_close(mean.y, y / total);
+        assert_eq!(par_weighted_mean(&points, &vec![0.0; points.len()]), None);
+    }
+
+    #[test]
+    fn energies_and_momentum_match_serial() {
+        let positions = points(7);
+        let velocities = points(8);
+        let weights = factors(positions.len());
+        let (mass, gravity, reference) = (2.5, Vec2::new(0.0, -100.0), Vec2::new(0.0, -300.0));
+        let (mut kinetic, mut potential) = (0.0f64, 0.0f64);
+        let (mut px, mut py) = (0.0f64, 0.0f64);
+        for ((p, v), &f) in positions.iter().zip(&velocities).zip(&weights) {
+            let m = (mass * f) as f64;
+            kinetic += 0.5 * m * v.length_squared() as f64;
+            potential -= m * gravity.dot(*p - reference) as f64;
+            px += m * v.x as f64;
+            py += m * v.y as f64;
+        }
+        assert_close(total_kinetic_energy(&velocities, &weights, mass), kinetic);
+        assert_close(
+            total_potential_energy(&positions, &weights, mass, gravity, reference),
+            potential,
+        );
+        let momentum = total_momentum(&velocities, &weights, mass);
+        assert_close(momentum.x, px);
+        assert_close(momentum.y, py);
+    }
+}
@@ -1,261 +1,3594 @@
-use bevy::{prelude::*, window::PrimaryWindow};
-use rayon::prelude::*;
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    asset::{RenderAssetUsages, io::file::FileAssetReader},
+    diagnostic::FrameCount,
+    ecs::{bundle::NoBundleEffect, query::QueryFilter},
+    image::ImageSampler,
+    mesh::MeshTag,
+    prelude::*,
+    render::{
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        view::screenshot::{Screenshot, save_to_disk},
+    },
+    window::{PrimaryWindow, WindowFocused},
+};
+use rand::Rng;
+use rayon::{ThreadPool, ThreadPoolBuilder, prelude::*};
 
 use crate::{
-    components::ParticleId,
-    kernels::{poly6_kernel, spiky_kernel_gradient, viscosity_laplacian},
-    resources::{BOUNDARY_HEIGHT, BOUNDARY_WIDTH, FluidConfig, FluidSimulation, PARTICLE_RADIUS},
+    components::{
+        ColorLegendLabel, ComparisonParticle, DiagnosticsLabel, InitialConditionLabel,
+        OccupancyHeatmap, ParticleId, TrailCanvas,
+    },
+    contour::{ScalarGrid, marching_squares},
+    geometry::{
+        Obstacle, RotatingObstacle, Segment, chute_basin_regions, frozen_at, funnel_segments,
+        gravity_at, paddle_wheel, upper_half_freeze,
+    },
+    gpu::{GpuSolver, NeighborSums},
+    grid::GridView,
+    kernels::{KernelCache, artificial_viscosity, spiky_kernel_gradient},
+    materials::{AdditiveParticleMaterial, pack_color},
+    resources::{
+        AdaptiveParticleCount, BoundaryBehavior, ColorField, ColorRange, ComparisonConfig,
+        ComparisonSim, DEFAULT_PARTICLE_TEXTURE, DomainBoundaries, ENERGY_HISTORY_LEN,
+        EnergyHistory, FlowEvent, FlowMeter, FluidConfig, FluidSimulation, ForceBreakdown,
+        GravityMode, IdPattern, InitialCondition, IntegrationOrder, MIN_ACTIVE_PARTICLES,
+        PARTICLE_COUNT, PROBE_HISTORY_LEN, ParticleProbe, ParticleRemoved, ParticleSink,
+        ParticleSpawned, Phase, PhaseProfiler, PhaseTimes, ProbeSample, RenderConfig,
+        SceneElements, SelectedInitialCondition, SettleDetector, SimStats, SimulationSettled,
+        SweepRecorder, SweepRow, ViscosityKernel, ViscosityModel, WindowUnfocused,
+        update_separations,
+    },
+    scene::{DEFAULT_SCENE_PATH, SceneFile, load_scene, save_scene},
+    spectrum::energy_spectrum,
+    stats::{
+        par_max_vec2_len, par_mean, par_mean_vec2_len, par_min_max, par_sum, total_kinetic_energy,
+        total_momentum, total_potential_energy,
+    },
+    toml_dump::write_table,
 };
 
+#[cfg(feature = "simd")]
+use crate::simd;
+
+/// Resets the main instance to `condition` the way 'R' does: places the particles,
+/// assigns ids, applies the initial velocity and recalibrates the target density if enabled.
+fn reset_main_instance(
+    sim: &mut FluidSimulation,
+    config: &mut FluidConfig,
+    condition: InitialCondition,
+    elements: &SceneElements,
+    id_pattern: IdPattern,
+) {
+    let placed = sim.reset(
+        condition,
+        config.seed,
+        config.init_jitter,
+        &elements.obstacles,
+    );
+    if placed < PARTICLE_COUNT {
+        warn!("Obstacles leave too little room, placed {placed} of {PARTICLE_COUNT} particles");
+    }
+    sim.assign_ids(id_pattern);
+    sim.apply_initial_velocity(config.initial_velocity, config.seed);
+    if config.auto_calibrate_target_density {
+        calibrate_target_density(sim, config);
+    }
+}
+
 /// Handles user input for resetting the simulation.
-/// Press 'R' to randomize particle positions.
+/// Press 'C' to cycle the selected initial condition.
+/// Press 'R' to reset to the selected initial condition (random layouts use the configured seed).
 /// Press 'G' to arrange particles in a grid pattern.
-pub fn handle_input(input: Res<ButtonInput<KeyCode>>, mut sim: ResMut<FluidSimulation>) {
-    if input.just_pressed(KeyCode::KeyR) {
-        sim.reset_random();
+/// Press 'W' to toggle the demo funnel walls.
+/// Press 'V' to toggle the demo gravity regions.
+/// Press 'N' to toggle the demo paddle wheel.
+/// Press 'Z' to toggle gravity.
+/// Hold ',' / '.' to tilt gravity clockwise / counter-clockwise.
+/// Press '1' / '2' / '3' / '4' to cycle the left / right / bottom / top wall between closed,
+/// open and periodic.
+/// Press 'S' to toggle automatic stirring.
+/// Press 'I' to freeze the upper half of the domain, or release every freeze region.
+/// Press 'B' to drop a blob of particles from the top of the domain.
+/// Press 'Q' to spin up a vortex around the cursor (or the domain center) with `swirl`.
+/// Press 'K' to fire an outward blast around the cursor (or the domain center) with `blast`.
+/// Press '=' / '-' to scale the particle mass up / down by 10%.
+/// Press 'E' to toggle the energy plot.
+/// Press 'D' to toggle the domain outline.
+/// Press 'H' to toggle the grid occupancy heatmap.
+/// Press 'U' to toggle the particle trails.
+/// Press 'A' to toggle camera auto-framing.
+/// Press '[' / ']' to halve / double the playback speed.
+/// Resets, 'Q' and 'K' also affect the `--compare` instance; the other keys affect the main
+/// instance only.
+#[allow(clippy::too_many_arguments)]
+pub fn handle_input(
+    input: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    mut config: ResMut<FluidConfig>,
+    mut render_config: ResMut<RenderConfig>,
+    mut sim: ResMut<FluidSimulation>,
+    mut energy: ResMut<EnergyHistory>,
+    mut selected: ResMut<SelectedInitialCondition>,
+    mut elements: ResMut<SceneElements>,
+    mut comparison: Option<ResMut<ComparisonSim>>,
+    comparison_config: Option<ResMut<ComparisonConfig>>,
+) {
+    if input.just_pressed(KeyCode::KeyC) {
+        selected.0 = selected.0.next();
+    }
+
+    let reset = if input.just_pressed(KeyCode::KeyR) {
+        Some(selected.0)
     } else if input.just_pressed(KeyCode::KeyG) {
-        sim.reset_to_grid();
+        Some(InitialCondition::Grid)
+    } else {
+        None
+    };
+    if let Some(condition) = reset {
+        reset_main_instance(
+            &mut sim,
+            &mut config,
+            condition,
+            &elements,
+            render_config.id_pattern,
+        );
+        // The comparison instance restarts from the same seed for an identical start.
+        if let (Some(other), Some(mut other_config)) =
+            (comparison.as_deref_mut(), comparison_config)
+        {
+            other.0.reset(
+                condition,
+                config.seed,
+                other_config.0.init_jitter,
+                &elements.obstacles,
+            );
+            other.0.assign_ids(render_config.id_pattern);
+            other
+                .0
+                .apply_initial_velocity(other_config.0.initial_velocity, config.seed);
+            if other_config.0.auto_calibrate_target_density {
+                calibrate_target_density(&mut other.0, &mut other_config.0);
+            }
+        }
+    }
+
+    if input.just_pressed(KeyCode::KeyW) {
+        elements.segments = if elements.segments.is_empty() {
+            funnel_segments()
+        } else {
+            Vec::new()
+        };
+    }
+
+    if input.just_pressed(KeyCode::KeyV) {
+        elements.gravity_regions = if elements.gravity_regions.is_empty() {
+            chute_basin_regions()
+        } else {
+            Vec::new()
+        };
+    }
+
+    if input.just_pressed(KeyCode::KeyN) {
+        elements.rotating_obstacles = if elements.rotating_obstacles.is_empty() {
+            paddle_wheel(sim.domain)
+        } else {
+            Vec::new()
+        };
+    }
+
+    if input.just_pressed(KeyCode::KeyI) {
+        elements.freeze_regions = if elements.freeze_regions.is_empty() {
+            upper_half_freeze(sim.domain)
+        } else {
+            Vec::new()
+        };
+    }
+
+    if input.just_pressed(KeyCode::KeyB) {
+        let requested = config.blob_particle_count;
+        let added = sim.drop_blob(config.blob_radius, requested);
+        if added == 0 && requested > 0 {
+            warn!("Particle capacity reached, no blob dropped; press 'R' or 'G' to reset");
+        } else if added < requested {
+            warn!("Particle capacity reached, dropped {added} of {requested} blob particles");
+        }
+    }
+
+    if input.just_pressed(KeyCode::KeyQ) {
+        let swirl = config.swirl;
+        let cursor = cursor_position(&q_window, &q_camera, render_config.world_scale);
+        let spin = |sim: &mut FluidSimulation| {
+            let center = cursor.unwrap_or(sim.domain.center());
+            sim.apply_swirl(center, swirl.radius, swirl.angular_velocity)
+        };
+        spin(&mut sim);
+        if let Some(other) = comparison.as_deref_mut() {
+            spin(&mut other.0);
+        }
+    }
+
+    if input.just_pressed(KeyCode::KeyK) {
+        let blast = config.blast;
+        let cursor = cursor_position(&q_window, &q_camera, render_config.world_scale);
+        let seed = config.seed;
+        let detonate = |sim: &mut FluidSimulation| {
+            let center = cursor.unwrap_or(sim.domain.center());
+            sim.apply_blast(center, blast.radius, blast.speed, seed)
+        };
+        detonate(&mut sim);
+        if let Some(other) = comparison.as_deref_mut() {
+            detonate(&mut other.0);
+        }
+    }
+
+    if input.just_pressed(KeyCode::Equal) {
+        config.particle_mass *= MASS_STEP;
+    } else if input.just_pressed(KeyCode::Minus) {
+        config.particle_mass /= MASS_STEP;
+    }
+
+    if input.just_pressed(KeyCode::KeyZ) {
+        config.gravity_enabled = !config.gravity_enabled;
+    }
+    let tilt = input.pressed(KeyCode::Period) as i32 - input.pressed(KeyCode::Comma) as i32;
+    if tilt != 0 {
+        let angle = config.gravity_angle + tilt as f32 * TILT_RATE * time.delta_secs();
+        config.gravity_angle = (angle + 180.0).rem_euclid(360.0) - 180.0;
+    }
+
+    let walls = &mut config.boundaries;
+    for (key, wall) in [
+        (KeyCode::Digit1, &mut walls.left),
+        (KeyCode::Digit2, &mut walls.right),
+        (KeyCode::Digit3, &mut walls.bottom),
+        (KeyCode::Digit4, &mut walls.top),
+    ] {
+        if input.just_pressed(key) {
+            *wall = wall.next();
+        }
+    }
+
+    if input.just_pressed(KeyCode::KeyS) {
+        config.auto_stir.enabled = !config.auto_stir.enabled;
+    }
+
+    if input.just_pressed(KeyCode::KeyE) {
+        energy.visible = !energy.visible;
+    }
+
+    // Bypassed so toggling an overlay does not respawn the particle sprites.
+    if input.just_pressed(KeyCode::KeyD) {
+        let render_config = render_config.bypass_change_detection();
+        render_config.show_domain_box = !render_config.show_domain_box;
+    }
+    if input.just_pressed(KeyCode::KeyH) {
+        let render_config = render_config.bypass_change_detection();
+        render_config.show_occupancy_heatmap = !render_config.show_occupancy_heatmap;
+    }
+    if input.just_pressed(KeyCode::KeyU) {
+        let render_config = render_config.bypass_change_detection();
+        render_config.show_trails = !render_config.show_trails;
+    }
+    if input.just_pressed(KeyCode::KeyA) {
+        let render_config = render_config.bypass_change_detection();
+        render_config.auto_frame = !render_config.auto_frame;
+    }
+
+    if input.just_pressed(KeyCode::BracketLeft) {
+        config.steps_per_frame = (config.steps_per_frame * 0.5).clamp(MIN_SPEED, MAX_SPEED);
+    } else if input.just_pressed(KeyCode::BracketRight) {
+        config.steps_per_frame = (config.steps_per_frame * 2.0).clamp(MIN_SPEED, MAX_SPEED);
+    }
+}
+
+/// Factor applied to `particle_mass` per '=' / '-' key press.
+const MASS_STEP: f32 = 1.1;
+
+/// Speed at which holding ',' / '.' tilts gravity, in degrees per second.
+const TILT_RATE: f32 = 45.0;
+
+/// Playback speed range reachable with '[' / ']', in physics steps per frame.
+const MIN_SPEED: f32 = 1.0 / 16.0;
+const MAX_SPEED: f32 = 16.0;
+
+/// Cursor path samples are spaced at most this fraction of `mouse_radius` apart.
+const INTERACTION_SAMPLE_SPACING: f32 = 0.5;
+/// Upper bound on the cursor path samples per physics step.
+const MAX_INTERACTION_SAMPLES: usize = 32;
+
+/// Mouse interaction applied during a physics step.
+/// The interaction point sweeps from `from` to `pos` over the step.
+#[derive(Clone, Copy, Default)]
+struct Interaction {
+    from: Vec2,
+    pos: Vec2,
+    /// Points sampled along the swept path, including `pos`; 0 is treated as 1.
+    samples: usize,
+    /// 1.0 attracts, -1.0 repels, 0.0 disables.
+    factor: f32,
+}
+
+impl Interaction {
+    /// A stationary interaction point.
+    fn at(pos: Vec2, factor: f32) -> Self {
+        Self {
+            from: pos,
+            pos,
+            samples: 1,
+            factor,
+        }
+    }
+
+    /// The part of a frame's sweep from `from` to `to` covered by step `step` of `steps`,
+    /// sampled densely enough that every particle within `radius` of the path is reached.
+    fn swept(from: Vec2, to: Vec2, step: usize, steps: usize, radius: f32, factor: f32) -> Self {
+        let steps = steps.max(1) as f32;
+        let start = from.lerp(to, step as f32 / steps);
+        let end = from.lerp(to, (step + 1) as f32 / steps);
+        let spacing = (radius * INTERACTION_SAMPLE_SPACING).max(f32::EPSILON);
+        let samples =
+            ((start.distance(end) / spacing).ceil() as usize).clamp(1, MAX_INTERACTION_SAMPLES);
+        Self {
+            from: start,
+            pos: end,
+            samples,
+            factor,
+        }
+    }
+
+    /// Force on a particle at `pos`: the strongest pull (or push) from any sampled point of the
+    /// path, so a fast swipe moves every particle along it without double-counting overlaps.
+    fn force_at(&self, pos: Vec2, radius: f32, strength: f32) -> Vec2 {
+        if self.factor == 0.0 {
+            return Vec2::ZERO;
+        }
+        let samples = self.samples.max(1);
+        let mut force = Vec2::ZERO;
+        let mut best = 0.0;
+        for s in 1..=samples {
+            let point = self.from.lerp(self.pos, s as f32 / samples as f32);
+            let to_point = point - pos;
+            let dist = to_point.length();
+            if dist < radius && dist > 0.001 {
+                let magnitude = strength * (1.0 - dist / radius);
+                if magnitude > best {
+                    best = magnitude;
+                    force = to_point / dist * magnitude * self.factor;
+                }
+            }
+        }
+        force
     }
 }
 
+/// Single-threaded pool used when `FluidConfig::deterministic` is set.
+/// Running the parallel passes inside it fixes the execution order, so every
+/// per-particle write and reduction is bit-identical across runs.
+fn deterministic_pool() -> &'static ThreadPool {
+    static POOL: OnceLock<ThreadPool> = OnceLock::new();
+    POOL.get_or_init(|| {
+        ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .expect("failed to build deterministic thread pool")
+    })
+}
+
 /// Updates the fluid physics simulation using parallel computation.
 /// Performs density calculation, pressure computation, force integration, and position updates.
+/// When `auto_stir` is enabled its circling point replaces the cursor interaction.
+/// The cursor's path since the previous tick is split across the tick's steps and sampled
+/// along its length, so fast drags act continuously instead of in disconnected spots.
+/// Runs in `FixedUpdate` at `physics_rate` ticks per second, `steps_per_frame` steps per
+/// tick, carrying fractional steps over to later ticks. While `RenderConfig::capture` is
+/// on it runs in `Update` instead, `capture_substeps` steps per rendered frame.
+/// The `--compare` instance is stepped in lockstep, with the same interaction.
+#[allow(clippy::too_many_arguments)]
 pub fn update_physics_rayon(
     mut sim: ResMut<FluidSimulation>,
     config: Res<FluidConfig>,
+    render_config: Res<RenderConfig>,
     q_window: Query<&Window, With<PrimaryWindow>>,
     q_camera: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
     mouse_btn: Res<ButtonInput<MouseButton>>,
+    mut adaptive: ResMut<AdaptiveParticleCount>,
+    elements: Res<SceneElements>,
+    mut stir_angle: Local<f32>,
+    mut prev_cursor: Local<Option<Vec2>>,
+    mut profiler: ResMut<PhaseProfiler>,
+    mut probe: ResMut<ParticleProbe>,
+    mut comparison: Option<ResMut<ComparisonSim>>,
+    comparison_config: Option<Res<ComparisonConfig>>,
+    mut gpu: Option<ResMut<GpuSolver>>,
 ) {
     let dt = 0.002 * config.time_scale;
     if dt <= 0.0 {
         return;
     }
 
+    // Whole steps owed this tick; the remainder carries over to later ticks.
+    let speed = if render_config.capture {
+        render_config.capture_substeps.max(1) as f32
+    } else {
+        config.steps_per_frame.max(0.0)
+    };
+    sim.step_carry += speed;
+    let steps = sim.step_carry.floor();
+    sim.step_carry -= steps;
+
     // Handle mouse interaction
-    let mut interaction_pos = Vec2::ZERO;
-    let mut interaction_factor = 0.0;
+    let cursor = cursor_position(&q_window, &q_camera, render_config.world_scale);
+    let cursor_from = prev_cursor.or(cursor);
+    // Only a step consumes the swept path, so at fractional speeds the drag since the last
+    // step carries over to the next tick that steps.
+    if steps >= 1.0 || cursor.is_none() {
+        *prev_cursor = cursor;
+    }
 
-    if let (Ok(window), Ok((camera, camera_transform))) = (q_window.single(), q_camera.single())
-        && let Some(cursor_screen_pos) = window.cursor_position()
-        && let Ok(world_pos) = camera.viewport_to_world_2d(camera_transform, cursor_screen_pos)
-    {
-        interaction_pos = world_pos;
+    let factor = if mouse_btn.pressed(MouseButton::Left) {
+        1.0 // Attract
+    } else if mouse_btn.pressed(MouseButton::Right) {
+        -1.0 // Repel
+    } else {
+        0.0
+    };
 
-        if mouse_btn.pressed(MouseButton::Left) {
-            interaction_factor = 1.0; // Attract
-        } else if mouse_btn.pressed(MouseButton::Right) {
-            interaction_factor = -1.0; // Repel
+    let stir = config.auto_stir;
+    let steps = steps as usize;
+    let mut times = PhaseTimes::default();
+    let start = Instant::now();
+    for step in 0..steps {
+        let interaction = if stir.enabled {
+            // Advanced by simulated time so the path is independent of the frame rate.
+            *stir_angle = (*stir_angle + stir.rate * dt) % std::f32::consts::TAU;
+            Interaction::at(
+                stir.center + Vec2::from_angle(*stir_angle) * stir.radius,
+                stir.factor,
+            )
+        } else if let (Some(from), Some(to)) = (cursor_from, cursor) {
+            Interaction::swept(from, to, step, steps, config.mouse_radius, factor)
+        } else {
+            Interaction::default()
+        };
+        step_instance(
+            &mut sim,
+            &config,
+            &elements,
+            interaction,
+            &mut times,
+            Some(&mut probe),
+            gpu.as_deref_mut(),
+        );
+        if let (Some(other), Some(other_config)) =
+            (comparison.as_deref_mut(), comparison_config.as_deref())
+        {
+            step_instance(
+                &mut other.0,
+                &other_config.0,
+                &elements,
+                interaction,
+                &mut times,
+                None,
+                gpu.as_deref_mut(),
+            );
         }
     }
+    profiler.record(&times);
+    if steps >= 1 {
+        adaptive.record(start.elapsed().as_secs_f32() * 1000.0 / steps as f32);
+    }
+}
+
+/// Keeps the fixed timestep at `FluidConfig::physics_rate`.
+pub fn apply_physics_rate(config: Res<FluidConfig>, mut fixed: ResMut<Time<Fixed>>) {
+    let rate = config
+        .physics_rate
+        .clamp(MIN_PHYSICS_RATE, MAX_PHYSICS_RATE);
+    let timestep = Duration::from_secs_f32(1.0 / rate);
+    if fixed.timestep() != timestep {
+        fixed.set_timestep(timestep);
+    }
+}
 
-    let sim = &mut *sim;
-    let h = config.smoothing_radius;
-    let h_sq = h * h;
-    let target_density = config.target_density;
-    let pressure_k = config.pressure_multiplier;
-    let viscosity_mu = config.viscosity_strength;
-    let gravity = config.gravity;
-    let interact_rad = config.mouse_radius;
-    let interact_str = config.mouse_strength;
+/// Bounds of `FluidConfig::physics_rate`, in ticks per second.
+const MIN_PHYSICS_RATE: f32 = 1.0;
+const MAX_PHYSICS_RATE: f32 = 10_000.0;
 
-    // Rebuild spatial grid for neighbor searches
-    sim.grid_map.par_iter_mut().for_each(|cell| cell.clear());
-    let grid_w = sim.grid_width_cells;
-    let cell_size = sim.grid_cell_size;
-    let off_x = sim.grid_offset_x;
-    let off_y = sim.grid_offset_y;
+/// Sets how far rendering is between the previous and latest physics step from the time
+/// left over in the fixed-timestep accumulator, so motion stays smooth when the frame rate
+/// and `physics_rate` differ. The renderer trails the physics by up to one step. At
+/// fractional speeds the carried-over step fraction is added, so slow motion moves
+/// smoothly across ticks without a step.
+pub fn interpolate_fixed_steps(
+    time: Res<Time<Fixed>>,
+    config: Res<FluidConfig>,
+    mut sim: ResMut<FluidSimulation>,
+    comparison: Option<ResMut<ComparisonSim>>,
+) {
+    let overstep = time.overstep_fraction();
+    let speed = config.steps_per_frame.max(0.0);
+    let alpha = if speed < 1.0 {
+        (sim.step_carry + overstep * speed).min(1.0)
+    } else {
+        overstep.min(1.0)
+    };
+    sim.interpolation_alpha = alpha;
+    if let Some(mut other) = comparison {
+        other.0.interpolation_alpha = alpha;
+    }
+}
+
+/// Run condition: whether capture mode (`RenderConfig::capture`) steps the physics once
+/// per rendered frame instead of in `FixedUpdate`.
+pub fn capturing(render_config: Res<RenderConfig>) -> bool {
+    render_config.capture
+}
+
+/// The cursor's position in simulation units, or `None` when it is outside the window.
+fn cursor_position(
+    q_window: &Query<&Window, With<PrimaryWindow>>,
+    q_camera: &Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    world_scale: f32,
+) -> Option<Vec2> {
+    let (window, (camera, camera_transform)) = (q_window.single().ok()?, q_camera.single().ok()?);
+    let world_pos = camera
+        .viewport_to_world_2d(camera_transform, window.cursor_position()?)
+        .ok()?;
+    Some(world_pos / world_scale)
+}
 
-    for (i, pos) in sim.positions.iter().enumerate() {
-        let gx = ((pos.x + off_x) / cell_size) as usize;
-        let gy = ((pos.y + off_y) / cell_size) as usize;
-        let idx = (gy * grid_w + gx).clamp(0, sim.grid_map.len() - 1);
-        sim.grid_map[idx].push(i);
+/// Run condition of `update_physics_rayon`: false while the window is unfocused with
+/// `pause_on_unfocus` on. Skipped ticks owe no steps, so nothing is caught up on resume.
+pub fn physics_running(config: Res<FluidConfig>, unfocused: Res<WindowUnfocused>) -> bool {
+    !(config.pause_on_unfocus && unfocused.0)
+}
+
+/// Tracks the primary window's focus for `pause_on_unfocus`.
+pub fn track_window_focus(
+    mut focus: MessageReader<WindowFocused>,
+    primary: Query<Entity, With<PrimaryWindow>>,
+    mut unfocused: ResMut<WindowUnfocused>,
+) {
+    for message in focus.read() {
+        if primary.contains(message.window) {
+            unfocused.0 = !message.focused;
+        }
     }
-    let positions = &sim.positions;
-    let grid = &sim.grid_map;
+}
 
-    // Calculate density and pressure for each particle
-    sim.densities
-        .par_iter_mut()
-        .zip(&mut sim.pressures)
-        .enumerate()
-        .for_each(|(i, (density_out, pressure_out))| {
-            let pos = positions[i];
-            let mut d = 0.0;
-            let gx = ((pos.x + off_x) / cell_size) as usize;
-            let gy = ((pos.y + off_y) / cell_size) as usize;
-
-            for dy in -1..=1 {
-                for dx in -1..=1 {
-                    let cx = (gx as isize + dx) as usize;
-                    let cy = (gy as isize + dy) as usize;
-                    if cx >= sim.grid_width_cells || cy >= sim.grid_height_cells {
-                        continue;
-                    }
-                    if let Some(cell) = grid.get(cy * grid_w + cx) {
-                        for &j in cell {
-                            let dist_sq = pos.distance_squared(positions[j]);
-                            if dist_sq < h_sq {
-                                d += config.particle_mass * poly6_kernel(dist_sq, h);
-                            }
-                        }
+/// Advances one simulation instance by a step of its own `time_scale`, in the deterministic
+/// pool when its config asks for it, and on `gpu` when its config selects `Backend::Gpu`.
+#[allow(clippy::too_many_arguments)]
+fn step_instance(
+    sim: &mut FluidSimulation,
+    config: &FluidConfig,
+    elements: &SceneElements,
+    interaction: Interaction,
+    times: &mut PhaseTimes,
+    probe: Option<&mut ParticleProbe>,
+    gpu: Option<&mut GpuSolver>,
+) {
+    let dt = 0.002 * config.time_scale;
+    if dt <= 0.0 {
+        return;
+    }
+    let gpu = gpu.and_then(|gpu| gpu.accepts(config));
+    if config.deterministic {
+        deterministic_pool().install(|| {
+            step_simulation(
+                sim,
+                config,
+                elements,
+                interaction,
+                dt,
+                NeighborSource::Grid,
+                times,
+                probe,
+                gpu,
+            )
+        });
+    } else {
+        step_simulation(
+            sim,
+            config,
+            elements,
+            interaction,
+            dt,
+            NeighborSource::Grid,
+            times,
+            probe,
+            gpu,
+        );
+    }
+}
+
+/// Distance of each split child from the parent, as a fraction of `smoothing_radius`.
+const SPLIT_OFFSET_FRACTION: f32 = 0.25;
+
+/// Adaptive particle refinement (`FluidConfig::refinement`): splits particles whose shear
+/// rate exceeds `split_threshold` and merges calm nearby pairs below `merge_threshold`.
+/// Candidates are found with the latest step's grid and densities before any slot moves.
+pub fn refine_particles(mut sim: ResMut<FluidSimulation>, config: Res<FluidConfig>) {
+    let refinement = config.refinement;
+    if !refinement.enabled {
+        return;
+    }
+    let sim = &mut *sim;
+    let n = sim.active_count.min(sim.positions.len());
+    let h = config.smoothing_radius;
+
+    // Shear rate estimate |∇v| ≈ Σ (m_j / ρ_j) |v_j - v_i| |∇W|, as for power-law viscosity.
+    let shear_rates: Vec<f32> = {
+        let sim = &*sim;
+        let grid = sim.grid_view();
+        (0..n)
+            .into_par_iter()
+            .map(|i| {
+                let pos = sim.positions[i];
+                let vel = sim.velocities[i];
+                let mut rate = 0.0;
+                grid.for_each_candidate(pos, |j| {
+                    let dist = pos.distance(sim.positions[j]);
+                    if i != j && j < n && dist < h {
+                        rate += config.particle_mass * sim.mass_factors[j]
+                            / sim.densities[j].max(config.min_density())
+                            * (sim.velocities[j] - vel).length()
+                            * spiky_kernel_gradient(dist, h);
                     }
+                });
+                rate
+            })
+            .collect()
+    };
+
+    // Greedily pair each calm particle with its nearest calm, unpaired neighbor.
+    let merge_dist_sq = (refinement.merge_distance * h).powi(2);
+    let is_calm = |i: usize| shear_rates[i] < refinement.merge_threshold;
+    let mut paired = vec![false; n];
+    let mut merges = Vec::new();
+    {
+        let grid = sim.grid_view();
+        for i in (0..n).filter(|&i| is_calm(i)) {
+            if paired[i] {
+                continue;
+            }
+            let pos = sim.positions[i];
+            let mut nearest: Option<(usize, f32)> = None;
+            grid.for_each_candidate(pos, |j| {
+                if i == j
+                    || j >= n
+                    || paired[j]
+                    || !is_calm(j)
+                    || sim.mass_factors[i] + sim.mass_factors[j] > refinement.max_mass
+                {
+                    return;
+                }
+                let dist_sq = pos.distance_squared(sim.positions[j]);
+                if dist_sq < merge_dist_sq && nearest.is_none_or(|(_, best)| dist_sq < best) {
+                    nearest = Some((j, dist_sq));
                 }
+            });
+            if let Some((j, _)) = nearest {
+                paired[i] = true;
+                paired[j] = true;
+                merges.push((i.min(j), i.max(j)));
             }
-            *density_out = d;
-            *pressure_out = pressure_k * (d - target_density);
+        }
+    }
+
+    // Children are appended past `n`, so the indices in `merges` stay valid.
+    for i in 0..n {
+        if paired[i]
+            || shear_rates[i] <= refinement.split_threshold
+            || sim.mass_factors[i] * 0.5 < refinement.min_mass
+        {
+            continue;
+        }
+        let axis = sim.velocities[i].perp().try_normalize().unwrap_or(Vec2::X);
+        if !sim.split_particle(i, axis * SPLIT_OFFSET_FRACTION * h) {
+            break;
+        }
+    }
+
+    for &(keep, other) in &merges {
+        sim.merge_into(keep, other);
+    }
+    // Removing from the highest index down only ever moves already-merged or unpaired
+    // particles into the freed slots.
+    let mut removed: Vec<usize> = merges.iter().map(|&(_, other)| other).collect();
+    removed.sort_unstable_by(|a, b| b.cmp(a));
+    for other in removed {
+        sim.flow_log.push(FlowEvent::Removed {
+            sink: ParticleSink::Merge,
+            time: sim.elapsed,
+            age: sim.ages[other],
         });
+        sim.remove_active(other);
+    }
+}
 
-    let densities = &sim.densities;
-    let pressures = &sim.pressures;
-    let velocities = &sim.velocities;
+/// Nudges the active particle count towards the `target_frame_ms` physics budget.
+/// Changes by 0.5% of the particles per frame to avoid visible popping, and ramps
+/// back up to the full count when the budget is disabled.
+/// With `auto_mass_scaling` each change rescales `particle_mass` to keep the total mass.
+pub fn adapt_particle_count(
+    mut config: ResMut<FluidConfig>,
+    adaptive: Res<AdaptiveParticleCount>,
+    mut sim: ResMut<FluidSimulation>,
+) {
+    let total = sim.live_count;
+    let step = (total / 200).max(1);
+    let current = sim.active_count;
+    let target = config.target_frame_ms;
+    let measured = adaptive.smoothed_step_ms;
+    let next = if target <= 0.0 || measured < target * 0.8 {
+        (current + step).min(total)
+    } else if measured > target {
+        current
+            .saturating_sub(step)
+            .max(MIN_ACTIVE_PARTICLES.min(total))
+    } else {
+        current
+    };
+    if next == current {
+        return;
+    }
+    if config.auto_mass_scaling {
+        set_active_count_keeping_mass(&mut sim, &mut config, next);
+    } else {
+        sim.set_active_count(next);
+    }
+}
 
-    // Calculate forces (pressure, viscosity, interaction)
-    sim.forces
-        .par_iter_mut()
-        .enumerate()
-        .for_each(|(i, force_out)| {
-            let pos = positions[i];
-            let dens = densities[i];
-            let press = pressures[i];
-            let vel = velocities[i];
+/// Changes the active particle count to `count` and scales `particle_mass` so the total mass
+/// of the active particles stays put, and with it the volume the fluid settles to at
+/// `target_density`. Needs no density pass, so it costs nothing per frame.
+fn set_active_count_keeping_mass(
+    sim: &mut FluidSimulation,
+    config: &mut FluidConfig,
+    count: usize,
+) {
+    let active_mass = |sim: &FluidSimulation| par_sum(&sim.mass_factors[..sim.active_count]);
+    let before = active_mass(sim);
+    sim.set_active_count(count);
+    let after = active_mass(sim);
+    if before > 0.0 && after > 0.0 {
+        config.particle_mass *= before / after;
+    }
+}
 
-            let mut f_pressure = Vec2::ZERO;
-            let mut f_viscosity = Vec2::ZERO;
-            let gx = ((pos.x + off_x) / cell_size) as usize;
-            let gy = ((pos.y + off_y) / cell_size) as usize;
-
-            for dy in -1..=1 {
-                for dx in -1..=1 {
-                    let cx = (gx as isize + dx) as usize;
-                    let cy = (gy as isize + dy) as usize;
-                    if cx >= sim.grid_width_cells || cy >= sim.grid_height_cells {
-                        continue;
-                    }
+/// Bounds of the power-law viscosity multiplier, keeping very thin or very thick
+/// regions from stalling or destabilizing the explicit integration.
+const VISCOSITY_FACTOR_MIN: f32 = 0.05;
+const VISCOSITY_FACTOR_MAX: f32 = 20.0;
 
-                    if let Some(cell) = grid.get(cy * grid_w + cx) {
-                        for &j in cell {
-                            if i == j {
-                                continue;
-                            }
-                            let other_pos = positions[j];
-                            let dist = pos.distance(other_pos);
+/// Resolves a wall contact with outward `normal`: reflects the approaching normal velocity
+/// scaled by `restitution` and removes the `friction` fraction of the tangential velocity.
+#[inline(always)]
+fn wall_contact(vel: &mut Vec2, normal: Vec2, restitution: f32, friction: f32) {
+    let vn = vel.dot(normal);
+    if vn < 0.0 {
+        *vel -= (1.0 + restitution) * vn * normal;
+    }
+    let tangential = *vel - vel.dot(normal) * normal;
+    *vel -= tangential * friction;
+}
 
-                            if dist < h && dist > 0.0001 {
-                                let dir = (other_pos - pos) / dist;
-                                let safe_dens = densities[j].max(0.0001);
+/// `wall_contact` against a wall moving at `wall_velocity`: the contact acts on the
+/// velocity relative to the wall, so the particle leaves with the wall's motion added.
+#[inline(always)]
+fn moving_wall_contact(
+    vel: &mut Vec2,
+    normal: Vec2,
+    wall_velocity: Vec2,
+    restitution: f32,
+    friction: f32,
+) {
+    let mut relative = *vel - wall_velocity;
+    wall_contact(&mut relative, normal, restitution, friction);
+    *vel = relative + wall_velocity;
+}
 
-                                let slope = spiky_kernel_gradient(dist, h);
-                                let pressure_term =
-                                    (press / dens / dens) + (pressures[j] / safe_dens / safe_dens);
-                                f_pressure += -config.particle_mass
-                                    * config.particle_mass
-                                    * pressure_term
-                                    * slope
-                                    * dir;
+/// The colliders of a step: segment walls, obstacles, paddle wheels and the domain walls.
+#[derive(Clone, Copy)]
+struct Colliders<'a> {
+    segments: &'a [Segment],
+    obstacles: &'a [Obstacle],
+    wheels: &'a [RotatingObstacle],
+    /// Simulation time the wheels are posed at.
+    time: f32,
+    radius: f32,
+    /// Domain bounds shrunk by `radius`.
+    bounds: Rect,
+    walls: DomainBoundaries,
+    restitution: f32,
+    friction: f32,
+    /// Maximum `resolve` passes per particle, see `FluidConfig::collision_iterations`.
+    iterations: u32,
+}
 
-                                let vel_diff = velocities[j] - vel;
-                                let laplacian = viscosity_laplacian(dist, h);
-                                f_viscosity += vel_diff
-                                    * viscosity_mu
-                                    * laplacian
-                                    * (1.0 / safe_dens)
-                                    * config.particle_mass;
-                            }
-                        }
-                    }
-                }
+impl Colliders<'_> {
+    /// The first collider the path `start -> end` runs into: segment walls, obstacles or
+    /// closed domain walls. Returns where the particle stops, clear of it, and the outward
+    /// normal there. Paddle wheels move, so only `resolve` handles them.
+    #[inline(always)]
+    fn sweep(&self, start: Vec2, end: Vec2) -> Option<(Vec2, Vec2)> {
+        let path = end - start;
+        let mut first: Option<(f32, Vec2, Vec2)> = None;
+        let mut consider = |t: f32, stop: Vec2, normal: Vec2| {
+            if first.is_none_or(|(best, _, _)| t < best) {
+                first = Some((t, stop, normal));
+            }
+        };
+        for segment in self.segments {
+            if let Some(t) = segment.sweep_intersection(start, end) {
+                let normal = segment.normal();
+                let side = if (start - segment.a).dot(normal) >= 0.0 {
+                    normal
+                } else {
+                    -normal
+                };
+                consider(t, start + path * t + side * self.radius, side);
+            }
+        }
+        for obstacle in self.obstacles {
+            if let Some((t, normal)) = obstacle.sweep_intersection(start, end, self.radius) {
+                consider(t, start + path * t, normal);
             }
+        }
+        for (behavior, axis, bound, normal) in [
+            (self.walls.left, 0, self.bounds.min.x, Vec2::X),
+            (self.walls.right, 0, self.bounds.max.x, Vec2::NEG_X),
+            (self.walls.bottom, 1, self.bounds.min.y, Vec2::Y),
+            (self.walls.top, 1, self.bounds.max.y, Vec2::NEG_Y),
+        ] {
+            // Signed distances inside the wall, positive towards the domain.
+            let from = (start[axis] - bound) * normal[axis];
+            let to = (end[axis] - bound) * normal[axis];
+            if behavior == BoundaryBehavior::Reflect && from >= 0.0 && to < 0.0 {
+                let t = from / (from - to);
+                consider(t, start + path * t, normal);
+            }
+        }
+        first.map(|(_, stop, normal)| (stop, normal))
+    }
 
-            let mut f_interaction = Vec2::ZERO;
-            if interaction_factor != 0.0 {
-                let to_mouse = interaction_pos - pos;
-                let dist = to_mouse.length();
-                if dist < interact_rad && dist > 0.001 {
-                    let dir = to_mouse / dist;
-                    let strength = interact_str * (1.0 - dist / interact_rad);
-                    f_interaction = dir * strength * interaction_factor;
-                }
+    /// One pass over every collider in turn: segment walls, obstacles and paddle wheels push
+    /// the particle out to its radius and reflect the approaching normal velocity (relative
+    /// to a wheel's moving surface), closed domain walls clamp and bounce it, periodic ones
+    /// wrap it. Returns whether anything moved the particle, i.e. whether another pass may be
+    /// needed.
+    #[inline(always)]
+    fn resolve(&self, pos: &mut Vec2, vel: &mut Vec2) -> bool {
+        let Self {
+            segments,
+            obstacles,
+            wheels,
+            time,
+            radius,
+            bounds,
+            walls,
+            restitution,
+            friction,
+            ..
+        } = *self;
+        let keep_tangential = 1.0 - friction;
+
+        let mut hit = false;
+        for segment in segments {
+            let closest = segment.closest_point(*pos);
+            let offset = *pos - closest;
+            let dist_sq = offset.length_squared();
+            if dist_sq < radius * radius {
+                let dist = dist_sq.sqrt();
+                let normal = if dist > 1e-6 {
+                    offset / dist
+                } else {
+                    segment.normal()
+                };
+                *pos = closest + normal * radius;
+                wall_contact(vel, normal, restitution, friction);
+                hit = true;
             }
+        }
 
-            *force_out = f_pressure + f_viscosity + (gravity * dens) + f_interaction;
-        });
-    sim.positions
-        .par_iter_mut()
-        .zip(&mut sim.velocities)
-        .zip(&sim.forces)
-        .zip(&sim.densities)
-        .for_each(|(((pos, vel), force), dens)| {
-            let acceleration = *force / dens.max(0.0001);
-            *vel += acceleration * dt;
-            *vel *= 0.99; // Numerical damping
-            *pos += *vel * dt;
+        for obstacle in obstacles {
+            if let Some((surface, normal)) = obstacle.resolve(*pos, radius) {
+                *pos = surface;
+                wall_contact(vel, normal, restitution, friction);
+                hit = true;
+            }
+        }
 
-            let w = BOUNDARY_WIDTH / 2.0 - PARTICLE_RADIUS;
-            let hh = BOUNDARY_HEIGHT / 2.0 - PARTICLE_RADIUS;
-            let restitution = config.boundary_damping;
+        for wheel in wheels {
+            if let Some((surface, normal)) = wheel.resolve(*pos, radius, time) {
+                *pos = surface;
+                let wall_velocity = wheel.surface_velocity(surface);
+                moving_wall_contact(vel, normal, wall_velocity, restitution, friction);
+                hit = true;
+            }
+        }
 
-            if pos.x < -w {
-                pos.x = -w;
-                vel.x *= -restitution;
-            } else if pos.x > w {
-                pos.x = w;
+        // Open walls leave the particle outside the bounds for removal below.
+        let x_wall = if pos.x < bounds.min.x {
+            Some(walls.left)
+        } else if pos.x > bounds.max.x {
+            Some(walls.right)
+        } else {
+            None
+        };
+        match x_wall {
+            Some(BoundaryBehavior::Reflect) => {
+                pos.x = pos.x.clamp(bounds.min.x, bounds.max.x);
+                hit = true;
                 vel.x *= -restitution;
+                vel.y *= keep_tangential;
             }
+            // The wrapped position may land in a collider at the opposite wall.
+            Some(BoundaryBehavior::Periodic) => {
+                pos.x = wrap(pos.x, bounds.min.x, bounds.max.x);
+                hit = true;
+            }
+            Some(BoundaryBehavior::Open) | None => {}
+        }
 
-            if pos.y < -hh {
-                pos.y = -hh;
+        let y_wall = if pos.y < bounds.min.y {
+            Some(walls.bottom)
+        } else if pos.y > bounds.max.y {
+            Some(walls.top)
+        } else {
+            None
+        };
+        match y_wall {
+            Some(BoundaryBehavior::Reflect) if pos.y < bounds.min.y => {
+                pos.y = bounds.min.y;
+                hit = true;
                 vel.y = vel.y.max(0.0) * restitution;
-            } else if pos.y > hh {
-                pos.y = hh;
+                vel.x *= keep_tangential;
+            }
+            Some(BoundaryBehavior::Reflect) => {
+                pos.y = bounds.max.y;
+                hit = true;
                 vel.y *= -restitution;
+                vel.x *= keep_tangential;
             }
-        });
+            Some(BoundaryBehavior::Periodic) => {
+                pos.y = wrap(pos.y, bounds.min.y, bounds.max.y);
+                hit = true;
+            }
+            Some(BoundaryBehavior::Open) | None => {}
+        }
+        hit
+    }
 }
 
-/// Sets up the initial scene with particle entities and camera.
-pub fn setup_scene(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    sim: Res<FluidSimulation>,
-) {
-    commands.spawn(Camera2d);
-    let tex = asset_server.load("circle.png");
-    let bundles: Vec<_> = (0..sim.positions.len())
+/// Where the SPH passes take neighbor candidates from.
+#[derive(Clone, Copy)]
+enum Neighbors<'a> {
+    /// Particles in the grid cells around the query point.
+    Grid(GridView<'a>),
+    /// Every one of the first `n` particles: the O(n²) reference the grid is checked against.
+    All(usize),
+}
+
+/// Where `step_simulation` takes neighbor candidates from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum NeighborSource {
+    /// The spatial grid.
+    Grid,
+    /// Every active particle, for `FluidSimulation::step_bruteforce`.
+    AllPairs,
+}
+
+impl Neighbors<'_> {
+    #[inline(always)]
+    fn for_each_candidate(&self, pos: Vec2, f: impl FnMut(usize)) {
+        match self {
+            Neighbors::Grid(grid) => grid.for_each_candidate(pos, f),
+            Neighbors::All(n) => (0..*n).for_each(f),
+        }
+    }
+}
+
+/// Density of particle `i`, `Σ m_j W(|x_i - x_j|, h)` over its neighbor candidates.
+/// With the `simd` feature, grid neighbors are summed four at a time by `simd::density`.
+#[inline(always)]
+fn density_at(
+    neighbors: &Neighbors,
+    positions: &[Vec2],
+    mass_factors: &[f32],
+    i: usize,
+    kernels: &KernelCache,
+    min_dist_sq: f32,
+    particle_mass: f32,
+) -> f32 {
+    #[cfg(feature = "simd")]
+    if let Neighbors::Grid(grid) = neighbors {
+        return simd::density(
+            grid,
+            positions,
+            mass_factors,
+            i,
+            kernels.h,
+            min_dist_sq,
+            particle_mass,
+        );
+    }
+    scalar_density(
+        neighbors,
+        positions,
+        mass_factors,
+        i,
+        kernels,
+        min_dist_sq,
+        particle_mass,
+    )
+}
+
+/// Scalar form of `density_at`, one candidate at a time.
+#[inline(always)]
+fn scalar_density(
+    neighbors: &Neighbors,
+    positions: &[Vec2],
+    mass_factors: &[f32],
+    i: usize,
+    kernels: &KernelCache,
+    min_dist_sq: f32,
+    particle_mass: f32,
+) -> f32 {
+    let pos = positions[i];
+    let h_sq = kernels.h * kernels.h;
+    let mut d = 0.0;
+    neighbors.for_each_candidate(pos, |j| {
+        let dist_sq = pos.distance_squared(positions[j]);
+        if dist_sq < h_sq {
+            // The self-contribution stays at r = 0; only neighbor pairs are softened.
+            let kernel_dist_sq = if i == j {
+                dist_sq
+            } else {
+                dist_sq.max(min_dist_sq)
+            };
+            d += particle_mass * mass_factors[j] * kernels.poly6(kernel_dist_sq);
+        }
+    });
+    d
+}
+
+/// Shepard-filtered densities `ρ_i / Σ_j (m_j / ρ_j) W_ij` of the first `n` particles, where
+/// `densities` holds their plain summed densities. The sum is the kernel's partition of unity,
+/// 1 in the bulk and less where the support is cut off by a free surface. Neighbor densities
+/// are raised to `min_density` like everywhere else they divide.
+#[allow(clippy::too_many_arguments)]
+fn shepard_filter(
+    neighbors: &Neighbors,
+    positions: &[Vec2],
+    mass_factors: &[f32],
+    densities: &[f32],
+    n: usize,
+    kernels: &KernelCache,
+    min_dist_sq: f32,
+    particle_mass: f32,
+    min_density: f32,
+) -> Vec<f32> {
+    let h_sq = kernels.h * kernels.h;
+    (0..n)
+        .into_par_iter()
         .map(|i| {
-            (
-                Sprite {
-                    image: tex.clone(),
-                    custom_size: Some(Vec2::splat(PARTICLE_RADIUS * 2.5)),
-                    color: Color::srgb(0.2, 0.5, 1.0),
-                    ..default()
-                },
-                Transform::from_translation(sim.positions[i].extend(0.0)),
-                ParticleId(i),
+            let pos = positions[i];
+            let mut weight = 0.0;
+            neighbors.for_each_candidate(pos, |j| {
+                let dist_sq = pos.distance_squared(positions[j]);
+                if dist_sq < h_sq {
+                    let kernel_dist_sq = if i == j {
+                        dist_sq
+                    } else {
+                        dist_sq.max(min_dist_sq)
+                    };
+                    weight += particle_mass * mass_factors[j] / densities[j].max(min_density)
+                        * kernels.poly6(kernel_dist_sq);
+                }
+            });
+            if weight > 0.0 {
+                densities[i] / weight
+            } else {
+                densities[i]
+            }
+        })
+        .collect()
+}
+
+/// Sets `config.target_density` to the mean density of `sim`'s active particles where they
+/// are, so a layout that has not moved yet starts at rest density.
+pub fn calibrate_target_density(sim: &mut FluidSimulation, config: &mut FluidConfig) {
+    let n = sim.active_count.min(sim.positions.len());
+    let mean = mean_density_in_place(sim, config);
+    if mean > 0.0 {
+        info!(
+            "Calibrated target_density from {} to {mean} over {n} particles",
+            config.target_density
+        );
+        config.target_density = mean;
+    }
+}
+
+/// Mean density of `sim`'s active particles where they are, computed like the density pass
+/// on a freshly rebuilt grid.
+fn mean_density_in_place(sim: &mut FluidSimulation, config: &FluidConfig) -> f32 {
+    let n = sim.active_count.min(sim.positions.len());
+    let h = config.smoothing_radius;
+    sim.configure_grid(
+        h,
+        config.grid_scan_radius,
+        config.grid_cell_size,
+        config.grid_margin,
+    );
+    sim.bin_particles(n);
+    let min_dist = h * config.min_dist_fraction.clamp(0.0, 1.0);
+    let kernels = sim.kernels(h, config.kernel_table_size);
+    let neighbors = Neighbors::Grid(sim.grid_view());
+    let densities: Vec<f32> = (0..n)
+        .into_par_iter()
+        .map(|i| {
+            density_at(
+                &neighbors,
+                &sim.positions,
+                &sim.mass_factors,
+                i,
+                &kernels,
+                min_dist * min_dist,
+                config.particle_mass,
             )
         })
         .collect();
-    commands.spawn_batch(bundles);
+    par_mean(&densities)
 }
 
-/// Synchronizes particle visual representation with simulation state.
-/// Updates positions and colors particles based on velocity.
-pub fn sync_rendering(
-    sim: Res<FluidSimulation>,
-    mut query: Query<(&mut Transform, &mut Sprite, &ParticleId)>,
-) {
-    let max_sq = 400.0f32.powi(2);
-    query.par_iter_mut().for_each(|(mut t, mut s, pid)| {
-        let i = pid.0;
-        if let Some(pos) = sim.positions.get(i) {
-            t.translation.x = pos.x;
-            t.translation.y = pos.y;
-            t.translation.z = (i % 100) as f32 * 0.001;
+/// Samples per density probe of `measure_wave_speed` across the middle half of the fluid's
+/// depth, averaged to smooth out particle noise.
+const WAVE_PROBE_SAMPLES: usize = 9;
+
+/// Impulse response: lays out a weightless grid in `sim`'s domain at its own rest density,
+/// pushes the particles of one column of a copy a third of a smoothing radius along +x and
+/// tracks how far the copy's density, averaged over the depth, exceeds the original's at two
+/// probes further along. The excess is the pulse alone, without the layout's own relaxation.
+/// Returns the speed of its peak between the probes, to compare with the `pressure_eos`
+/// sound speed, or `None` if it did not reach the far probe after the near one.
+///
+/// The pulse is followed until a wave of half the sound speed would reach the far probe,
+/// which is before one at the full speed, reflected off the wall behind the column, reaches
+/// the near one.
+pub fn measure_wave_speed(sim: &FluidSimulation, config: &FluidConfig) -> Option<f32> {
+    let mut config = FluidConfig {
+        gravity_enabled: false,
+        ..config.clone()
+    };
+    let layout = || {
+        let mut run = FluidSimulation::with_domain(
+            config.seed,
+            sim.domain,
+            sim.base_cell_size,
+            sim.particle_radius,
+        );
+        run.reset_to_grid(config.seed, config.init_jitter, &[]);
+        run
+    };
+    let (mut still, mut pushed) = (layout(), layout());
+    let target_density = mean_density_in_place(&mut still, &config);
+    if target_density > 0.0 {
+        config.target_density = target_density;
+    }
+    let config = &config;
+    let sound_speed = config
+        .pressure_eos
+        .sound_speed(config.target_density, config.pressure_multiplier);
+    if sound_speed <= 0.0 {
+        return None;
+    }
+    let dt = 0.002 * config.time_scale;
+    let h = config.smoothing_radius;
+    let elements = SceneElements::default();
+    let step = |run: &mut FluidSimulation| {
+        step_simulation(
+            run,
+            config,
+            &elements,
+            Interaction::default(),
+            dt,
+            NeighborSource::Grid,
+            &mut PhaseTimes::default(),
+            None,
+            None,
+        )
+    };
+    deterministic_pool().install(|| {
+        let n = still.active_count.min(still.positions.len());
+        let (lo, hi) = still.bounding_box();
+        let column = lo.x + (hi.x - lo.x) * 0.3;
+        let probes = [0.45, 0.65].map(|f| lo.x + (hi.x - lo.x) * f);
+        for pos in &mut pushed.positions[..n] {
+            if (pos.x - column).abs() < h * 0.5 {
+                pos.x += h / 3.0;
+            }
         }
-        if let Some(vel) = sim.velocities.get(i) {
-            let n = (vel.length_squared() / max_sq).clamp(0.0, 1.0).sqrt();
-            s.color = Color::mix(&Color::srgb(0.1, 0.2, 0.9), &Color::srgb(1.0, 1.0, 1.0), n); // Velocity-based coloring
+
+        let probe_density = |run: &FluidSimulation, x: f32| {
+            (0..WAVE_PROBE_SAMPLES)
+                .map(|k| {
+                    let f = 0.25 + 0.5 * k as f32 / (WAVE_PROBE_SAMPLES - 1) as f32;
+                    run.sample_density_at(Vec2::new(x, lo.y + (hi.y - lo.y) * f), config)
+                })
+                .sum::<f32>()
+                / WAVE_PROBE_SAMPLES as f32
+        };
+        let steps = ((probes[1] - column) / (0.5 * sound_speed) / dt).ceil() as usize;
+        // Step and size of the highest density excess over the unpushed run at each probe.
+        let mut peaks = [(0, f32::MIN); 2];
+        for k in 1..=steps {
+            step(&mut still);
+            step(&mut pushed);
+            for (peak, &x) in peaks.iter_mut().zip(&probes) {
+                let excess = probe_density(&pushed, x) - probe_density(&still, x);
+                if excess > peak.1 {
+                    *peak = (k, excess);
+                }
+            }
         }
-    });
+        let travel = (peaks[1].0 as f32 - peaks[0].0 as f32) * dt;
+        (travel > 0.0).then(|| (probes[1] - probes[0]) / travel)
+    })
 }
+
+/// Time-reversal check: runs `steps` steps forward from a grid layout (with `init_jitter`
+/// and `initial_velocity`), negates every velocity and runs `steps` more, then returns the
+/// mean and largest distance of the particles from their starting positions. An ideal
+/// reversible solver returns exactly; run with zero viscosity, no damping and elastic walls,
+/// the error measures the integrator's asymmetry and the growth of rounding errors.
+/// The grid start avoids the pressure shock of overlapping random positions. The layout
+/// uses `sim`'s domain, whose walls are then moved `2 * smoothing_radius` out: the default
+/// lattice is taller than the domain, and a wall clamping a particle cannot be undone.
+pub fn time_reversal_error(
+    sim: &FluidSimulation,
+    config: &FluidConfig,
+    steps: usize,
+) -> (f32, f32) {
+    let dt = 0.002 * config.time_scale;
+    let mut run = FluidSimulation::with_domain(
+        config.seed,
+        sim.domain,
+        sim.base_cell_size,
+        sim.particle_radius,
+    );
+    run.reset_to_grid(config.seed, config.init_jitter, &[]);
+    run.domain = run.domain.inflate(2.0 * config.smoothing_radius);
+    run.set_grid_cell_size(run.grid_cell_size);
+    run.apply_initial_velocity(config.initial_velocity, config.seed);
+    let mut config = config.clone();
+    if config.auto_calibrate_target_density {
+        calibrate_target_density(&mut run, &mut config);
+    }
+    let config = &config;
+    let n = run.active_count.min(run.positions.len());
+    let start = run.positions[..n].to_vec();
+    deterministic_pool().install(|| {
+        for _ in 0..steps {
+            run.step(config, dt);
+        }
+        // One more step kicks the velocities with the forces at the turning point; reversing
+        // those from the turning point makes the first backward kick undo it exactly.
+        let turn = run.positions.clone();
+        run.step(config, dt);
+        run.positions = turn;
+        for vel in &mut run.velocities[..n] {
+            *vel = -*vel;
+        }
+        for _ in 0..steps {
+            run.step(config, dt);
+        }
+    });
+    // Open boundaries can remove particles, so only the survivors' slots are compared.
+    let n = n.min(run.active_count);
+    let errors: Vec<f32> = start[..n]
+        .iter()
+        .zip(&run.positions[..n])
+        .map(|(a, b)| a.distance(*b))
+        .collect();
+    (
+        par_mean(&errors),
+        errors.iter().copied().fold(0.0, f32::max),
+    )
+}
+
+impl FluidSimulation {
+    /// Advances the simulation by one CPU step of length `dt`, without scene elements or
+    /// interaction: the headless entry point for library users.
+    pub fn step(&mut self, config: &FluidConfig, dt: f32) {
+        step_simulation(
+            self,
+            config,
+            &SceneElements::default(),
+            Interaction::default(),
+            dt,
+            NeighborSource::Grid,
+            &mut PhaseTimes::default(),
+            None,
+            None,
+        );
+    }
+
+    /// Like `step`, but every SPH pass scans all pairs of active particles instead of the
+    /// grid. O(n²) and only meant as the reference the grid-accelerated step is checked
+    /// against; the grid is still rebuilt for the queries outside the step.
+    pub fn step_bruteforce(&mut self, config: &FluidConfig, dt: f32) {
+        step_simulation(
+            self,
+            config,
+            &SceneElements::default(),
+            Interaction::default(),
+            dt,
+            NeighborSource::AllPairs,
+            &mut PhaseTimes::default(),
+            None,
+            None,
+        );
+    }
+}
+
+/// Advances the simulation by one step of length `dt`, with neighbors from `source`.
+/// The time spent in each phase is added to `times`, and the selected particle's state is
+/// recorded into `probe`. With `gpu` the neighbor force sums of a share of the particles come
+/// from its compute passes, which run while the CPU computes the rest.
+#[allow(clippy::too_many_arguments)]
+fn step_simulation(
+    sim: &mut FluidSimulation,
+    config: &FluidConfig,
+    elements: &SceneElements,
+    interaction: Interaction,
+    dt: f32,
+    source: NeighborSource,
+    times: &mut PhaseTimes,
+    mut probe: Option<&mut ParticleProbe>,
+    mut gpu: Option<&mut GpuSolver>,
+) {
+    let mut lap = Instant::now();
+    remove_escaped(sim, config.boundaries);
+    sim.prev_positions.clone_from(&sim.positions);
+    sim.interpolation_alpha = 1.0;
+    let n = sim.active_count.min(sim.positions.len());
+
+    let h = config.smoothing_radius;
+    let kernels = sim.kernels(h, config.kernel_table_size);
+    let min_dist = h * config.min_dist_fraction.clamp(0.0, 1.0);
+    let min_dist_sq = min_dist * min_dist;
+    let target_density = config.target_density;
+    let pressure_k = config.pressure_multiplier;
+    let pressure_eos = config.pressure_eos;
+    let clamp_pressure = config.clamp_negative_pressure;
+    let background_pressure = config.background_pressure;
+    let viscosity_mu = config.viscosity_strength;
+    let viscosity_kernel = config.viscosity_kernel;
+    let sound_speed = pressure_eos.sound_speed(target_density, pressure_k);
+    let gravity_as_force = config.gravity_mode == GravityMode::Force;
+    let gravity = config.current_gravity();
+    let interact_rad = config.mouse_radius;
+    let interact_str = config.mouse_strength;
+    let surface_density = config.surface_threshold * target_density;
+    let min_density = config.min_density();
+    let surface_cohesion = config.surface_cohesion;
+    let shepard_correction = config.shepard_correction;
+    let pressure_of = |d: f32| {
+        let pressure = pressure_eos.pressure(d, target_density, pressure_k);
+        let pressure = if clamp_pressure {
+            pressure.max(0.0)
+        } else {
+            pressure
+        };
+        pressure + background_pressure
+    };
+
+    // Rebuild spatial grid for neighbor searches, unless it is still valid within the skin
+    sim.configure_grid(
+        h,
+        config.grid_scan_radius,
+        config.grid_cell_size,
+        config.grid_margin,
+    );
+    let skin = config.neighbor_skin.max(0.0);
+    if skin > 0.0 {
+        let needed = ((h + skin) / sim.grid_cell_size).ceil() as usize;
+        sim.grid_scan_radius = sim.grid_scan_radius.max(needed);
+    }
+    let overflow = if sim.grid_within_skin(n, skin) {
+        sim.grid_overflow
+    } else {
+        sim.bin_particles(n)
+    };
+    let grid_w = sim.grid_width_cells;
+    let cell_size = sim.grid_cell_size;
+    let off_x = sim.grid_offset_x;
+    let off_y = sim.grid_offset_y;
+    if overflow > 0 && sim.grid_overflow == 0 {
+        warn!(
+            "{overflow} particles lie beyond the {} grid margin; raise `grid_margin`",
+            sim.grid_margin
+        );
+    }
+    debug_assert!(
+        overflow == 0,
+        "{overflow} particles lie beyond the {} grid margin",
+        sim.grid_margin
+    );
+    sim.grid_overflow = overflow;
+
+    if config.verify_neighbors {
+        verify_neighbor_search(sim, h);
+    }
+    lap = times.lap(Phase::Grid, lap);
+
+    // Built from the fields directly so the particle buffers below stay mutably borrowable.
+    let grid_view = GridView {
+        cells: &sim.grid_map,
+        width: grid_w,
+        height: sim.grid_height_cells,
+        cell_size,
+        offset_x: off_x,
+        offset_y: off_y,
+        scan_radius: sim.grid_scan_radius,
+    };
+    let grid = match source {
+        NeighborSource::Grid => Neighbors::Grid(grid_view),
+        NeighborSource::AllPairs => Neighbors::All(n),
+    };
+    // Neighbor contributions scale with the neighbor's mass only, so each particle's
+    // acceleration stays independent of its own mass, as with uniform masses.
+    let mass_factors = &sim.mass_factors;
+    let flow_index = match config.viscosity_model {
+        ViscosityModel::Newtonian => None,
+        ViscosityModel::PowerLaw { n: index } => Some(index),
+    };
+
+    if config.predict_positions {
+        let positions = &sim.positions;
+        let velocities = &sim.velocities;
+        sim.predicted_positions[..n]
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, predicted)| *predicted = positions[i] + velocities[i] * dt);
+    }
+    lap = times.lap(Phase::Forces, lap);
+
+    // Extra iterations re-evaluate density and forces at positions predicted from the
+    // previous iteration's forces, relaxing towards the target density. The grid is not
+    // rebuilt; the final forces are applied to the unpredicted state.
+    let iterations = config.pressure_iterations.max(1);
+    for iteration in 0..iterations {
+        let positions = if iteration == 0 && !config.predict_positions {
+            &sim.positions
+        } else {
+            &sim.predicted_positions
+        };
+
+        // The GPU sums the forces of a leading share of the particles in the background.
+        let gpu_count = gpu.as_deref_mut().map_or(0, |gpu| {
+            gpu.submit(
+                config,
+                &grid_view,
+                positions,
+                &sim.velocities,
+                mass_factors,
+                n,
+            )
+        });
+        // Calculate density and pressure for each particle
+        sim.densities[..n]
+            .par_iter_mut()
+            .zip(&mut sim.pressures[..n])
+            .zip(&mut sim.is_surface[..n])
+            .enumerate()
+            .for_each(|(i, ((density_out, pressure_out), surface_out))| {
+                let d = density_at(
+                    &grid,
+                    positions,
+                    mass_factors,
+                    i,
+                    &kernels,
+                    min_dist_sq,
+                    config.particle_mass,
+                );
+                *density_out = d;
+                *pressure_out = pressure_of(d);
+                *surface_out = d < surface_density;
+            });
+
+        // The surface flags keep the raw densities, which show the truncated support.
+        if shepard_correction {
+            let corrected = shepard_filter(
+                &grid,
+                positions,
+                mass_factors,
+                &sim.densities,
+                n,
+                &kernels,
+                min_dist_sq,
+                config.particle_mass,
+                min_density,
+            );
+            sim.densities[..n]
+                .par_iter_mut()
+                .zip(&mut sim.pressures[..n])
+                .zip(corrected)
+                .for_each(|((density_out, pressure_out), d)| {
+                    *density_out = d;
+                    *pressure_out = pressure_of(d);
+                });
+        }
+
+        // Shear rate estimate |∇v| ≈ Σ (m / ρ_j) |v_j - v_i| |∇W| feeds the power-law viscosity.
+        if let Some(flow_index) = flow_index {
+            let densities = &sim.densities;
+            let velocities = &sim.velocities;
+            sim.viscosity_factors[..n]
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(i, factor_out)| {
+                    let pos = positions[i];
+                    let vel = velocities[i];
+                    let mut shear_rate = 0.0;
+                    grid.for_each_candidate(pos, |j| {
+                        let dist = pos.distance(positions[j]);
+                        if i != j && dist < h {
+                            shear_rate += config.particle_mass * mass_factors[j]
+                                / densities[j].max(min_density)
+                                * (velocities[j] - vel).length()
+                                * kernels.spiky_gradient(dist);
+                        }
+                    });
+                    *factor_out = shear_rate
+                        .max(1e-3)
+                        .powf(flow_index - 1.0)
+                        .clamp(VISCOSITY_FACTOR_MIN, VISCOSITY_FACTOR_MAX);
+                });
+        }
+        lap = times.lap(Phase::Density, lap);
+
+        let densities = &sim.densities;
+        let pressures = &sim.pressures;
+        let velocities = &sim.velocities;
+        let viscosity_factors = &sim.viscosity_factors;
+        let is_surface = &sim.is_surface;
+        let gravity_regions = config.active_gravity_regions(&elements.gravity_regions);
+
+        // Calculate forces (pressure, viscosity, interaction), with the neighbor sums from
+        // the GPU where `sums` is given.
+        let force_terms = |i: usize, sums: Option<&NeighborSums>| {
+            let pos = positions[i];
+            let dens = densities[i].max(min_density);
+            let press = pressures[i];
+            let vel = velocities[i];
+
+            let mut f_pressure = Vec2::ZERO;
+            let mut f_viscosity = Vec2::ZERO;
+            let mut to_neighbors = Vec2::ZERO;
+
+            if let Some(sums) = sums {
+                f_pressure = sums.pressure;
+                f_viscosity = sums.viscosity;
+                to_neighbors = sums.to_neighbors;
+            } else {
+                grid.for_each_candidate(pos, |j| {
+                    if i == j {
+                        return;
+                    }
+                    let other_pos = positions[j];
+                    let dist = pos.distance(other_pos);
+
+                    if dist < h && dist > 0.0001 {
+                        let dir = (other_pos - pos) / dist;
+                        let safe_dens = densities[j].max(min_density);
+
+                        let kernel_dist = dist.max(min_dist);
+                        let slope = kernels.spiky_gradient(kernel_dist);
+                        let pressure_term =
+                            (press / dens / dens) + (pressures[j] / safe_dens / safe_dens);
+                        f_pressure += -dens
+                            * config.particle_mass
+                            * mass_factors[j]
+                            * pressure_term
+                            * slope
+                            * dir;
+
+                        let vel_diff = velocities[j] - vel;
+                        match viscosity_kernel {
+                            ViscosityKernel::Laplacian => {
+                                let laplacian = kernels.viscosity_laplacian(kernel_dist);
+                                let mu = if flow_index.is_some() {
+                                    viscosity_mu
+                                        * 0.5
+                                        * (viscosity_factors[i] + viscosity_factors[j])
+                                } else {
+                                    viscosity_mu
+                                };
+                                f_viscosity += vel_diff
+                                    * mu
+                                    * laplacian
+                                    * (1.0 / safe_dens)
+                                    * config.particle_mass
+                                    * mass_factors[j];
+                            }
+                            // Same form as the pressure term, with Π_ij in place of p/ρ².
+                            ViscosityKernel::Artificial { alpha } => {
+                                let pi = artificial_viscosity(
+                                    (-vel_diff).dot(pos - other_pos),
+                                    dist * dist,
+                                    h,
+                                    alpha,
+                                    sound_speed,
+                                    0.5 * (dens + safe_dens),
+                                );
+                                f_viscosity += -dens
+                                    * config.particle_mass
+                                    * mass_factors[j]
+                                    * pi
+                                    * slope
+                                    * dir;
+                            }
+                        }
+
+                        to_neighbors += dir * kernels.poly6(dist * dist);
+                    }
+                });
+            }
+
+            let f_interaction = interaction.force_at(pos, interact_rad, interact_str);
+
+            // Surface particles only have neighbors on one side; pull them back toward the bulk.
+            let f_cohesion = if is_surface[i] {
+                to_neighbors.normalize_or_zero() * surface_cohesion * dens
+            } else {
+                Vec2::ZERO
+            };
+
+            let f_gravity = if gravity_as_force {
+                gravity_at(gravity_regions, pos, gravity) * dens
+            } else {
+                Vec2::ZERO
+            };
+
+            ForceBreakdown {
+                pressure: f_pressure,
+                viscosity: f_viscosity,
+                gravity: f_gravity,
+                interaction: f_interaction,
+                cohesion: f_cohesion,
+            }
+        };
+        sim.forces[gpu_count..n]
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(k, force_out)| *force_out = force_terms(gpu_count + k, None).total());
+        // The CPU falls back to summing the GPU's share itself if the device failed.
+        let gpu_sums: &[NeighborSums] = match gpu.as_deref_mut() {
+            Some(gpu) if gpu_count > 0 => {
+                if gpu.finish() {
+                    &gpu.sums
+                } else {
+                    &[]
+                }
+            }
+            _ => &[],
+        };
+        sim.forces[..gpu_count]
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, force_out)| *force_out = force_terms(i, gpu_sums.get(i)).total());
+        // Recomputed for the selected particle alone, matching the final iteration's forces.
+        if let Some(probe) = probe.as_deref_mut()
+            && let Some(i) = probe.selected.filter(|&i| i < n)
+        {
+            probe.forces = force_terms(i, gpu_sums.get(i));
+            if !gravity_as_force {
+                probe.forces.gravity = gravity_at(gravity_regions, positions[i], gravity)
+                    * densities[i].max(min_density);
+            }
+        }
+
+        if iteration + 1 < iterations {
+            let positions = &sim.positions;
+            let velocities = &sim.velocities;
+            let forces = &sim.forces;
+            let densities = &sim.densities;
+            let gravity_regions = config.active_gravity_regions(&elements.gravity_regions);
+            sim.predicted_positions[..n]
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(i, predicted)| {
+                    let gravity_accel = if gravity_as_force {
+                        Vec2::ZERO
+                    } else {
+                        gravity_at(gravity_regions, positions[i], gravity)
+                    };
+                    let acceleration = forces[i] / densities[i].max(min_density) + gravity_accel;
+                    *predicted = positions[i] + (velocities[i] + acceleration * dt) * dt;
+                });
+        }
+        lap = times.lap(Phase::Forces, lap);
+    }
+    sim.ages[..n].par_iter_mut().for_each(|age| *age += dt);
+    sim.elapsed += dt;
+
+    let gravity_regions = config.active_gravity_regions(&elements.gravity_regions);
+    let freeze_regions = &elements.freeze_regions;
+    let radius = sim.particle_radius;
+    let colliders = Colliders {
+        segments: &elements.segments,
+        obstacles: &elements.obstacles,
+        wheels: &elements.rotating_obstacles,
+        time: sim.elapsed,
+        radius,
+        bounds: sim.domain.inflate(-radius),
+        walls: config.boundaries,
+        restitution: config.boundary_damping,
+        friction: config.boundary_friction.clamp(0.0, 1.0),
+        iterations: config.collision_iterations,
+    };
+    sim.positions[..n]
+        .par_iter_mut()
+        .zip(&mut sim.velocities[..n])
+        .zip(&sim.forces[..n])
+        .zip(&sim.densities[..n])
+        .for_each(|(((pos, vel), force), dens)| {
+            if frozen_at(freeze_regions, *pos) {
+                *vel = Vec2::ZERO;
+                return;
+            }
+            let gravity_accel = if gravity_as_force {
+                Vec2::ZERO
+            } else {
+                gravity_at(gravity_regions, *pos, gravity)
+            };
+            let acceleration = *force / dens.max(min_density) + gravity_accel;
+            let start = *pos;
+            *vel += acceleration * dt;
+            match config.integration_order {
+                IntegrationOrder::DampBeforePosition => {
+                    *vel *= config.linear_damping;
+                    *pos += *vel * dt;
+                }
+                IntegrationOrder::DampAfterPosition => {
+                    *pos += *vel * dt;
+                    *vel *= config.linear_damping;
+                }
+            }
+
+            let Colliders {
+                restitution,
+                friction,
+                ..
+            } = colliders;
+
+            // Continuous collision: stop at the first collider the step's path crosses, so
+            // fast particles cannot tunnel through thin walls or obstacles before the
+            // overlap check runs.
+            if let Some((stop, normal)) = colliders.sweep(start, *pos) {
+                *pos = stop;
+                wall_contact(vel, normal, restitution, friction);
+            }
+
+            // A push out of one collider can land inside another, e.g. in the notch
+            // between two obstacles, so passes repeat until none finds an overlap.
+            for _ in 0..colliders.iterations.max(1) {
+                if !colliders.resolve(pos, vel) {
+                    break;
+                }
+            }
+        });
+
+    // Uniform rescale keeps the relative flow structure, unlike clamping each particle.
+    if let Some(cap) = config.global_speed_cap {
+        let max_speed = par_max_vec2_len(&sim.velocities[..n]);
+        if cap >= 0.0 && max_speed > cap {
+            let factor = cap / max_speed;
+            sim.velocities[..n]
+                .par_iter_mut()
+                .for_each(|vel| *vel *= factor);
+        }
+    }
+    times.lap(Phase::Integration, lap);
+
+    if let Some(probe) = probe {
+        match probe.selected {
+            Some(i) if i < n => {
+                probe.position = sim.positions[i];
+                probe.velocity = sim.velocities[i];
+                probe.density = sim.densities[i];
+                probe.pressure = sim.pressures[i];
+            }
+            _ => probe.selected = None,
+        }
+    }
+}
+
+/// Removes the particles the previous step left outside the domain through an open wall,
+/// before the grid is rebuilt, so later passes only ever see valid indices. Every active
+/// particle may escape; the step then has nothing to do.
+fn remove_escaped(sim: &mut FluidSimulation, walls: DomainBoundaries) {
+    if !walls.any_open() {
+        return;
+    }
+    let bounds = sim.domain.inflate(-sim.particle_radius);
+    let n = sim.active_count.min(sim.positions.len());
+    let escaped: Vec<usize> = (0..n)
+        .filter(|&i| !bounds.contains(sim.positions[i]))
+        .collect();
+    // Descending order only ever moves particles that are staying into the freed slots.
+    for &i in escaped.iter().rev() {
+        let pos = sim.positions[i];
+        let sink = if pos.x < bounds.min.x {
+            ParticleSink::LeftWall
+        } else if pos.x > bounds.max.x {
+            ParticleSink::RightWall
+        } else if pos.y < bounds.min.y {
+            ParticleSink::BottomWall
+        } else {
+            ParticleSink::TopWall
+        };
+        sim.flow_log.push(FlowEvent::Removed {
+            sink,
+            time: sim.elapsed,
+            age: sim.ages[i],
+        });
+        sim.remove_active(i);
+    }
+}
+
+/// Wraps `v` into `[min, max)`, for periodic walls.
+#[inline(always)]
+fn wrap(v: f32, min: f32, max: f32) -> f32 {
+    min + (v - min).rem_euclid((max - min).max(f32::EPSILON))
+}
+
+/// Sets up the initial scene with the camera, centered on the domain (both halves with
+/// `--compare`), and HUD.
+pub fn setup_scene(
+    mut commands: Commands,
+    sim: Res<FluidSimulation>,
+    comparison: Option<Res<ComparisonSim>>,
+    render_config: Res<RenderConfig>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let domain = comparison.map_or(sim.domain, |other| sim.domain.union(other.0.domain));
+    let center = domain.center() * render_config.world_scale;
+    commands.spawn((Camera2d, Transform::from_translation(center.extend(0.0))));
+    // Resized to the grid by `update_occupancy_heatmap` once it is shown.
+    let mut heatmap = Image::new_fill(
+        Extent3d::default(),
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    heatmap.sampler = ImageSampler::nearest();
+    commands.spawn((
+        Sprite::from_image(images.add(heatmap)),
+        Visibility::Hidden,
+        OccupancyHeatmap,
+    ));
+    // Resized to the domain by `update_trails` once they are shown.
+    let trails = Image::new_fill(
+        Extent3d::default(),
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    commands.spawn((
+        Sprite::from_image(images.add(trails)),
+        Visibility::Hidden,
+        TrailCanvas::default(),
+    ));
+    commands.spawn((
+        Text::default(),
+        TextFont {
+            font_size: 16.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(8.0),
+            left: Val::Px(8.0),
+            ..default()
+        },
+        InitialConditionLabel,
+    ));
+    commands.spawn((
+        Text::default(),
+        TextFont {
+            font_size: 16.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(8.0),
+            right: Val::Px(8.0),
+            ..default()
+        },
+        DiagnosticsLabel,
+    ));
+    for label in [
+        ColorLegendLabel::Min,
+        ColorLegendLabel::Max,
+        ColorLegendLabel::Title,
+    ] {
+        commands.spawn((
+            Text2d::default(),
+            TextFont {
+                font_size: 14.0,
+                ..default()
+            },
+            Visibility::Hidden,
+            label,
+        ));
+    }
+}
+
+/// Shows the particle mass and the mean-to-target density ratio in the HUD.
+/// Density scales linearly with mass, so a ratio away from 1.0 after changing the mass
+/// explains why the fluid contracts (below 1) or expands (above 1).
+/// Also shows the sound speed and CFL number from `SimStats`, which bound the time step,
+/// and the particle throughput from the `FlowMeter`. With `--compare` it adds the mean
+/// separation of same-index particles between the two instances.
+pub fn update_diagnostics_label(
+    sim: Res<FluidSimulation>,
+    config: Res<FluidConfig>,
+    stats: Res<SimStats>,
+    flow: Res<FlowMeter>,
+    comparison: Option<Res<ComparisonSim>>,
+    mut labels: Query<&mut Text, With<DiagnosticsLabel>>,
+) {
+    let n = sim.active_count.min(sim.densities.len());
+    let mean_density = par_mean(&sim.densities[..n]);
+    let ratio = if config.target_density > 0.0 {
+        mean_density / config.target_density
+    } else {
+        0.0
+    };
+    let max_cell = sim.grid_map.iter().map(Vec::len).max().unwrap_or(0);
+    let walls = config.boundaries;
+    for mut text in &mut labels {
+        text.0 = format!(
+            "Mass: {:.3} (=/-)\nMean density / target: {ratio:.2}\nDensest grid cell: {max_cell}\n\
+             Gravity: {} (Z), tilt {:.0}° (,/.)\nWalls (1-4): left {}, right {}, bottom {}, top {}\n\
+             Sound speed: {:.3}, CFL: {:.3}\n\
+             Inflow: {:.1}/s, outflow: {:.1}/s, mean residence: {:.2}s",
+            config.particle_mass,
+            if config.gravity_enabled { "on" } else { "off" },
+            config.gravity_angle,
+            walls.left.label(),
+            walls.right.label(),
+            walls.bottom.label(),
+            walls.top.label(),
+            stats.sound_speed,
+            stats.cfl,
+            flow.inflow_rate,
+            flow.outflow_rate,
+            flow.mean_residence_time
+        );
+        if comparison.is_some() {
+            text.0
+                .push_str(&format!("\nA/B separation: {:.3}", stats.mean_separation));
+        }
+    }
+}
+
+/// Shows the selected initial condition in the HUD.
+pub fn update_initial_condition_label(
+    selected: Res<SelectedInitialCondition>,
+    mut labels: Query<&mut Text, With<InitialConditionLabel>>,
+) {
+    for mut text in &mut labels {
+        text.0 = format!(
+            "Initial condition: {} (C: cycle, R: reset)",
+            selected.0.label()
+        );
+    }
+}
+
+/// Directory screenshots are written to, relative to the working directory.
+const SCREENSHOT_DIR: &str = "screenshots";
+
+/// Saves the rendered window as `screenshots/frame-<frame>-<unix secs>.png`.
+/// Press 'P' for a single screenshot, or Shift+P to toggle a burst that captures every
+/// `screenshot_interval` frames, e.g. to assemble a video externally.
+/// Ctrl+P toggles capture mode, which saves every frame (see `RenderConfig::capture`).
+/// Capture is asynchronous; the file is written a frame or two later.
+pub fn capture_screenshots(
+    mut commands: Commands,
+    input: Res<ButtonInput<KeyCode>>,
+    mut render_config: ResMut<RenderConfig>,
+    config: Res<FluidConfig>,
+    frame: Res<FrameCount>,
+    mut burst: Local<bool>,
+) {
+    let mut capture = false;
+    if input.just_pressed(KeyCode::KeyP) {
+        if input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+            let render_config = render_config.bypass_change_detection();
+            render_config.capture = !render_config.capture;
+            if render_config.capture {
+                // Simulated seconds shown per second of playback.
+                let step_time = 0.002 * config.time_scale;
+                let playback = render_config.capture_substeps.max(1) as f32
+                    * step_time
+                    * render_config.capture_fps;
+                info!(
+                    "Capture started: {} steps per frame, plays back at {playback:.3}x at {} fps",
+                    render_config.capture_substeps.max(1),
+                    render_config.capture_fps
+                );
+            } else {
+                info!("Capture stopped");
+            }
+        } else if input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+            *burst = !*burst;
+            info!(
+                "Burst screenshots {}",
+                if *burst { "started" } else { "stopped" }
+            );
+        } else {
+            capture = true;
+        }
+    }
+    if render_config.capture
+        || *burst
+            && frame
+                .0
+                .is_multiple_of(render_config.screenshot_interval.max(1))
+    {
+        capture = true;
+    }
+    if !capture {
+        return;
+    }
+
+    if let Err(e) = std::fs::create_dir_all(SCREENSHOT_DIR) {
+        error!("Cannot create '{SCREENSHOT_DIR}' directory: {e}");
+        return;
+    }
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let path = format!("{SCREENSHOT_DIR}/frame-{:06}-{timestamp}.png", frame.0);
+    commands
+        .spawn(Screenshot::primary_window())
+        .observe(save_to_disk(path));
+}
+
+/// Directory velocity field exports are written to, relative to the working directory.
+const EXPORT_DIR: &str = "exports";
+
+/// Kinetic energy spectrum of `sim`'s SPH velocity field sampled on a square grid of
+/// `resolution` cells per side (rounded up to a power of two), see `energy_spectrum`.
+pub fn velocity_spectrum(
+    sim: &FluidSimulation,
+    config: &FluidConfig,
+    resolution: usize,
+) -> Vec<(f32, f32)> {
+    let cells = resolution.max(2).next_power_of_two();
+    let field = sim.sample_velocity_cells(cells, cells, config);
+    energy_spectrum(&field, cells, cells, sim.domain.size())
+}
+
+/// Press 'X' to write the SPH velocity field, sampled every
+/// `RenderConfig::velocity_field_resolution` pixels, to `exports/` as `x,y,vx,vy` CSV rows
+/// in simulation units, e.g. for quiver plots. 'Shift+X' writes its kinetic energy spectrum
+/// on a `spectrum_resolution` grid instead, as `k,energy` rows with `k` in radians per
+/// simulation unit; the domain is treated as periodic, so walls leak some energy to high `k`.
+pub fn export_velocity_field(
+    input: Res<ButtonInput<KeyCode>>,
+    sim: Res<FluidSimulation>,
+    config: Res<FluidConfig>,
+    render_config: Res<RenderConfig>,
+    frame: Res<FrameCount>,
+) {
+    if !input.just_pressed(KeyCode::KeyX) {
+        return;
+    }
+    if let Err(e) = std::fs::create_dir_all(EXPORT_DIR) {
+        error!("Cannot create '{EXPORT_DIR}' directory: {e}");
+        return;
+    }
+
+    if input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        let spectrum = velocity_spectrum(&sim, &config, render_config.spectrum_resolution);
+        let mut csv = String::from("k,energy\n");
+        for (k, energy) in &spectrum {
+            csv.push_str(&format!("{k},{energy}\n"));
+        }
+        let path = format!("{EXPORT_DIR}/spectrum-{:06}.csv", frame.0);
+        match std::fs::write(&path, csv) {
+            Ok(()) => info!("Wrote {} spectrum shells to {path}", spectrum.len()),
+            Err(e) => error!("Cannot write '{path}': {e}"),
+        }
+        return;
+    }
+
+    let resolution = render_config.velocity_field_resolution.max(1.0) / render_config.world_scale;
+    let field = sim.sample_velocity_field(resolution, &config);
+    let mut csv = String::from("x,y,vx,vy\n");
+    for (p, v) in &field {
+        csv.push_str(&format!("{},{},{},{}\n", p.x, p.y, v.x, v.y));
+    }
+    let path = format!("{EXPORT_DIR}/velocity-field-{:06}.csv", frame.0);
+    match std::fs::write(&path, csv) {
+        Ok(()) => info!("Wrote {} velocity samples to {path}", field.len()),
+        Err(e) => error!("Cannot write '{path}': {e}"),
+    }
+}
+
+/// Press 'O' to save the main instance, its config and every scene element to
+/// `DEFAULT_SCENE_PATH`, and 'L' to load that file back, replacing all of them.
+pub fn save_load_scene(
+    input: Res<ButtonInput<KeyCode>>,
+    mut sim: ResMut<FluidSimulation>,
+    mut config: ResMut<FluidConfig>,
+    mut elements: ResMut<SceneElements>,
+    mut render_config: ResMut<RenderConfig>,
+) {
+    if input.just_pressed(KeyCode::KeyO) {
+        let scene = SceneFile::capture(&sim, &config, &elements);
+        match save_scene(DEFAULT_SCENE_PATH, &scene) {
+            Ok(()) => info!(
+                "Saved {} particles to {DEFAULT_SCENE_PATH}",
+                scene.particles.positions.len()
+            ),
+            Err(e) => error!("Cannot save '{DEFAULT_SCENE_PATH}': {e}"),
+        }
+    } else if input.just_pressed(KeyCode::KeyL) {
+        match load_scene(DEFAULT_SCENE_PATH) {
+            Ok(scene) => {
+                let (loaded_config, loaded_elements) = scene.restore(&mut sim);
+                *config = loaded_config;
+                *elements = loaded_elements;
+                // Respawns the sprites, whose size follows the particle radius.
+                render_config.set_changed();
+                info!(
+                    "Loaded {} particles from {DEFAULT_SCENE_PATH}",
+                    sim.live_count
+                );
+            }
+            Err(e) => error!("Cannot load '{DEFAULT_SCENE_PATH}': {e}"),
+        }
+    }
+}
+
+/// Loads the configured particle texture, falling back to the default one if the file is missing.
+fn load_particle_texture(asset_server: &AssetServer, path: &str) -> Handle<Image> {
+    let full_path = FileAssetReader::get_base_path().join("assets").join(path);
+    if full_path.is_file() {
+        asset_server.load(path.to_owned())
+    } else {
+        warn!("Particle texture '{path}' not found, falling back to '{DEFAULT_PARTICLE_TEXTURE}'");
+        asset_server.load(DEFAULT_PARTICLE_TEXTURE)
+    }
+}
+
+/// Shared appearance of the particle entities, per `RenderConfig::additive_blend`.
+enum ParticleLook {
+    Sprite(Handle<Image>),
+    Additive(Handle<Mesh>, Handle<AdditiveParticleMaterial>),
+}
+
+/// Spawns one visual entity per particle slot of `sim`, each also carrying `marker`.
+fn spawn_particle_set(
+    commands: &mut Commands,
+    sim: &FluidSimulation,
+    look: &ParticleLook,
+    scale: f32,
+    marker: impl Bundle<Effect: NoBundleEffect> + Clone,
+) {
+    // Sized in simulation units; `sync_rendering` applies the world scale via the transform.
+    let size = Vec2::splat(sim.particle_radius * 2.5);
+    let color = Color::srgb(0.2, 0.5, 1.0);
+    let transform = |i: usize| {
+        Transform::from_translation(sim.positions[i].extend(0.0) * scale)
+            .with_scale(Vec3::splat(scale))
+    };
+    match look {
+        ParticleLook::Additive(quad, material) => {
+            let bundles: Vec<_> = (0..sim.positions.len())
+                .map(|i| {
+                    (
+                        Mesh2d(quad.clone()),
+                        MeshMaterial2d(material.clone()),
+                        MeshTag(pack_color(color)),
+                        transform(i),
+                        ParticleId(i),
+                        marker.clone(),
+                    )
+                })
+                .collect();
+            commands.spawn_batch(bundles);
+        }
+        ParticleLook::Sprite(tex) => {
+            let bundles: Vec<_> = (0..sim.positions.len())
+                .map(|i| {
+                    (
+                        Sprite {
+                            image: tex.clone(),
+                            custom_size: Some(size),
+                            color,
+                            ..default()
+                        },
+                        transform(i),
+                        ParticleId(i),
+                        marker.clone(),
+                    )
+                })
+                .collect();
+            commands.spawn_batch(bundles);
+        }
+    }
+}
+
+/// (Re)spawns particle entities whenever the texture or blend mode changes, including a set
+/// for the `--compare` instance.
+/// Alpha-blended particles are sprites; additive particles are quads with an additive material.
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_particles(
+    mut applied: Local<Option<(String, bool)>>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    render_config: Res<RenderConfig>,
+    sim: Res<FluidSimulation>,
+    comparison: Option<Res<ComparisonSim>>,
+    existing: Query<Entity, With<ParticleId>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<AdditiveParticleMaterial>>,
+) {
+    let requested = (
+        render_config.particle_texture.clone(),
+        render_config.additive_blend,
+    );
+    if applied.as_ref() == Some(&requested) {
+        return;
+    }
+    *applied = Some(requested);
+
+    for entity in &existing {
+        commands.entity(entity).despawn();
+    }
+
+    let tex = load_particle_texture(&asset_server, &render_config.particle_texture);
+    let look = if render_config.additive_blend {
+        let quad = meshes.add(Rectangle::from_size(Vec2::splat(sim.particle_radius * 2.5)));
+        let material = materials.add(AdditiveParticleMaterial { texture: tex });
+        ParticleLook::Additive(quad, material)
+    } else {
+        ParticleLook::Sprite(tex)
+    };
+    let scale = render_config.world_scale;
+    spawn_particle_set(&mut commands, &sim, &look, scale, ());
+    if let Some(other) = comparison {
+        spawn_particle_set(&mut commands, &other.0, &look, scale, ComparisonParticle);
+    }
+}
+
+/// Number of particles cross-checked per step when `verify_neighbors` is enabled.
+const NEIGHBOR_VERIFY_SAMPLES: usize = 8;
+
+/// Cross-checks the grid neighbor search of a few random particles against a brute-force
+/// scan within `h` and logs a warning on any mismatch.
+fn verify_neighbor_search(sim: &FluidSimulation, h: f32) {
+    let count = sim.active_count.min(sim.positions.len());
+    if count == 0 {
+        return;
+    }
+    let h_sq = h * h;
+    let grid = sim.grid_view();
+    let mut rng = rand::rng();
+    for _ in 0..NEIGHBOR_VERIFY_SAMPLES {
+        let i = rng.random_range(0..count);
+        let pos = sim.positions[i];
+
+        let mut from_grid = Vec::new();
+        grid.for_each_candidate(pos, |j| {
+            if pos.distance_squared(sim.positions[j]) < h_sq {
+                from_grid.push(j);
+            }
+        });
+        from_grid.sort_unstable();
+
+        let brute_force: Vec<usize> = (0..count)
+            .filter(|&j| pos.distance_squared(sim.positions[j]) < h_sq)
+            .collect();
+
+        if from_grid != brute_force {
+            let missed = brute_force
+                .iter()
+                .filter(|j| !from_grid.contains(j))
+                .count();
+            warn!(
+                "Neighbor search mismatch for particle {i} at {pos}: grid found {}, brute force found {} ({missed} missed)",
+                from_grid.len(),
+                brute_force.len(),
+            );
+        }
+    }
+}
+
+/// Draws the free surface as the marching-squares contour of the density field
+/// at half the target density.
+pub fn draw_surface_contour(
+    mut gizmos: Gizmos,
+    sim: Res<FluidSimulation>,
+    config: Res<FluidConfig>,
+    render_config: Res<RenderConfig>,
+) {
+    if !render_config.show_surface_contour {
+        return;
+    }
+    let scale = render_config.world_scale;
+    let spacing = render_config.contour_resolution.max(1.0) / scale;
+    let origin = sim.domain.min;
+    let cols = (sim.domain.width() / spacing).ceil() as usize + 1;
+    let rows = (sim.domain.height() / spacing).ceil() as usize + 1;
+    let values = (0..cols * rows)
+        .into_par_iter()
+        .map(|k| {
+            let p = origin + Vec2::new((k % cols) as f32, (k / cols) as f32) * spacing;
+            sim.sample_density_at(p, &config)
+        })
+        .collect();
+    let field = ScalarGrid {
+        values,
+        cols,
+        rows,
+        origin,
+        spacing,
+    };
+    for (a, b) in marching_squares(&field, config.target_density * 0.5) {
+        gizmos.line_2d(a * scale, b * scale, Color::srgb(0.6, 0.9, 1.0));
+    }
+}
+
+/// Records the total kinetic and gravitational potential energy of the current state.
+/// Potential energy is measured from the domain wall that gravity points towards.
+pub fn record_energy(
+    sim: Res<FluidSimulation>,
+    config: Res<FluidConfig>,
+    mut energy: ResMut<EnergyHistory>,
+) {
+    let gravity = config.current_gravity();
+    let floor = sim.domain.center() + sim.domain.half_size() * gravity.signum();
+    let n = sim.active_count.min(sim.positions.len());
+    let kinetic = total_kinetic_energy(
+        &sim.velocities[..n],
+        &sim.mass_factors[..n],
+        config.particle_mass,
+    );
+    let potential = total_potential_energy(
+        &sim.positions[..n],
+        &sim.mass_factors[..n],
+        config.particle_mass,
+        gravity,
+        floor,
+    );
+    energy.push(kinetic, potential);
+}
+
+/// Sends `SimulationSettled` once the latest kinetic energy sample has stayed below
+/// `settle_energy` for `settle_duration` seconds. Runs after `record_energy`.
+pub fn detect_settling(
+    time: Res<Time>,
+    config: Res<FluidConfig>,
+    energy: Res<EnergyHistory>,
+    mut detector: ResMut<SettleDetector>,
+    mut settled: MessageWriter<SimulationSettled>,
+) {
+    if config.settle_energy <= 0.0 {
+        return;
+    }
+    let kinetic_energy = energy.kinetic.back().copied().unwrap_or(0.0);
+    if detector.update(
+        kinetic_energy,
+        config.settle_energy,
+        config.settle_duration,
+        time.delta_secs(),
+    ) {
+        settled.write(SimulationSettled {
+            kinetic_energy,
+            time: time.elapsed_secs(),
+        });
+    }
+}
+
+/// Sends the primary simulation's logged particle creations and removals as
+/// `ParticleSpawned` and `ParticleRemoved`, and feeds them to the `FlowMeter`, which a
+/// logged reset clears. The comparison instance's log is discarded.
+pub fn emit_flow_events(
+    mut sim: ResMut<FluidSimulation>,
+    comparison: Option<ResMut<ComparisonSim>>,
+    mut meter: ResMut<FlowMeter>,
+    mut spawned: MessageWriter<ParticleSpawned>,
+    mut removed: MessageWriter<ParticleRemoved>,
+) {
+    if let Some(mut other) = comparison {
+        other.0.flow_log.clear();
+    }
+    for event in sim.flow_log.drain(..) {
+        match event {
+            FlowEvent::Spawned { source, time } => {
+                let message = ParticleSpawned { source, time };
+                meter.record_spawn(&message);
+                spawned.write(message);
+            }
+            FlowEvent::Removed { sink, time, age } => {
+                let message = ParticleRemoved {
+                    sink,
+                    time,
+                    residence_time: age,
+                };
+                meter.record_removal(&message);
+                removed.write(message);
+            }
+            FlowEvent::Reset => *meter = FlowMeter::default(),
+        }
+    }
+    meter.update_rates(sim.elapsed);
+}
+
+/// Logs each `SimulationSettled` message.
+pub fn log_settling(mut settled: MessageReader<SimulationSettled>) {
+    for message in settled.read() {
+        info!(
+            "Simulation settled at {:.2}s (kinetic energy {:.3})",
+            message.time, message.kinetic_energy
+        );
+    }
+}
+
+/// Press 'M' to start (or abort) the parameter sweep configured in `FluidConfig::sweep`.
+/// For each value the main instance is reset to the selected initial condition and run
+/// until `SimulationSettled`; the mean density, kinetic energy and settle time are then
+/// recorded. When the last value settles, the rows are written to
+/// `exports/sweep-<parameter>-<frame>.csv` and the parameter is restored.
+#[allow(clippy::too_many_arguments)]
+pub fn run_parameter_sweep(
+    input: Res<ButtonInput<KeyCode>>,
+    mut config: ResMut<FluidConfig>,
+    render_config: Res<RenderConfig>,
+    mut sim: ResMut<FluidSimulation>,
+    selected: Res<SelectedInitialCondition>,
+    elements: Res<SceneElements>,
+    energy: Res<EnergyHistory>,
+    mut detector: ResMut<SettleDetector>,
+    mut settled: MessageReader<SimulationSettled>,
+    mut recorder: ResMut<SweepRecorder>,
+    frame: Res<FrameCount>,
+) {
+    let just_settled = settled.read().count() > 0;
+
+    if input.just_pressed(KeyCode::KeyM) {
+        if recorder.is_running() {
+            let parameter = recorder.parameter;
+            parameter.set(&mut config, recorder.original);
+            *recorder = SweepRecorder::default();
+            info!("Sweep of {} aborted", parameter.name());
+            return;
+        }
+        if config.settle_energy <= 0.0 {
+            warn!("Set `settle_energy` above zero so the sweep can detect settling");
+            return;
+        }
+        let sweep = config.sweep;
+        *recorder = SweepRecorder {
+            pending: sweep.values().into(),
+            rows: Vec::new(),
+            parameter: sweep.parameter,
+            original: sweep.parameter.get(&config),
+        };
+    } else if recorder.is_running() && just_settled {
+        let n = sim.active_count.min(sim.positions.len());
+        let row = SweepRow {
+            value: recorder.pending.pop_front().unwrap_or_default(),
+            mean_density: par_mean(&sim.densities[..n]),
+            kinetic_energy: energy.kinetic.back().copied().unwrap_or(0.0),
+            settle_time: sim.elapsed,
+        };
+        info!(
+            "Sweep: {} = {} settled after {:.2}s (mean density {:.4})",
+            recorder.parameter.name(),
+            row.value,
+            row.settle_time,
+            row.mean_density
+        );
+        recorder.rows.push(row);
+        if !recorder.is_running() {
+            finish_sweep(&mut recorder, &mut config, frame.0);
+            return;
+        }
+    } else {
+        return;
+    }
+
+    // Start the next value from a fresh reset; the detector must see it calm down anew.
+    let Some(&value) = recorder.pending.front() else {
+        return;
+    };
+    recorder.parameter.set(&mut config, value);
+    reset_main_instance(
+        &mut sim,
+        &mut config,
+        selected.0,
+        &elements,
+        render_config.id_pattern,
+    );
+    *detector = SettleDetector::default();
+}
+
+/// Writes the sweep's rows to `EXPORT_DIR` and restores the swept parameter.
+fn finish_sweep(recorder: &mut SweepRecorder, config: &mut FluidConfig, frame: u32) {
+    let recorder = std::mem::take(recorder);
+    recorder.parameter.set(config, recorder.original);
+
+    if let Err(e) = std::fs::create_dir_all(EXPORT_DIR) {
+        error!("Cannot create '{EXPORT_DIR}' directory: {e}");
+        return;
+    }
+    let path = format!(
+        "{EXPORT_DIR}/sweep-{}-{frame:06}.csv",
+        recorder.parameter.name()
+    );
+    match std::fs::write(&path, recorder.to_csv()) {
+        Ok(()) => info!("Wrote {} sweep rows to {path}", recorder.rows.len()),
+        Err(e) => error!("Cannot write '{path}': {e}"),
+    }
+}
+
+/// Refreshes the inspector's `SimStats` panel. Energies come from the latest
+/// `EnergyHistory` sample, so this runs after `record_energy`.
+pub fn update_sim_stats(
+    sim: Res<FluidSimulation>,
+    config: Res<FluidConfig>,
+    energy: Res<EnergyHistory>,
+    mut stats: ResMut<SimStats>,
+) {
+    let n = sim.active_count.min(sim.positions.len());
+    let (_, max_density) = par_min_max(&sim.densities[..n]);
+    let kinetic_energy = energy.kinetic.back().copied().unwrap_or(0.0);
+    let potential_energy = energy.potential.back().copied().unwrap_or(0.0);
+    let max_speed = par_max_vec2_len(&sim.velocities[..n]);
+    let sound_speed = config
+        .pressure_eos
+        .sound_speed(config.target_density, config.pressure_multiplier);
+    let dt = 0.002 * config.time_scale;
+    let cfl = if config.smoothing_radius > 0.0 {
+        (sound_speed + max_speed) * dt / config.smoothing_radius
+    } else {
+        0.0
+    };
+    let (bounds_min, bounds_max) = sim.bounding_box();
+    *stats = SimStats {
+        particle_count: sim.live_count,
+        active_count: sim.active_count,
+        mean_density: par_mean(&sim.densities[..n]),
+        max_density,
+        mean_speed: par_mean_vec2_len(&sim.velocities[..n]),
+        max_speed,
+        sound_speed,
+        cfl,
+        kinetic_energy,
+        potential_energy,
+        total_energy: kinetic_energy + potential_energy,
+        total_mass: config.particle_mass * par_sum(&sim.mass_factors[..n]),
+        momentum: total_momentum(
+            &sim.velocities[..n],
+            &sim.mass_factors[..n],
+            config.particle_mass,
+        ),
+        center_of_mass: sim.center_of_mass(),
+        bounds_min,
+        bounds_max,
+        mean_separation: par_mean(&sim.separations[..n]),
+    };
+}
+
+/// Prints the current `FluidConfig` and `SimStats` to stdout as TOML when `T`
+/// is pressed, so a run can be reproduced or compared later.
+pub fn dump_config(
+    input: Res<ButtonInput<KeyCode>>,
+    config: Res<FluidConfig>,
+    stats: Res<SimStats>,
+) {
+    if !input.just_pressed(KeyCode::KeyT) {
+        return;
+    }
+    let mut out = format!(
+        "# fluid2d config dump\n# seed = {}, particles = {} ({} active)\n\n",
+        config.seed, stats.particle_count, stats.active_count
+    );
+    write_table(&mut out, "config", config.as_partial_reflect());
+    out.push('\n');
+    write_table(&mut out, "stats", stats.as_partial_reflect());
+    println!("{out}");
+}
+
+/// Computes the SPH velocity divergence `∇·v_i = (1/ρ_i) Σ m (v_j - v_i)·∇W_ij` for
+/// `ColorField::Divergence`, reusing the neighbor grid from the latest step.
+pub fn update_divergence(
+    mut sim: ResMut<FluidSimulation>,
+    config: Res<FluidConfig>,
+    render_config: Res<RenderConfig>,
+) {
+    if render_config.color_field != ColorField::Divergence {
+        return;
+    }
+    let sim = &mut *sim;
+    let n = sim.active_count.min(sim.positions.len());
+    let h = config.smoothing_radius;
+    let grid = GridView {
+        cells: &sim.grid_map,
+        width: sim.grid_width_cells,
+        height: sim.grid_height_cells,
+        cell_size: sim.grid_cell_size,
+        offset_x: sim.grid_offset_x,
+        offset_y: sim.grid_offset_y,
+        scan_radius: sim.grid_scan_radius,
+    };
+    let positions = &sim.positions;
+    let velocities = &sim.velocities;
+    let densities = &sim.densities;
+    let mass_factors = &sim.mass_factors;
+    sim.divergences[..n]
+        .par_iter_mut()
+        .enumerate()
+        .for_each(|(i, divergence_out)| {
+            let pos = positions[i];
+            let vel = velocities[i];
+            let mut sum = 0.0;
+            grid.for_each_candidate(pos, |j| {
+                if i == j || j >= n {
+                    return;
+                }
+                let offset = positions[j] - pos;
+                let dist = offset.length();
+                if dist < h && dist > 0.0001 {
+                    // ∇_i W_ij points from i towards j for a kernel decreasing with distance.
+                    let grad = offset / dist * spiky_kernel_gradient(dist, h);
+                    sum += config.particle_mass * mass_factors[j] * (velocities[j] - vel).dot(grad);
+                }
+            });
+            *divergence_out = sum / densities[i].max(config.min_density());
+        });
+}
+
+/// Pairs the main and `--compare` particles by index and measures how far each pair has
+/// drifted apart, for `ColorField::Separation` and `SimStats::mean_separation`.
+pub fn update_separation(
+    mut sim: ResMut<FluidSimulation>,
+    comparison: Option<ResMut<ComparisonSim>>,
+) {
+    if let Some(mut other) = comparison {
+        update_separations(&mut sim, &mut other.0);
+    }
+}
+
+/// Size of the energy plot in pixels.
+const ENERGY_PLOT_SIZE: Vec2 = Vec2::new(300.0, 100.0);
+
+/// Draws the energy history as a scrolling plot in the top-left corner.
+/// Kinetic energy is yellow, potential energy blue, and total energy white,
+/// all normalized to the largest total in the window.
+pub fn draw_energy_plot(
+    mut gizmos: Gizmos,
+    sim: Res<FluidSimulation>,
+    render_config: Res<RenderConfig>,
+    energy: Res<EnergyHistory>,
+) {
+    if !energy.visible {
+        return;
+    }
+    let scale = render_config.world_scale;
+    let min = Vec2::new(
+        sim.domain.min.x * scale + 20.0,
+        sim.domain.max.y * scale - 20.0 - ENERGY_PLOT_SIZE.y,
+    );
+    gizmos.rect_2d(
+        Isometry2d::from_translation(min + ENERGY_PLOT_SIZE * 0.5),
+        ENERGY_PLOT_SIZE,
+        Color::srgba(1.0, 1.0, 1.0, 0.4),
+    );
+
+    let totals: Vec<f32> = energy
+        .kinetic
+        .iter()
+        .zip(&energy.potential)
+        .map(|(k, p)| k + p)
+        .collect();
+    let max = totals
+        .iter()
+        .copied()
+        .fold(energy.max_value(), f32::max)
+        .max(f32::EPSILON);
+    let dx = ENERGY_PLOT_SIZE.x / (ENERGY_HISTORY_LEN - 1) as f32;
+    let to_point =
+        |k: usize, value: f32| min + Vec2::new(k as f32 * dx, value / max * ENERGY_PLOT_SIZE.y);
+
+    gizmos.linestrip_2d(
+        energy
+            .kinetic
+            .iter()
+            .enumerate()
+            .map(|(k, &v)| to_point(k, v)),
+        Color::srgb(1.0, 0.85, 0.2),
+    );
+    gizmos.linestrip_2d(
+        energy
+            .potential
+            .iter()
+            .enumerate()
+            .map(|(k, &v)| to_point(k, v)),
+        Color::srgb(0.3, 0.6, 1.0),
+    );
+    gizmos.linestrip_2d(
+        totals.iter().enumerate().map(|(k, &v)| to_point(k, v)),
+        Color::WHITE,
+    );
+}
+
+/// Draws the `ParticleProbe`'s history in the top-right corner: density (cyan), pressure
+/// (red) and speed (yellow), each stretched over the plot height between its own smallest
+/// and largest sample, since their units differ.
+pub fn draw_probe_history(
+    mut gizmos: Gizmos,
+    sim: Res<FluidSimulation>,
+    render_config: Res<RenderConfig>,
+    probe: Res<ParticleProbe>,
+) {
+    if probe.selected.is_none() || probe.history.len() < 2 {
+        return;
+    }
+    let scale = render_config.world_scale;
+    let min = Vec2::new(
+        sim.domain.max.x * scale - 20.0 - ENERGY_PLOT_SIZE.x,
+        sim.domain.max.y * scale - 20.0 - ENERGY_PLOT_SIZE.y,
+    );
+    gizmos.rect_2d(
+        Isometry2d::from_translation(min + ENERGY_PLOT_SIZE * 0.5),
+        ENERGY_PLOT_SIZE,
+        Color::srgba(1.0, 1.0, 1.0, 0.4),
+    );
+
+    let dx = ENERGY_PLOT_SIZE.x / (PROBE_HISTORY_LEN - 1) as f32;
+    let colors = [
+        Color::srgb(0.3, 0.9, 1.0),
+        Color::srgb(1.0, 0.3, 0.3),
+        Color::srgb(1.0, 0.85, 0.2),
+    ];
+    for (series, color) in colors.into_iter().enumerate() {
+        let value = |sample: &ProbeSample| sample.values()[series];
+        let (lo, hi) = probe
+            .history
+            .iter()
+            .map(value)
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), v| {
+                (lo.min(v), hi.max(v))
+            });
+        let range = (hi - lo).max(f32::EPSILON);
+        gizmos.linestrip_2d(
+            probe.history.iter().enumerate().map(|(k, sample)| {
+                min + Vec2::new(
+                    k as f32 * dx,
+                    (value(sample) - lo) / range * ENERGY_PLOT_SIZE.y,
+                )
+            }),
+            color,
+        );
+    }
+}
+
+/// Outlines gravity regions, with an arrow showing each region's gravity direction.
+pub fn draw_gravity_regions(
+    mut gizmos: Gizmos,
+    elements: Res<SceneElements>,
+    render_config: Res<RenderConfig>,
+) {
+    let scale = render_config.world_scale;
+    let color = Color::srgba(0.8, 0.5, 1.0, 0.6);
+    for region in &elements.gravity_regions {
+        let center = region.rect.center() * scale;
+        gizmos.rect_2d(
+            Isometry2d::from_translation(center),
+            region.rect.size() * scale,
+            color,
+        );
+        let arrow = region.gravity.normalize_or_zero() * region.rect.size().min_element() * 0.25;
+        gizmos.arrow_2d(center, center + arrow * scale, color);
+    }
+}
+
+/// Size of the cell occupancy histogram in pixels.
+const HISTOGRAM_SIZE: Vec2 = Vec2::new(300.0, 100.0);
+/// Most bars drawn; larger occupancies share bars.
+const HISTOGRAM_MAX_BINS: usize = 32;
+
+/// Draws a histogram of non-empty grid cell occupancy in the bottom-right corner and
+/// outlines the densest cell. A long tail means a few crowded cells dominate the
+/// neighbor-search cost.
+pub fn draw_cell_histogram(
+    mut gizmos: Gizmos,
+    sim: Res<FluidSimulation>,
+    render_config: Res<RenderConfig>,
+) {
+    if !render_config.show_cell_histogram {
+        return;
+    }
+    let Some((densest, max_count)) = sim
+        .grid_map
+        .iter()
+        .map(Vec::len)
+        .enumerate()
+        .max_by_key(|&(_, len)| len)
+    else {
+        return;
+    };
+    if max_count == 0 {
+        return;
+    }
+
+    let bins = max_count.min(HISTOGRAM_MAX_BINS);
+    let mut histogram = vec![0usize; bins];
+    for cell in sim.grid_map.iter().filter(|cell| !cell.is_empty()) {
+        histogram[(cell.len() - 1) * bins / max_count] += 1;
+    }
+    let tallest = histogram.iter().copied().max().unwrap_or(1).max(1);
+
+    let scale = render_config.world_scale;
+    let min = Vec2::new(
+        sim.domain.max.x * scale - 20.0 - HISTOGRAM_SIZE.x,
+        sim.domain.min.y * scale + 20.0,
+    );
+    gizmos.rect_2d(
+        Isometry2d::from_translation(min + HISTOGRAM_SIZE * 0.5),
+        HISTOGRAM_SIZE,
+        Color::srgba(1.0, 1.0, 1.0, 0.4),
+    );
+    let bar_width = HISTOGRAM_SIZE.x / bins as f32;
+    for (bin, &count) in histogram.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let height = count as f32 / tallest as f32 * HISTOGRAM_SIZE.y;
+        let size = Vec2::new(bar_width * 0.8, height);
+        let center = min + Vec2::new((bin as f32 + 0.5) * bar_width, height * 0.5);
+        gizmos.rect_2d(
+            Isometry2d::from_translation(center),
+            size,
+            Color::srgb(0.4, 0.9, 0.5),
+        );
+    }
+
+    let cell = sim.grid_cell_size;
+    let col = densest % sim.grid_width_cells;
+    let row = densest / sim.grid_width_cells;
+    let cell_min = Vec2::new(
+        col as f32 * cell - sim.grid_offset_x,
+        row as f32 * cell - sim.grid_offset_y,
+    );
+    gizmos.rect_2d(
+        Isometry2d::from_translation((cell_min + Vec2::splat(cell * 0.5)) * scale),
+        Vec2::splat(cell * scale),
+        Color::srgb(1.0, 0.2, 0.2),
+    );
+}
+
+/// Paints the latest grid's cell occupancy into the `OccupancyHeatmap` sprite, one texel
+/// per cell over the whole grid (margin included), behind the particles. Cells shade from
+/// clear through yellow to red with their particle count relative to the fullest cell.
+pub fn update_occupancy_heatmap(
+    sim: Res<FluidSimulation>,
+    render_config: Res<RenderConfig>,
+    mut images: ResMut<Assets<Image>>,
+    mut heatmap: Query<(&mut Sprite, &mut Transform, &mut Visibility), With<OccupancyHeatmap>>,
+) {
+    let Ok((mut sprite, mut transform, mut visibility)) = heatmap.single_mut() else {
+        return;
+    };
+    let (width, height) = (sim.grid_width_cells, sim.grid_height_cells);
+    if !render_config.show_occupancy_heatmap || width == 0 || height == 0 {
+        *visibility = Visibility::Hidden;
+        return;
+    }
+    *visibility = Visibility::Visible;
+    let Some(image) = images.get_mut(&sprite.image) else {
+        return;
+    };
+    let size = Extent3d {
+        width: width as u32,
+        height: height as u32,
+        depth_or_array_layers: 1,
+    };
+    if image.texture_descriptor.size != size {
+        image.resize(size);
+    }
+    let Some(texels) = image.data.as_mut() else {
+        return;
+    };
+    let max_count = sim.grid_map.iter().map(Vec::len).max().unwrap_or(0).max(1) as f32;
+    let opacity = render_config.occupancy_heatmap_opacity.clamp(0.0, 1.0);
+    for (cell, count) in sim.grid_map.iter().map(Vec::len).enumerate() {
+        let t = count as f32 / max_count;
+        let color = Color::hsla(60.0 * (1.0 - t), 1.0, 0.5, opacity * t);
+        // Texture rows run top to bottom, grid rows bottom to top.
+        let (col, row) = (cell % width, cell / width);
+        let texel = ((height - 1 - row) * width + col) * 4;
+        texels[texel..texel + 4].copy_from_slice(&color.to_srgba().to_u8_array());
+    }
+
+    let scale = render_config.world_scale;
+    let grid_min = -Vec2::new(sim.grid_offset_x, sim.grid_offset_y);
+    let extent = Vec2::new(width as f32, height as f32) * sim.grid_cell_size;
+    sprite.custom_size = Some(extent * scale);
+    transform.translation = ((grid_min + extent * 0.5) * scale).extend(-1.0);
+}
+
+/// Fades the `TrailCanvas` exposure by `trail_fade_rate`, adds `trail_intensity` at every
+/// active particle of the main instance and paints it into the canvas sprite over the
+/// domain, behind the particles. Hiding the trails clears them.
+pub fn update_trails(
+    sim: Res<FluidSimulation>,
+    render_config: Res<RenderConfig>,
+    time: Res<Time>,
+    mut images: ResMut<Assets<Image>>,
+    mut canvas: Query<(
+        &mut TrailCanvas,
+        &mut Sprite,
+        &mut Transform,
+        &mut Visibility,
+    )>,
+) {
+    let Ok((mut canvas, mut sprite, mut transform, mut visibility)) = canvas.single_mut() else {
+        return;
+    };
+    if !render_config.show_trails {
+        if *visibility != Visibility::Hidden {
+            *visibility = Visibility::Hidden;
+            canvas.exposure.fill(0.0);
+        }
+        return;
+    }
+    *visibility = Visibility::Visible;
+    let Some(image) = images.get_mut(&sprite.image) else {
+        return;
+    };
+
+    let scale = render_config.world_scale;
+    let texel = render_config.trail_resolution.max(0.5) / scale;
+    let domain = sim.domain;
+    let width = (domain.width() / texel).ceil().max(1.0) as usize;
+    let height = (domain.height() / texel).ceil().max(1.0) as usize;
+    if canvas.width != width || canvas.height != height {
+        *canvas = TrailCanvas {
+            exposure: vec![0.0; width * height],
+            width,
+            height,
+        };
+        image.resize(Extent3d {
+            width: width as u32,
+            height: height as u32,
+            depth_or_array_layers: 1,
+        });
+    }
+
+    let fade = (-render_config.trail_fade_rate.max(0.0) * time.delta_secs()).exp();
+    canvas.exposure.par_iter_mut().for_each(|e| *e *= fade);
+    let n = sim.active_count.min(sim.positions.len());
+    for &pos in &sim.positions[..n] {
+        let offset = (pos - domain.min) / texel;
+        if offset.x < 0.0 || offset.y < 0.0 {
+            continue;
+        }
+        let (col, row) = (offset.x as usize, offset.y as usize);
+        if col < width && row < height {
+            // Texture rows run top to bottom, the domain bottom to top.
+            canvas.exposure[(height - 1 - row) * width + col] += render_config.trail_intensity;
+        }
+    }
+
+    let Some(texels) = image.data.as_mut() else {
+        return;
+    };
+    let color = render_config.trail_color.to_srgba();
+    texels
+        .par_chunks_mut(4)
+        .zip(&canvas.exposure)
+        .for_each(|(texel, &exposure)| {
+            let alpha = color.alpha * (1.0 - (-exposure).exp());
+            texel.copy_from_slice(&color.with_alpha(alpha).to_u8_array());
+        });
+
+    sprite.custom_size = Some(domain.size() * scale);
+    transform.translation = (domain.center() * scale).extend(-0.5);
+}
+
+/// Draws the domain walls of each instance from their current bounds: closed walls in gray
+/// while `show_domain_box` is on, and always open walls in red and periodic ones in cyan.
+pub fn draw_domain_walls(
+    mut gizmos: Gizmos,
+    sim: Res<FluidSimulation>,
+    config: Res<FluidConfig>,
+    render_config: Res<RenderConfig>,
+    comparison: Option<Res<ComparisonSim>>,
+    comparison_config: Option<Res<ComparisonConfig>>,
+) {
+    let scale = render_config.world_scale;
+    let other = comparison
+        .as_deref()
+        .zip(comparison_config.as_deref())
+        .map(|(other, other_config)| (&other.0, &other_config.0));
+    for (sim, config) in std::iter::once((&*sim, &*config)).chain(other) {
+        let (min, max) = (sim.domain.min * scale, sim.domain.max * scale);
+        let walls = config.boundaries;
+        for (behavior, a, b) in [
+            (walls.left, min, Vec2::new(min.x, max.y)),
+            (walls.right, Vec2::new(max.x, min.y), max),
+            (walls.bottom, min, Vec2::new(max.x, min.y)),
+            (walls.top, Vec2::new(min.x, max.y), max),
+        ] {
+            let color = match behavior {
+                BoundaryBehavior::Reflect if render_config.show_domain_box => {
+                    Color::srgba(0.8, 0.8, 0.8, 0.6)
+                }
+                BoundaryBehavior::Reflect => continue,
+                BoundaryBehavior::Open => Color::srgb(1.0, 0.3, 0.3),
+                BoundaryBehavior::Periodic => Color::srgb(0.3, 0.9, 1.0),
+            };
+            gizmos.line_2d(a, b, color);
+        }
+    }
+}
+
+/// Draws the line between the main and `--compare` instances' domains.
+pub fn draw_comparison_divider(
+    mut gizmos: Gizmos,
+    sim: Res<FluidSimulation>,
+    comparison: Option<Res<ComparisonSim>>,
+    render_config: Res<RenderConfig>,
+) {
+    if comparison.is_none() {
+        return;
+    }
+    let scale = render_config.world_scale;
+    let x = sim.domain.max.x * scale;
+    gizmos.line_2d(
+        Vec2::new(x, sim.domain.min.y * scale),
+        Vec2::new(x, sim.domain.max.y * scale),
+        Color::srgba(1.0, 1.0, 1.0, 0.4),
+    );
+}
+
+/// Outlines the inspector-editable obstacles, paddle wheels and freeze regions.
+pub fn draw_obstacles(
+    mut gizmos: Gizmos,
+    sim: Res<FluidSimulation>,
+    elements: Res<SceneElements>,
+    render_config: Res<RenderConfig>,
+) {
+    let scale = render_config.world_scale;
+    let color = Color::srgb(0.9, 0.7, 0.3);
+    for obstacle in &elements.obstacles {
+        match *obstacle {
+            Obstacle::Circle { center, radius } => {
+                gizmos.circle_2d(center * scale, radius * scale, color);
+            }
+            Obstacle::Box { center, half_size } => {
+                gizmos.rect_2d(center * scale, half_size * 2.0 * scale, color);
+            }
+        }
+    }
+    for wheel in &elements.rotating_obstacles {
+        gizmos.circle_2d(wheel.center * scale, wheel.hub_radius * scale, color);
+        for arm in wheel.arms_at(sim.elapsed) {
+            gizmos.line_2d(arm.a * scale, arm.b * scale, color);
+        }
+    }
+    for region in &elements.freeze_regions {
+        gizmos.rect_2d(
+            region.rect.center() * scale,
+            region.rect.size() * scale,
+            Color::srgba(0.6, 0.9, 1.0, 0.6),
+        );
+    }
+}
+
+/// Selects the active particle nearest the cursor, within one smoothing radius, for the
+/// `ParticleProbe` on a middle click; clicking away from every particle clears the selection.
+pub fn select_probe_particle(
+    mouse_btn: Res<ButtonInput<MouseButton>>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    sim: Res<FluidSimulation>,
+    config: Res<FluidConfig>,
+    render_config: Res<RenderConfig>,
+    mut probe: ResMut<ParticleProbe>,
+) {
+    if !mouse_btn.just_pressed(MouseButton::Middle) {
+        return;
+    }
+    let (Ok(window), Ok((camera, camera_transform))) = (q_window.single(), q_camera.single())
+    else {
+        return;
+    };
+    let Some(cursor) = window
+        .cursor_position()
+        .and_then(|screen| camera.viewport_to_world_2d(camera_transform, screen).ok())
+    else {
+        return;
+    };
+    let cursor = cursor / render_config.world_scale;
+    let n = sim.active_count.min(sim.positions.len());
+    let reach_sq = config.smoothing_radius * config.smoothing_radius;
+    let previous = probe.selected;
+    probe.selected = sim.positions[..n]
+        .iter()
+        .map(|p| p.distance_squared(cursor))
+        .enumerate()
+        .filter(|&(_, dist_sq)| dist_sq <= reach_sq)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(i, _)| i);
+    if probe.selected != previous {
+        probe.history.clear();
+    }
+}
+
+/// Records the `ParticleProbe`'s current state into its history, one sample per frame.
+pub fn record_probe_history(mut probe: ResMut<ParticleProbe>) {
+    if probe.selected.is_some() {
+        probe.record();
+    } else if !probe.history.is_empty() {
+        probe.history.clear();
+    }
+}
+
+/// Draws the force terms on the `ParticleProbe`'s particle as arrows, scaled so the largest
+/// term is three smoothing radii long, with the total in white.
+pub fn draw_particle_forces(
+    mut gizmos: Gizmos,
+    probe: Res<ParticleProbe>,
+    config: Res<FluidConfig>,
+    render_config: Res<RenderConfig>,
+) {
+    if probe.selected.is_none() {
+        return;
+    }
+    let scale = render_config.world_scale;
+    let start = probe.position * scale;
+    let forces = &probe.forces;
+    let total = forces.total();
+    let largest = forces
+        .terms()
+        .iter()
+        .map(|(force, _)| force.length())
+        .fold(total.length(), f32::max);
+    gizmos.circle_2d(start, config.smoothing_radius * 0.5 * scale, Color::WHITE);
+    if largest <= 0.0 {
+        return;
+    }
+    let length = config.smoothing_radius * 3.0 * scale / largest;
+    for (force, color) in forces.terms() {
+        if force != Vec2::ZERO {
+            gizmos.arrow_2d(start, start + force * length, color);
+        }
+    }
+    gizmos.arrow_2d(start, start + total * length, Color::WHITE);
+}
+
+/// Draws segment walls as gizmo lines.
+pub fn draw_segments(
+    mut gizmos: Gizmos,
+    elements: Res<SceneElements>,
+    render_config: Res<RenderConfig>,
+) {
+    let scale = render_config.world_scale;
+    for segment in &elements.segments {
+        gizmos.line_2d(
+            segment.a * scale,
+            segment.b * scale,
+            Color::srgb(0.9, 0.9, 0.9),
+        );
+    }
+}
+
+/// Squared speed below which `rotate_to_velocity` keeps the previous rotation,
+/// since the direction of a nearly stationary particle is mostly noise.
+const MIN_ROTATION_SPEED_SQ: f32 = 1e-6;
+
+/// Per-particle components written by `sync_rendering`; sprite or mesh depending on the blend mode.
+type ParticleVisual = (
+    &'static mut Transform,
+    &'static mut Visibility,
+    Option<&'static mut Sprite>,
+    Option<&'static mut MeshTag>,
+    &'static ParticleId,
+);
+
+/// Colors for `ColorField::Id`, indexed by id. The third is `SPAWNED_PARTICLE_ID`.
+const ID_COLORS: [Color; 3] = [
+    Color::srgb(0.2, 0.5, 1.0),
+    Color::srgb(1.0, 0.75, 0.2),
+    Color::srgb(0.4, 0.9, 0.5),
+];
+
+/// Values of the active color field mapped to the two ends of its colormap, per
+/// `RenderConfig::color_range`. Never returns an empty range.
+fn color_field_range(render_config: &RenderConfig, sim: &FluidSimulation) -> (f32, f32) {
+    let n = sim.active_count.min(sim.positions.len());
+    let (lo, hi) = match (render_config.color_field, render_config.color_range) {
+        (ColorField::Id, _) => (0.0, (ID_COLORS.len() - 1) as f32),
+        (field, ColorRange::Fixed { min, max }) => {
+            if min > max {
+                warn_once!("Fixed color range has min {min} above max {max}; swapping them");
+            }
+            let (lo, hi) = (min.min(max), min.max(max));
+            if field == ColorField::Divergence {
+                let extent = lo.abs().max(hi.abs());
+                (-extent, extent)
+            } else {
+                (lo, hi)
+            }
+        }
+        (ColorField::Velocity, ColorRange::Auto) => {
+            let speeds: Vec<f32> = sim.velocities[..n].par_iter().map(|v| v.length()).collect();
+            par_min_max(&speeds)
+        }
+        (ColorField::Divergence, ColorRange::Auto) => {
+            let (lo, hi) = par_min_max(&sim.divergences[..n]);
+            let extent = lo.abs().max(hi.abs());
+            (-extent, extent)
+        }
+        (ColorField::Separation, ColorRange::Auto) => (0.0, par_min_max(&sim.separations[..n]).1),
+    };
+    (lo, hi.max(lo + 1e-6))
+}
+
+/// Colormap of each color field, at `t` in [0, 1] along `color_field_range` (clamped).
+fn color_field_color(field: ColorField, t: f32) -> Color {
+    let t = t.clamp(0.0, 1.0);
+    match field {
+        ColorField::Velocity => Color::mix(&Color::srgb(0.1, 0.2, 0.9), &Color::WHITE, t),
+        // Red where the flow compresses, white where it is incompressible, blue where it expands.
+        ColorField::Divergence if t < 0.5 => {
+            Color::mix(&Color::srgb(1.0, 0.1, 0.1), &Color::WHITE, t * 2.0)
+        }
+        ColorField::Divergence => {
+            Color::mix(&Color::WHITE, &Color::srgb(0.1, 0.2, 1.0), t * 2.0 - 1.0)
+        }
+        ColorField::Id => ID_COLORS[(t * (ID_COLORS.len() - 1) as f32).round() as usize],
+        ColorField::Separation => Color::mix(&Color::WHITE, &Color::srgb(0.9, 0.1, 0.8), t),
+    }
+}
+
+/// Legend suffix naming the color range mode.
+fn range_mode(range: ColorRange) -> &'static str {
+    match range {
+        ColorRange::Auto => "auto",
+        ColorRange::Fixed { .. } => "fixed",
+    }
+}
+
+/// Size of the color legend bar in pixels.
+const LEGEND_SIZE: Vec2 = Vec2::new(200.0, 12.0);
+
+/// Draws a gradient bar for the active color field along the bottom-left of the domain,
+/// with its endpoint values and name as world-space text.
+pub fn draw_color_legend(
+    mut gizmos: Gizmos,
+    sim: Res<FluidSimulation>,
+    render_config: Res<RenderConfig>,
+    mut labels: Query<(
+        &mut Text2d,
+        &mut Transform,
+        &mut Visibility,
+        &ColorLegendLabel,
+    )>,
+) {
+    let shown = render_config.show_color_legend;
+    for (_, _, mut visibility, _) in &mut labels {
+        visibility.set_if_neq(if shown {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+    }
+    if !shown {
+        return;
+    }
+
+    let field = render_config.color_field;
+    let (lo, hi) = color_field_range(&render_config, &sim);
+    let scale = render_config.world_scale;
+    let min = Vec2::new(
+        sim.domain.min.x * scale + 20.0,
+        sim.domain.min.y * scale + 40.0,
+    );
+    let columns = LEGEND_SIZE.x as usize / 2;
+    for column in 0..=columns {
+        let t = column as f32 / columns as f32;
+        let x = min.x + t * LEGEND_SIZE.x;
+        gizmos.line_2d(
+            Vec2::new(x, min.y),
+            Vec2::new(x, min.y + LEGEND_SIZE.y),
+            color_field_color(field, t),
+        );
+    }
+    gizmos.rect_2d(
+        Isometry2d::from_translation(min + LEGEND_SIZE * 0.5),
+        LEGEND_SIZE,
+        Color::srgba(1.0, 1.0, 1.0, 0.4),
+    );
+
+    for (mut text, mut transform, _, label) in &mut labels {
+        let (content, anchor) = match label {
+            ColorLegendLabel::Min => (format!("{lo:.2}"), Vec2::new(0.0, -12.0)),
+            ColorLegendLabel::Max => (format!("{hi:.2}"), Vec2::new(LEGEND_SIZE.x, -12.0)),
+            ColorLegendLabel::Title => (
+                match (field, render_config.color_range) {
+                    (ColorField::Id, _) => "Id (last: spawned)".to_owned(),
+                    (ColorField::Velocity, range) => format!("Speed ({})", range_mode(range)),
+                    (ColorField::Divergence, range) => {
+                        format!("Divergence, red compresses ({})", range_mode(range))
+                    }
+                    (ColorField::Separation, range) => {
+                        format!("A/B separation ({})", range_mode(range))
+                    }
+                },
+                Vec2::new(LEGEND_SIZE.x * 0.5, LEGEND_SIZE.y + 12.0),
+            ),
+        };
+        if text.0 != content {
+            text.0 = content;
+        }
+        transform.translation = (min + anchor).extend(1.0);
+    }
+}
+
+/// Narrowest view auto-framing zooms in to, as a camera scale (1.0 = one pixel per unit).
+const AUTO_FRAME_MIN_SCALE: f32 = 0.25;
+
+/// While `auto_frame` is on, eases the camera towards the fluid's center of mass and zooms
+/// so its bounding box, grown by `auto_frame_margin`, fits the window. With `--compare`
+/// both instances are framed together. Turning it off snaps back to the whole-domain view
+/// `setup_scene` starts with.
+pub fn auto_frame_camera(
+    time: Res<Time>,
+    sim: Res<FluidSimulation>,
+    comparison: Option<Res<ComparisonSim>>,
+    render_config: Res<RenderConfig>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    mut q_camera: Query<(&mut Transform, &mut Projection), With<Camera2d>>,
+    mut was_framing: Local<bool>,
+) {
+    let Ok((mut transform, mut projection)) = q_camera.single_mut() else {
+        return;
+    };
+    let Projection::Orthographic(ortho) = &mut *projection else {
+        return;
+    };
+    let scale = render_config.world_scale;
+    if !render_config.auto_frame {
+        if std::mem::take(&mut *was_framing) {
+            let domain = comparison
+                .as_ref()
+                .map_or(sim.domain, |other| sim.domain.union(other.0.domain));
+            transform.translation = (domain.center() * scale).extend(transform.translation.z);
+            ortho.scale = 1.0;
+        }
+        return;
+    }
+    *was_framing = true;
+    let Ok(window) = q_window.single() else {
+        return;
+    };
+
+    let (mut min, mut max) = sim.bounding_box();
+    let mut center = sim.center_of_mass();
+    if let Some(other) = &comparison {
+        let (other_min, other_max) = other.0.bounding_box();
+        min = min.min(other_min);
+        max = max.max(other_max);
+        center = (center + other.0.center_of_mass()) * 0.5;
+    }
+    // Centered on the center of mass, the view must reach the box's farther side.
+    let half_extent = (center - min).max(max - center) * (1.0 + render_config.auto_frame_margin);
+    let fit = half_extent * 2.0 * scale / window.size().max(Vec2::ONE);
+    let target_scale = fit.max_element().max(AUTO_FRAME_MIN_SCALE);
+
+    let blend = 1.0 - (-render_config.auto_frame_rate.max(0.0) * time.delta_secs()).exp();
+    let target = (center * scale).extend(transform.translation.z);
+    transform.translation = transform.translation.lerp(target, blend);
+    ortho.scale += (target_scale - ortho.scale) * blend;
+}
+
+/// Synchronizes particle visual representation with simulation state.
+/// Updates positions (optionally interpolated between physics steps) and colors particles
+/// by `RenderConfig::color_field`.
+/// Simulation units are converted to pixels with `RenderConfig::world_scale`.
+/// The `--compare` instance shares the main instance's color range, so colors compare directly.
+#[allow(clippy::too_many_arguments)]
+pub fn sync_rendering(
+    sim: Res<FluidSimulation>,
+    config: Res<FluidConfig>,
+    render_config: Res<RenderConfig>,
+    mut query: Query<ParticleVisual, Without<ComparisonParticle>>,
+    comparison: Option<Res<ComparisonSim>>,
+    comparison_config: Option<Res<ComparisonConfig>>,
+    mut comparison_query: Query<ParticleVisual, With<ComparisonParticle>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    mut profiler: ResMut<PhaseProfiler>,
+) {
+    let start = Instant::now();
+    let range = color_field_range(&render_config, &sim);
+    let view = if render_config.cull_offscreen {
+        q_camera
+            .single()
+            .ok()
+            .and_then(|(camera, transform)| visible_world_rect(camera, transform))
+            // Simulation units, grown by one sprite so particles on the edge stay drawn.
+            .map(|rect| {
+                Rect::from_corners(
+                    rect.min / render_config.world_scale,
+                    rect.max / render_config.world_scale,
+                )
+                .inflate(sim.particle_radius * 2.5)
+            })
+    } else {
+        None
+    };
+    sync_particle_set(&sim, &config, &render_config, range, view, &mut query);
+    if let (Some(other), Some(other_config)) = (comparison, comparison_config) {
+        sync_particle_set(
+            &other.0,
+            &other_config.0,
+            &render_config,
+            range,
+            view,
+            &mut comparison_query,
+        );
+    }
+    let mut times = PhaseTimes::default();
+    times.lap(Phase::RenderSync, start);
+    profiler.record(&times);
+}
+
+/// World-space rectangle (in pixels) seen by `camera`, or `None` before its viewport is known.
+fn visible_world_rect(camera: &Camera, transform: &GlobalTransform) -> Option<Rect> {
+    let viewport = camera.logical_viewport_rect()?;
+    let a = camera.viewport_to_world_2d(transform, viewport.min).ok()?;
+    let b = camera.viewport_to_world_2d(transform, viewport.max).ok()?;
+    Some(Rect::from_corners(a, b))
+}
+
+/// Writes one instance's particle state to its visual entities, coloring the color field's
+/// values over `(lo, hi)`. Particles outside `view` (simulation units), inactive ones and
+/// those thinned out by `max_sprites` are hidden and not synced.
+fn sync_particle_set<F: QueryFilter>(
+    sim: &FluidSimulation,
+    config: &FluidConfig,
+    render_config: &RenderConfig,
+    (lo, hi): (f32, f32),
+    view: Option<Rect>,
+    query: &mut Query<ParticleVisual, F>,
+) {
+    let stride = match render_config.max_sprites {
+        0 => 1,
+        limit => sim.active_count.div_ceil(limit).max(1),
+    };
+    let scale = render_config.world_scale;
+    let field = render_config.color_field;
+    let alpha = sim.interpolation_alpha.clamp(0.0, 1.0);
+    let interpolate = config.interpolate_rendering && alpha < 1.0;
+    // Particles that move further than half the domain height between two steps were
+    // teleported (reset, wrap) and snap to their new position instead of interpolating.
+    let snap_distance = sim.domain.height() * 0.5;
+    let snap_sq = snap_distance * snap_distance;
+    query
+        .par_iter_mut()
+        .for_each(|(mut t, mut visibility, sprite, tag, pid)| {
+            let i = pid.0;
+            let in_view = |&pos: &Vec2| view.is_none_or(|view| view.contains(pos));
+            let shown = i < sim.active_count
+                && i % stride == 0
+                && sim.positions.get(i).is_some_and(in_view);
+            visibility.set_if_neq(if shown {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            });
+            if !shown {
+                return;
+            }
+            if let Some(&current) = sim.positions.get(i) {
+                let pos = match sim.prev_positions.get(i) {
+                    Some(&prev) if interpolate && prev.distance_squared(current) < snap_sq => {
+                        prev.lerp(current, alpha)
+                    }
+                    _ => current,
+                };
+                t.translation.x = pos.x * scale;
+                t.translation.y = pos.y * scale;
+                t.translation.z = (i % 100) as f32 * 0.001;
+                t.scale = Vec3::splat(scale);
+            }
+            if !render_config.rotate_to_velocity {
+                t.rotation = Quat::IDENTITY;
+            } else if let Some(&vel) = sim.velocities.get(i)
+                && vel.length_squared() > MIN_ROTATION_SPEED_SQ
+            {
+                t.rotation = Quat::from_rotation_z(vel.to_angle());
+            }
+            let value = match field {
+                ColorField::Velocity => sim.velocities.get(i).map(|v| v.length()),
+                ColorField::Divergence => sim.divergences.get(i).copied(),
+                ColorField::Separation => sim.separations.get(i).copied(),
+                ColorField::Id => sim
+                    .ids
+                    .get(i)
+                    .map(|&id| (id as usize % ID_COLORS.len()) as f32),
+            };
+            let color =
+                if config.highlight_surface && sim.is_surface.get(i).copied().unwrap_or(false) {
+                    Color::srgb(1.0, 0.5, 0.1)
+                } else if let Some(value) = value {
+                    color_field_color(field, (value - lo) / (hi - lo))
+                } else {
+                    return;
+                };
+            let color = match sim.ages.get(i) {
+                Some(&age) if render_config.fade_in_time > 0.0 => {
+                    color.with_alpha((age / render_config.fade_in_time).min(1.0))
+                }
+                _ => color,
+            };
+            if let Some(mut s) = sprite {
+                s.color = color;
+            }
+            if let Some(mut tag) = tag {
+                tag.0 = pack_color(color);
+            }
+        });
+}
+
+/// Closes the profiler's frame and, when 'F' is pressed, prints the mean milliseconds per
+/// frame of each simulation phase over the last second, to show where the time goes.
+pub fn report_profile(input: Res<ButtonInput<KeyCode>>, mut profiler: ResMut<PhaseProfiler>) {
+    profiler.end_frame(Instant::now());
+    if !input.just_pressed(KeyCode::KeyF) {
+        return;
+    }
+    let (mean, frames) = profiler.averages();
+    let mut out =
+        format!("# physics profile, ms per frame over the last second ({frames} frames)\n");
+    for phase in Phase::ALL {
+        out.push_str(&format!(
+            "{:<14}{:>8.3}\n",
+            phase.label(),
+            mean.0[phase as usize]
+        ));
+    }
+    out.push_str(&format!(
+        "{:<14}{:>8.3}",
+        "Total",
+        mean.0.iter().sum::<f32>()
+    ));
+    println!("{out}");
+}
+
+#[cfg(test)]
+mod tests;
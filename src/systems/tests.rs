@@ -0,0 +1,930 @@
+//! Headless scenario tests of the physics step.
+
+use super::*;
+use crate::{
+    gpu,
+    resources::{Backend, PARTICLE_RADIUS},
+};
+
+/// Time step of the scenario tests, matching the default `time_scale`.
+fn test_dt(config: &FluidConfig) -> f32 {
+    0.002 * config.time_scale
+}
+
+/// Advances `sim` by `steps` CPU steps of `dt` without interaction.
+fn run(
+    sim: &mut FluidSimulation,
+    config: &FluidConfig,
+    elements: &SceneElements,
+    dt: f32,
+    steps: usize,
+) {
+    for _ in 0..steps {
+        step_simulation(
+            sim,
+            config,
+            elements,
+            Interaction::default(),
+            dt,
+            NeighborSource::Grid,
+            &mut PhaseTimes::default(),
+            None,
+            None,
+        );
+    }
+}
+
+/// Advances `sim` by CPU steps of `dt` until the `SettleDetector` fed with its kinetic energy
+/// fires for `config.settle_energy` and `config.settle_duration`, as `detect_settling` does in
+/// the app. Returns the steps taken, or `None` if it had not settled after `max_steps`.
+fn run_until_settled(
+    sim: &mut FluidSimulation,
+    config: &FluidConfig,
+    elements: &SceneElements,
+    dt: f32,
+    max_steps: usize,
+) -> Option<usize> {
+    let mut detector = SettleDetector::default();
+    (1..=max_steps).find(|_| {
+        run(sim, config, elements, dt, 1);
+        let n = sim.active_count;
+        let kinetic_energy = total_kinetic_energy(
+            &sim.velocities[..n],
+            &sim.mass_factors[..n],
+            config.particle_mass,
+        );
+        detector.update(
+            kinetic_energy,
+            config.settle_energy,
+            config.settle_duration,
+            dt,
+        )
+    })
+}
+
+/// Sound speed of `config`'s equation of state at its target density.
+fn sound_speed(config: &FluidConfig) -> f32 {
+    config
+        .pressure_eos
+        .sound_speed(config.target_density, config.pressure_multiplier)
+}
+
+/// Steps of `dt` a pressure wave takes to travel `distance` through `config`'s fluid.
+fn sound_travel_steps(config: &FluidConfig, distance: f32, dt: f32) -> usize {
+    (distance / sound_speed(config) / dt).ceil() as usize
+}
+
+/// Steps of `dt` a body dropped from rest takes to fall `distance` under `config`'s gravity.
+fn fall_steps(config: &FluidConfig, distance: f32, dt: f32) -> usize {
+    ((2.0 * distance / config.current_gravity().length()).sqrt() / dt).ceil() as usize
+}
+
+/// Steps over which `linear_damping` shrinks every velocity by a factor `e` `times` times.
+fn damping_steps(config: &FluidConfig, times: f32) -> usize {
+    (times / -config.linear_damping.ln()).ceil() as usize
+}
+
+/// A simulation holding only a `drop_blob` blob of `config`'s size.
+fn blob(config: &FluidConfig) -> FluidSimulation {
+    let mut sim = FluidSimulation::new(config.seed);
+    sim.load_particles(&[], &[], 0);
+    sim.drop_blob(config.blob_radius, config.blob_particle_count);
+    sim
+}
+
+/// Largest distance between matching particles of two runs of the same layout.
+fn max_deviation(a: &FluidSimulation, b: &FluidSimulation) -> f32 {
+    let n = a.active_count;
+    a.positions[..n]
+        .iter()
+        .zip(&b.positions[..n])
+        .map(|(a, b)| a.distance(*b))
+        .fold(0.0, f32::max)
+}
+
+/// The default config without gravity.
+fn weightless() -> FluidConfig {
+    FluidConfig {
+        gravity_enabled: false,
+        ..default()
+    }
+}
+
+/// Where a lone particle starting `50` above the origin ends up after one step at a speed
+/// that carries it 200 below the origin, with `elements` in the way.
+fn fast_particle_end(elements: &SceneElements) -> Vec2 {
+    let config = weightless();
+    let dt = test_dt(&config);
+    let mut sim = FluidSimulation::new(config.seed);
+    sim.load_particles(&[Vec2::new(0.0, 50.0)], &[Vec2::new(0.0, -250.0 / dt)], 1);
+    run(&mut sim, &config, elements, dt, 1);
+    sim.positions[0]
+}
+
+/// Damping e-folding times the blob spacing test lets each blob float for. The packed blob
+/// springs apart and rings; after these its motion is down to under 1% of the start.
+const BLOB_SPACING_DAMPING_TIMES: f32 = 5.0;
+
+/// Drops a blob into an otherwise empty domain, lets it float until its ringing has been
+/// damped out and returns the smallest distance between two particles. Without gravity only
+/// the pressure, viscosity and cohesion forces set the blob's spacing.
+fn blob_min_spacing(config: &FluidConfig) -> f32 {
+    let mut sim = blob(config);
+    let dt = test_dt(config);
+    let steps = damping_steps(config, BLOB_SPACING_DAMPING_TIMES);
+    deterministic_pool().install(|| run(&mut sim, config, &SceneElements::default(), dt, steps));
+    let positions = &sim.positions[..sim.active_count];
+    positions
+        .iter()
+        .enumerate()
+        .flat_map(|(i, a)| positions[i + 1..].iter().map(move |b| a.distance(*b)))
+        .fold(f32::INFINITY, f32::min)
+}
+
+#[test]
+fn background_pressure_spaces_out_blob() {
+    // The linear law's pressure bottoms out at `-c²ρ0` in empty space; this baseline lifts it
+    // to zero there, so no pair of particles ever pulls together.
+    let config = weightless();
+    let floor = sound_speed(&config).powi(2) * config.target_density;
+    let [without, with] = [0.0, floor].map(|background_pressure| {
+        blob_min_spacing(&FluidConfig {
+            background_pressure,
+            ..config.clone()
+        })
+    });
+    assert!(
+        with > without,
+        "closest pair {with} with background pressure, {without} without"
+    );
+}
+
+/// Places two particles half a smoothing radius apart in an otherwise empty domain, steps
+/// them once and returns the larger of their speeds divided by the step, about the
+/// acceleration they felt. Their densities are far below `target_density`.
+fn isolated_pair_acceleration(config: &FluidConfig) -> f32 {
+    let dt = test_dt(config);
+    let mut sim = FluidSimulation::new(config.seed);
+    let center = sim.domain.center();
+    let offset = Vec2::X * config.smoothing_radius * 0.25;
+    sim.load_particles(&[center - offset, center + offset], &[], 2);
+    run(&mut sim, config, &SceneElements::default(), dt, 1);
+    sim.velocities[..2]
+        .iter()
+        .map(|vel| vel.length() / dt)
+        .fold(0.0, f32::max)
+}
+
+#[test]
+fn density_floor_bounds_isolated_pair() {
+    let [without, with] = [0.0, 0.5].map(|density_floor| {
+        isolated_pair_acceleration(&FluidConfig {
+            density_floor,
+            ..weightless()
+        })
+    });
+    assert!(
+        with.is_finite() && with < without,
+        "acceleration {with} with a density floor, {without} without"
+    );
+}
+
+#[test]
+fn reused_grid_matches_rebuilt_grid() {
+    let config = FluidConfig::default();
+    let skin = config.smoothing_radius * 0.4;
+    let dt = test_dt(&config);
+    // Long enough for a pressure wave to cross two kernel supports, so the layout has moved
+    // by more than the skin near its edges and reuse has had to give way to rebuilds.
+    let steps = sound_travel_steps(&config, 4.0 * config.smoothing_radius, dt);
+    let elements = SceneElements::default();
+    let [rebuilt, reused] = [0.0, skin].map(|neighbor_skin| {
+        let config = FluidConfig {
+            neighbor_skin,
+            ..config.clone()
+        };
+        // The calm grid layout lets the grid be reused; the random one moves too fast at first.
+        let mut sim = FluidSimulation::new(config.seed);
+        sim.reset_to_grid(config.seed, config.init_jitter, &[]);
+        deterministic_pool().install(|| run(&mut sim, &config, &elements, dt, steps));
+        sim
+    });
+    assert!(
+        reused.grid_rebuilds < steps,
+        "the grid was rebuilt on all {steps} steps"
+    );
+    let deviation = max_deviation(&rebuilt, &reused);
+    assert!(
+        deviation <= config.smoothing_radius * 1e-3,
+        "reused grid differs by up to {deviation}"
+    );
+}
+
+/// Drops a blob into an empty domain and returns how far the mean density of its outer rim
+/// (within half a smoothing radius of the edge) falls below that of its inner half, as a
+/// fraction of the inner mean: first for the plain summation, then after `shepard_filter`.
+fn shepard_surface_deficit(config: &FluidConfig) -> (f32, f32) {
+    let mut sim = blob(config);
+    let n = sim.active_count;
+    let h = config.smoothing_radius;
+    sim.configure_grid(
+        h,
+        config.grid_scan_radius,
+        config.grid_cell_size,
+        config.grid_margin,
+    );
+    sim.bin_particles(n);
+    let min_dist = h * config.min_dist_fraction.clamp(0.0, 1.0);
+    let neighbors = Neighbors::Grid(sim.grid_view());
+    let kernels = KernelCache::new(h, config.kernel_table_size);
+    let raw: Vec<f32> = (0..n)
+        .map(|i| {
+            density_at(
+                &neighbors,
+                &sim.positions,
+                &sim.mass_factors,
+                i,
+                &kernels,
+                min_dist * min_dist,
+                config.particle_mass,
+            )
+        })
+        .collect();
+    let corrected = shepard_filter(
+        &neighbors,
+        &sim.positions,
+        &sim.mass_factors,
+        &raw,
+        n,
+        &kernels,
+        min_dist * min_dist,
+        config.particle_mass,
+        config.min_density(),
+    );
+
+    let (lo, hi) = sim.bounding_box();
+    let center = (lo + hi) * 0.5;
+    let radii: Vec<f32> = sim.positions[..n]
+        .iter()
+        .map(|p| p.distance(center))
+        .collect();
+    let outer = radii.iter().copied().fold(0.0, f32::max);
+    let deficit = |densities: &[f32]| {
+        let mean_where = |keep: &dyn Fn(f32) -> bool| {
+            let picked: Vec<f32> = (0..n)
+                .filter(|&i| keep(radii[i]))
+                .map(|i| densities[i])
+                .collect();
+            par_mean(&picked)
+        };
+        let bulk = mean_where(&|r| r < outer * 0.5);
+        let rim = mean_where(&|r| r > outer - h * 0.5);
+        1.0 - rim / bulk
+    };
+    (deficit(&raw), deficit(&corrected))
+}
+
+#[test]
+fn shepard_filter_reduces_surface_deficit() {
+    let (raw, corrected) = shepard_surface_deficit(&FluidConfig::default());
+    assert!(
+        corrected.abs() < raw.abs(),
+        "rim {corrected} below the bulk Shepard-filtered, {raw} summed"
+    );
+}
+
+#[test]
+fn single_shear_mode_peaks_in_its_shell() {
+    const MODE: usize = 8;
+    let config = FluidConfig::default();
+    let mut sim = FluidSimulation::new(config.seed);
+    let n = sim.active_count;
+    let h = config.smoothing_radius;
+    sim.configure_grid(
+        h,
+        config.grid_scan_radius,
+        config.grid_cell_size,
+        config.grid_margin,
+    );
+    sim.bin_particles(n);
+    let min_dist = h * config.min_dist_fraction.clamp(0.0, 1.0);
+    let kernels = KernelCache::new(h, config.kernel_table_size);
+    let densities: Vec<f32> = {
+        let neighbors = Neighbors::Grid(sim.grid_view());
+        (0..n)
+            .map(|i| {
+                density_at(
+                    &neighbors,
+                    &sim.positions,
+                    &sim.mass_factors,
+                    i,
+                    &kernels,
+                    min_dist * min_dist,
+                    config.particle_mass,
+                )
+            })
+            .collect()
+    };
+    sim.densities[..n].copy_from_slice(&densities);
+    let (origin, size) = (sim.domain.min, sim.domain.size());
+    for i in 0..n {
+        let phase = std::f32::consts::TAU * MODE as f32 * (sim.positions[i].x - origin.x) / size.x;
+        sim.velocities[i] = Vec2::new(0.0, phase.sin());
+    }
+
+    let spectrum = velocity_spectrum(&sim, &config, RenderConfig::default().spectrum_resolution);
+    let peak = spectrum
+        .iter()
+        .enumerate()
+        .skip(1)
+        .max_by(|(_, a), (_, b)| a.1.total_cmp(&b.1))
+        .map_or(0, |(shell, _)| shell);
+    let expected = (MODE as f32 * size.max_element() / size.x).round() as usize;
+    assert_eq!(peak, expected, "shear mode {MODE} peaks in the wrong shell");
+}
+
+/// Runs the `paddle_wheel` demo over a random layout for `steps` with its wheel turning at
+/// `angular_velocity` and returns the fluid's angular momentum about the wheel center,
+/// counter-clockwise positive like the wheel's default spin.
+fn paddle_wheel_angular_momentum(config: &FluidConfig, angular_velocity: f32, steps: usize) -> f32 {
+    let mut sim = FluidSimulation::new(config.seed);
+    let wheels = paddle_wheel(sim.domain);
+    let center = wheels[0].center;
+    let elements = SceneElements {
+        rotating_obstacles: wheels
+            .iter()
+            .map(|&wheel| RotatingObstacle {
+                angular_velocity,
+                ..wheel
+            })
+            .collect(),
+        ..default()
+    };
+    run(&mut sim, config, &elements, test_dt(config), steps);
+    (0..sim.active_count)
+        .map(|i| {
+            let mass = config.particle_mass * sim.mass_factors[i];
+            mass * (sim.positions[i] - center).perp_dot(sim.velocities[i])
+        })
+        .sum()
+}
+
+#[test]
+fn paddle_wheel_drags_fluid_around() {
+    let config = FluidConfig::default();
+    let spin = RotatingObstacle::default().angular_velocity;
+    // One full turn, so every arm has swept the fluid wherever the arms started.
+    let steps = (std::f32::consts::TAU / spin / test_dt(&config)).ceil() as usize;
+    let spinning = paddle_wheel_angular_momentum(&config, spin, steps);
+    let still = paddle_wheel_angular_momentum(&config, 0.0, steps);
+    assert!(
+        spinning > still.abs(),
+        "angular momentum {spinning} with the wheel spinning, {still} held still"
+    );
+}
+
+#[test]
+fn frozen_particles_hold_until_released() {
+    let config = FluidConfig::default();
+    let dt = test_dt(&config);
+    let mut sim = FluidSimulation::new(config.seed);
+    // Each phase lasts as long as a fall through the whole domain: held, the free lower half
+    // has slumped onto the floor and pushes up against it; released, the upper half has come
+    // down whatever the fluid beneath it does.
+    let steps = fall_steps(&config, sim.domain.height(), dt);
+    let mut elements = SceneElements {
+        freeze_regions: upper_half_freeze(sim.domain),
+        ..default()
+    };
+    let held: Vec<usize> = (0..sim.active_count)
+        .filter(|&i| frozen_at(&elements.freeze_regions, sim.positions[i]))
+        .collect();
+    assert!(!held.is_empty());
+    let start: Vec<Vec2> = held.iter().map(|&i| sim.positions[i]).collect();
+    run(&mut sim, &config, &elements, dt, steps);
+    for (&i, &p) in held.iter().zip(&start) {
+        assert_eq!(sim.positions[i], p, "held particle {i} moved");
+    }
+
+    let mean_height = |sim: &FluidSimulation| {
+        held.iter().map(|&i| sim.positions[i].y).sum::<f32>() / held.len() as f32
+    };
+    let before = mean_height(&sim);
+    elements.freeze_regions.clear();
+    run(&mut sim, &config, &elements, dt, steps);
+    let fall = before - mean_height(&sim);
+    assert!(fall > 0.0, "released particles rose by {}", -fall);
+}
+
+#[test]
+fn fast_particle_stops_at_thin_wall() {
+    let elements = SceneElements {
+        segments: vec![Segment::new(Vec2::new(-100.0, 0.0), Vec2::new(100.0, 0.0))],
+        ..default()
+    };
+    let end = fast_particle_end(&elements);
+    assert!(end.y > 0.0, "tunneled through the wall to {end}");
+}
+
+#[test]
+fn fast_particle_stops_at_small_obstacles() {
+    for obstacle in [
+        Obstacle::Circle {
+            center: Vec2::ZERO,
+            radius: 3.0,
+        },
+        Obstacle::Box {
+            center: Vec2::ZERO,
+            half_size: Vec2::new(50.0, 2.0),
+        },
+    ] {
+        let elements = SceneElements {
+            obstacles: vec![obstacle],
+            ..default()
+        };
+        let end = fast_particle_end(&elements);
+        assert!(end.y > 0.0, "tunneled through {obstacle:?} to {end}");
+    }
+}
+
+/// Steps a resting particle wedged in the notch where a circular obstacle sinks into a
+/// wide box once and returns how deep it still overlaps either of them, as a fraction of
+/// its radius. The circle pushes it down into the box and the box pushes it back up, so a
+/// single collision pass leaves it inside the circle.
+fn wedged_particle_overlap(collision_iterations: u32) -> f32 {
+    let config = FluidConfig {
+        collision_iterations,
+        ..weightless()
+    };
+    let mut sim = FluidSimulation::new(config.seed);
+    let center = sim.domain.center();
+    let radius = config.smoothing_radius;
+    // The circle is listed first, matching the order that leaves the particle wedged.
+    let elements = SceneElements {
+        obstacles: vec![
+            Obstacle::Circle {
+                center: center + Vec2::Y * radius * 0.5,
+                radius,
+            },
+            Obstacle::Box {
+                center: center - Vec2::Y * radius,
+                half_size: Vec2::new(radius * 4.0, radius),
+            },
+        ],
+        ..default()
+    };
+    // Beside where the bare circle crosses the top of the box, overlapping both.
+    let notch = center + Vec2::new(-radius * 0.75_f32.sqrt(), sim.particle_radius * 0.5);
+    sim.load_particles(&[notch], &[], 1);
+    run(&mut sim, &config, &elements, test_dt(&config), 1);
+    let pos = sim.positions[0];
+    elements
+        .obstacles
+        .iter()
+        .filter_map(|obstacle| obstacle.resolve(pos, sim.particle_radius))
+        .map(|(surface, _)| surface.distance(pos))
+        .fold(0.0, f32::max)
+        / sim.particle_radius
+}
+
+#[test]
+fn collision_passes_free_wedged_particle() {
+    let single = wedged_particle_overlap(1);
+    assert!(single > 0.01, "one pass already freed the particle");
+    let iterated = wedged_particle_overlap(4);
+    assert!(
+        iterated <= 0.01,
+        "still {iterated} radii deep after four passes"
+    );
+}
+
+#[test]
+fn wrapped_particle_is_pushed_out_of_obstacle() {
+    let config = FluidConfig {
+        boundaries: DomainBoundaries {
+            left: BoundaryBehavior::Periodic,
+            right: BoundaryBehavior::Periodic,
+            ..default()
+        },
+        collision_iterations: 2,
+        ..weightless()
+    };
+    let dt = test_dt(&config);
+    let mut sim = FluidSimulation::new(config.seed);
+    let bounds = sim.domain.inflate(-sim.particle_radius);
+    let center = Vec2::new(bounds.min.x, bounds.center().y);
+    let radius = config.smoothing_radius;
+    let elements = SceneElements {
+        obstacles: vec![Obstacle::Circle { center, radius }],
+        ..default()
+    };
+    // One step carries the particle 5 past the right wall, which wraps it into the circle.
+    let start = Vec2::new(bounds.max.x - 5.0, center.y);
+    sim.load_particles(&[start], &[Vec2::X * 10.0 / dt], 1);
+    run(&mut sim, &config, &elements, dt, 1);
+    let pos = sim.positions[0];
+    assert!(
+        pos.distance(center) >= radius + sim.particle_radius - 1e-3,
+        "left inside the obstacle at {pos}"
+    );
+}
+
+#[test]
+fn mass_scaling_keeps_total_mass() {
+    let mut config = FluidConfig {
+        auto_mass_scaling: true,
+        ..default()
+    };
+    let mass = config.particle_mass;
+    let mut sim = FluidSimulation::new(config.seed);
+    let total = |sim: &FluidSimulation, config: &FluidConfig| {
+        config.particle_mass * sim.active_count as f32
+    };
+    let full = total(&sim, &config);
+    let live = sim.live_count;
+    set_active_count_keeping_mass(&mut sim, &mut config, live / 2);
+    let halved = total(&sim, &config);
+    assert!(
+        (halved - full).abs() <= full * 1e-5,
+        "total mass {full} became {halved}"
+    );
+    set_active_count_keeping_mass(&mut sim, &mut config, live);
+    assert!(
+        (config.particle_mass - mass).abs() <= mass * 1e-5,
+        "particle mass {mass} came back as {}",
+        config.particle_mass
+    );
+}
+
+#[test]
+fn grid_matches_brute_force_neighbors() {
+    let config = FluidConfig::default();
+    let mut sim = FluidSimulation::new(7);
+    run(
+        &mut sim,
+        &config,
+        &SceneElements::default(),
+        test_dt(&config),
+        5,
+    );
+    let n = sim.active_count;
+    // The step bins the particles before moving them, so bin them where they ended up.
+    sim.bin_particles(n);
+    let h = config.smoothing_radius;
+    let kernels = KernelCache::new(h, config.kernel_table_size);
+    let grid = Neighbors::Grid(sim.grid_view());
+    let all = Neighbors::All(n);
+    let within_h = |neighbors: &Neighbors, i: usize| {
+        let pos = sim.positions[i];
+        let mut found = Vec::new();
+        neighbors.for_each_candidate(pos, |j| {
+            if j < n && pos.distance(sim.positions[j]) < h {
+                found.push(j);
+            }
+        });
+        found.sort_unstable();
+        found
+    };
+    for i in 0..n {
+        assert_eq!(
+            within_h(&grid, i),
+            within_h(&all, i),
+            "neighbors of particle {i}"
+        );
+        let [from_grid, from_all] = [&grid, &all].map(|neighbors| {
+            scalar_density(
+                neighbors,
+                &sim.positions,
+                &sim.mass_factors,
+                i,
+                &kernels,
+                0.0,
+                config.particle_mass,
+            )
+        });
+        assert!(
+            (from_grid - from_all).abs() <= from_all * 1e-5,
+            "density of particle {i}: {from_grid} from the grid, {from_all} from every pair"
+        );
+    }
+}
+
+#[test]
+fn bruteforce_step_matches_grid_step() {
+    let config = FluidConfig::default();
+    let dt = test_dt(&config);
+    let h = config.smoothing_radius;
+    // Long enough for a pressure wave to cross two smoothing radii, so forces have passed
+    // through neighbors of neighbors. The two sum in different orders, and the rounding
+    // differences grow several times over every few steps of the collapsing layout.
+    let steps = sound_travel_steps(&config, 2.0 * h, dt);
+    let [grid, brute_force] = [false, true].map(|all_pairs| {
+        let mut sim = FluidSimulation::new(config.seed);
+        deterministic_pool().install(|| {
+            for _ in 0..steps {
+                if all_pairs {
+                    sim.step_bruteforce(&config, dt);
+                } else {
+                    sim.step(&config, dt);
+                }
+            }
+        });
+        sim
+    });
+    let deviation = max_deviation(&grid, &brute_force);
+    assert!(
+        deviation <= h * 1e-3,
+        "grid and brute-force runs differ by up to {deviation} after {steps} steps"
+    );
+}
+
+#[test]
+fn time_reversal_returns_to_start() {
+    // Inviscid, undamped and weightless, so an ideal solver would return exactly.
+    let config = FluidConfig {
+        viscosity_strength: 0.0,
+        linear_damping: 1.0,
+        boundary_damping: 1.0,
+        boundary_friction: 0.0,
+        auto_calibrate_target_density: true,
+        ..weightless()
+    };
+    let dt = test_dt(&config);
+    // Long enough for a pressure wave to cross four kernel supports, so every particle has
+    // been pushed by particles well outside its own neighborhood.
+    let steps = sound_travel_steps(&config, 8.0 * config.smoothing_radius, dt);
+    let sim = FluidSimulation::new(config.seed);
+    let (mean, max) = time_reversal_error(&sim, &config, steps);
+    // Each step rounds a position by up to an ulp of the largest coordinate, so the round
+    // trip can leave about `2 * steps` of them.
+    let extent = sim.domain.max.abs().max(sim.domain.min.abs()).max_element()
+        + 2.0 * config.smoothing_radius;
+    let tolerance = 2.0 * steps as f32 * f32::EPSILON * extent;
+    assert!(
+        max <= tolerance,
+        "return error up to {max} (mean {mean}) after {steps} + {steps} steps, \
+         allowed {tolerance}"
+    );
+}
+
+#[test]
+fn blob_falls_freely() {
+    let config = FluidConfig {
+        linear_damping: 1.0,
+        ..default()
+    };
+    let dt = test_dt(&config);
+    let g = config.gravity_magnitude.abs();
+    let mut sim = blob(&config);
+    let (min, max) = sim.bounding_box();
+    let reach = Vec2::splat(config.blob_radius * (1.0 + 1e-4));
+    assert!(
+        (max - min).cmple(reach * 2.0).all(),
+        "blob spans {min} to {max}"
+    );
+    // `drop_blob` starts just below the ceiling, which the spreading blob would push off.
+    let shift = sim.domain.center() - sim.center_of_mass();
+    for pos in &mut sim.positions[..sim.active_count] {
+        *pos += shift;
+    }
+    let start = sim.center_of_mass();
+    // As long as the blob takes to fall its own radius. Its packed lattice springs apart,
+    // but this stops before the spreading particles reach a wall.
+    let steps = fall_steps(&config, config.blob_radius, dt);
+    run(&mut sim, &config, &SceneElements::default(), dt, steps);
+    // Internal SPH forces cancel in the sum. Semi-implicit Euler moves a free particle
+    // |g| dt² k(k + 1) / 2 in `k` steps.
+    let fallen = (start - sim.center_of_mass()).y;
+    let k = steps as f32;
+    let expected = g * dt * dt * k * (k + 1.0) * 0.5;
+    let error = (fallen - expected).abs() / expected;
+    assert!(
+        error <= 0.01,
+        "fell {fallen} in {steps} steps, free fall predicts {expected}"
+    );
+}
+
+#[test]
+#[ignore = "needs a GPU adapter with compute shaders; run with `cargo test -- --ignored`"]
+fn gpu_backend_matches_cpu() {
+    let mut solver = gpu::headless_solver().expect("no GPU adapter with compute shaders");
+    let config = FluidConfig {
+        backend: Backend::Gpu,
+        ..default()
+    };
+    assert_eq!(gpu::unsupported(&config), None);
+    let dt = test_dt(&config);
+    // As long as a pressure wave takes to cross one kernel support, so every particle has
+    // felt its neighbors' forces; the GPU's own rounding makes the runs drift apart over
+    // many more.
+    let steps = sound_travel_steps(&config, config.smoothing_radius, dt);
+    let elements = SceneElements::default();
+    let [cpu, on_gpu] = [false, true].map(|use_gpu| {
+        let mut sim = FluidSimulation::new(config.seed);
+        deterministic_pool().install(|| {
+            for _ in 0..steps {
+                step_simulation(
+                    &mut sim,
+                    &config,
+                    &elements,
+                    Interaction::default(),
+                    dt,
+                    NeighborSource::Grid,
+                    &mut PhaseTimes::default(),
+                    None,
+                    use_gpu.then_some(&mut solver),
+                );
+            }
+        });
+        sim
+    });
+    let deviation = max_deviation(&cpu, &on_gpu);
+    assert!(
+        deviation <= config.smoothing_radius * 1e-3,
+        "CPU and GPU runs differ by up to {deviation} after {steps} steps"
+    );
+}
+
+#[cfg(feature = "simd")]
+#[test]
+fn simd_density_matches_scalar() {
+    let config = FluidConfig::default();
+    let mut sim = FluidSimulation::new(config.seed);
+    run(
+        &mut sim,
+        &config,
+        &SceneElements::default(),
+        test_dt(&config),
+        5,
+    );
+    let n = sim.active_count;
+    let h = config.smoothing_radius;
+    let min_dist = h * config.min_dist_fraction.clamp(0.0, 1.0);
+    let min_dist_sq = min_dist * min_dist;
+    let mass = config.particle_mass;
+    let grid = sim.grid_view();
+    let neighbors = Neighbors::Grid(grid);
+    let kernels = KernelCache::new(h, 0);
+    for i in 0..n {
+        let scalar = scalar_density(
+            &neighbors,
+            &sim.positions,
+            &sim.mass_factors,
+            i,
+            &kernels,
+            min_dist_sq,
+            mass,
+        );
+        let lanes = simd::density(
+            &grid,
+            &sim.positions,
+            &sim.mass_factors,
+            i,
+            h,
+            min_dist_sq,
+            mass,
+        );
+        let deviation = (scalar - lanes).abs() / scalar.abs().max(f32::MIN_POSITIVE);
+        assert!(
+            deviation <= 1e-5,
+            "particle {i}: scalar {scalar}, SIMD {lanes}"
+        );
+    }
+}
+
+/// Relative difference between the measured wave speed and the sound speed the wave-speed
+/// test accepts. The peak's arrival is only known to a step, a few percent of its travel
+/// time between the probes.
+const WAVE_SPEED_TOLERANCE: f32 = 0.1;
+
+#[test]
+fn wave_travels_at_sound_speed() {
+    let sim = FluidSimulation::new(0);
+    let stiff = FluidConfig::default();
+    let soft = FluidConfig {
+        pressure_multiplier: stiff.pressure_multiplier * 0.25,
+        ..stiff.clone()
+    };
+    for config in [&soft, &stiff] {
+        let speed = measure_wave_speed(&sim, config)
+            .expect("no pressure peak travelled between the probes");
+        let sound_speed = sound_speed(config);
+        let error = (speed - sound_speed).abs() / sound_speed;
+        assert!(
+            error <= WAVE_SPEED_TOLERANCE,
+            "wave speed {speed}, sound speed {sound_speed} at pressure multiplier {}",
+            config.pressure_multiplier
+        );
+    }
+}
+
+/// Tank of the hydrostatic test, narrow so the fluid laid into it has nowhere to spread.
+const HYDROSTATIC_TANK: Rect = Rect {
+    min: Vec2::new(-100.0, -200.0),
+    max: Vec2::new(100.0, 200.0),
+};
+
+/// Damping e-folding times the hydrostatic test waits at most for the tank to settle. Each
+/// shrinks the sloshing by a factor `e` beyond what the walls and viscosity take out.
+const HYDROSTATIC_DAMPING_TIMES: f32 = 20.0;
+
+/// Pressure samples the hydrostatic test takes down the probe line.
+const HYDROSTATIC_SAMPLES: usize = 32;
+
+/// Relative pressure gradient error the hydrostatic test accepts.
+const HYDROSTATIC_TOLERANCE: f32 = 0.05;
+
+/// Fills the lower half of `HYDROSTATIC_TANK` with a lattice at half a smoothing radius and
+/// steps it until the settling detector fires, then probes the SPH pressure down a vertical
+/// line through the tank center, from two smoothing radii below the surface to one above
+/// the floor, and fits it linearly against depth. Returns the mean sampled density, the
+/// pressure increase per unit depth and the coefficient of determination of that fit.
+///
+/// The walls are no-slip: along frictionless ones the layer pressed against them never
+/// stops sliding. Settled means every particle is slower than twice the speed gravity adds
+/// in one step, which resting particles pick up and lose again at every step, for as long
+/// as a sound wave takes to cross the fluid's depth and back.
+fn hydrostatic_profile(config: &FluidConfig) -> (f32, f32, f32) {
+    let h = config.smoothing_radius;
+    let dt = test_dt(config);
+    let mut sim =
+        FluidSimulation::with_domain(config.seed, HYDROSTATIC_TANK, 25.0, PARTICLE_RADIUS);
+    let spacing = h * 0.5;
+    let size = HYDROSTATIC_TANK.size() * Vec2::new(1.0, 0.5);
+    let (cols, rows) = ((size.x / spacing) as usize, (size.y / spacing) as usize);
+    let positions: Vec<Vec2> = (0..rows * cols)
+        .map(|k| {
+            let cell = Vec2::new((k % cols) as f32, (k / cols) as f32) + 0.5;
+            HYDROSTATIC_TANK.min + cell * spacing
+        })
+        .collect();
+    sim.load_particles(
+        &positions,
+        &vec![Vec2::ZERO; positions.len()],
+        positions.len(),
+    );
+
+    let resting_speed = 2.0 * config.current_gravity().length() * dt;
+    let config = &FluidConfig {
+        boundary_friction: 1.0,
+        settle_energy: 0.5 * config.particle_mass * resting_speed.powi(2) * positions.len() as f32,
+        settle_duration: 2.0 * size.y / sound_speed(config),
+        ..config.clone()
+    };
+    let max_steps = damping_steps(config, HYDROSTATIC_DAMPING_TIMES);
+    let steps = run_until_settled(&mut sim, config, &SceneElements::default(), dt, max_steps);
+    assert!(
+        steps.is_some(),
+        "the tank had not settled after {max_steps} steps"
+    );
+
+    let surface = sim.bounding_box().1.y;
+    let (top, bottom) = (surface - 2.0 * h, sim.domain.min.y + h);
+    assert!(top > bottom, "the tank is too shallow to sample");
+    let x = sim.domain.center().x;
+    let samples: Vec<(f32, f32, f32)> = (0..HYDROSTATIC_SAMPLES)
+        .map(|k| {
+            let y = top + (bottom - top) * k as f32 / (HYDROSTATIC_SAMPLES - 1) as f32;
+            let pos = Vec2::new(x, y);
+            (
+                surface - y,
+                sim.sample_pressure_at(pos, config),
+                sim.sample_density_at(pos, config),
+            )
+        })
+        .collect();
+
+    let count = samples.len() as f32;
+    let mean_depth = samples.iter().map(|s| s.0).sum::<f32>() / count;
+    let mean_pressure = samples.iter().map(|s| s.1).sum::<f32>() / count;
+    let (mut covariance, mut depth_var, mut pressure_var) = (0.0, 0.0, 0.0);
+    for &(depth, pressure, _) in &samples {
+        let (dd, dp) = (depth - mean_depth, pressure - mean_pressure);
+        covariance += dd * dp;
+        depth_var += dd * dd;
+        pressure_var += dp * dp;
+    }
+    let linearity = if pressure_var > 0.0 {
+        covariance * covariance / (depth_var * pressure_var)
+    } else {
+        0.0
+    };
+    let density = samples.iter().map(|s| s.2).sum::<f32>() / count;
+    (density, covariance / depth_var, linearity)
+}
+
+#[test]
+fn settled_tank_pressure_grows_at_rho_g() {
+    let config = FluidConfig::default();
+    let (density, gradient, linearity) = hydrostatic_profile(&config);
+    assert!(
+        linearity >= 0.95,
+        "pressure is not linear in depth (R² {linearity})"
+    );
+    let expected = density * config.current_gravity().length();
+    let error = (gradient - expected).abs() / expected;
+    assert!(
+        error <= HYDROSTATIC_TOLERANCE,
+        "pressure gradient {gradient} per unit depth, ρ|g| {expected} balances gravity"
+    );
+}
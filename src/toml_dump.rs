@@ -0,0 +1,130 @@
+use std::fmt::Write;
+
+use bevy::reflect::{PartialReflect, ReflectRef, VariantType};
+
+/// Appends `[name]` followed by one `key = value` line per field of a
+/// reflected struct. Nested structs and enum payloads become inline tables,
+/// and `None` fields are omitted since TOML has no null.
+pub fn write_table(out: &mut String, name: &str, value: &dyn PartialReflect) {
+    let _ = writeln!(out, "[{name}]");
+    match value.reflect_ref() {
+        ReflectRef::Struct(s) => {
+            for i in 0..s.field_len() {
+                let (Some(key), Some(field)) = (s.name_at(i), s.field_at(i)) else {
+                    continue;
+                };
+                if is_none(field) {
+                    continue;
+                }
+                let _ = write!(out, "{key} = ");
+                write_value(out, field);
+                out.push('\n');
+            }
+        }
+        _ => {
+            let _ = write!(out, "value = ");
+            write_value(out, value);
+            out.push('\n');
+        }
+    }
+}
+
+fn is_option(value: &dyn PartialReflect) -> bool {
+    value
+        .reflect_type_path()
+        .starts_with("core::option::Option")
+}
+
+fn is_none(value: &dyn PartialReflect) -> bool {
+    matches!(value.reflect_ref(), ReflectRef::Enum(e) if is_option(value) && e.variant_name() == "None")
+}
+
+/// Writes a single TOML value. Types without a TOML counterpart fall back to
+/// their `Debug` output as a string.
+fn write_value(out: &mut String, value: &dyn PartialReflect) {
+    if let Some(v) = value.try_downcast_ref::<f32>() {
+        write_float(out, f64::from(*v), &format!("{v:?}"));
+    } else if let Some(v) = value.try_downcast_ref::<f64>() {
+        write_float(out, *v, &format!("{v:?}"));
+    } else if let Some(v) = value.try_downcast_ref::<bool>() {
+        let _ = write!(out, "{v}");
+    } else if let Some(v) = value.try_downcast_ref::<usize>() {
+        let _ = write!(out, "{v}");
+    } else if let Some(v) = value.try_downcast_ref::<u64>() {
+        let _ = write!(out, "{v}");
+    } else if let Some(v) = value.try_downcast_ref::<u32>() {
+        let _ = write!(out, "{v}");
+    } else if let Some(v) = value.try_downcast_ref::<i32>() {
+        let _ = write!(out, "{v}");
+    } else if let Some(v) = value.try_downcast_ref::<String>() {
+        let _ = write!(out, "{v:?}");
+    } else {
+        match value.reflect_ref() {
+            ReflectRef::Struct(s) => {
+                let fields =
+                    (0..s.field_len()).filter_map(|i| Some((s.name_at(i)?, s.field_at(i)?)));
+                write_inline_table(out, fields);
+            }
+            ReflectRef::Enum(e) => match e.variant_type() {
+                // `Some(x)` is written as plain `x`.
+                VariantType::Tuple if e.field_len() == 1 && is_option(value) => {
+                    if let Some(inner) = e.field_at(0) {
+                        write_value(out, inner);
+                    }
+                }
+                VariantType::Unit => {
+                    let _ = write!(out, "{:?}", e.variant_name());
+                }
+                VariantType::Struct => {
+                    let fields =
+                        (0..e.field_len()).filter_map(|i| Some((e.name_at(i)?, e.field_at(i)?)));
+                    let _ = write!(out, "{{ {} = ", e.variant_name());
+                    write_inline_table(out, fields);
+                    out.push_str(" }");
+                }
+                VariantType::Tuple => {
+                    let _ = write!(out, "{{ {} = [", e.variant_name());
+                    for i in 0..e.field_len() {
+                        if i > 0 {
+                            out.push_str(", ");
+                        }
+                        if let Some(field) = e.field_at(i) {
+                            write_value(out, field);
+                        }
+                    }
+                    out.push_str("] }");
+                }
+            },
+            _ => {
+                let _ = write!(out, "{:?}", format!("{value:?}"));
+            }
+        }
+    }
+}
+
+fn write_inline_table<'a>(
+    out: &mut String,
+    fields: impl Iterator<Item = (&'a str, &'a dyn PartialReflect)>,
+) {
+    out.push('{');
+    let mut first = true;
+    for (key, field) in fields.filter(|(_, f)| !is_none(*f)) {
+        out.push_str(if first { " " } else { ", " });
+        first = false;
+        let _ = write!(out, "{key} = ");
+        write_value(out, field);
+    }
+    out.push_str(if first { "}" } else { " }" });
+}
+
+/// TOML floats need a decimal point (which `Debug` formatting provides) and
+/// spell non-finite values `inf`/`nan`.
+fn write_float(out: &mut String, v: f64, formatted: &str) {
+    if v.is_nan() {
+        out.push_str("nan");
+    } else if v.is_infinite() {
+        out.push_str(if v > 0.0 { "inf" } else { "-inf" });
+    } else {
+        out.push_str(formatted);
+    }
+}